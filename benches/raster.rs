@@ -0,0 +1,113 @@
+//! Throughput of gpu rasterization with a cold cache.
+//!
+//! Run with `ILMENITE_BENCH_FONT=/path/to/font.ttf cargo bench --bench raster`.
+
+use std::time::{Duration, Instant};
+
+use ilmenite::{ImtFont, ImtRasterOpts, ImtShapeOpts, ImtWeight};
+use vulkano::device::{Device, DeviceCreateInfo, QueueCreateInfo, QueueFlags};
+use vulkano::instance::{Instance, InstanceCreateInfo};
+use vulkano::VulkanLibrary;
+
+const ITERATIONS: usize = 20;
+
+fn main() {
+    let font_path = match std::env::var("ILMENITE_BENCH_FONT") {
+        Ok(font_path) => font_path,
+        Err(_) => {
+            eprintln!("Set ILMENITE_BENCH_FONT to the path of a TrueType font.");
+            return;
+        },
+    };
+
+    let bytes = std::fs::read(font_path).unwrap();
+    let library = VulkanLibrary::new().unwrap();
+
+    let instance = Instance::new(
+        library,
+        InstanceCreateInfo {
+            enumerate_portability: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let (physical_device, queue_family_index) = instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .find_map(|physical_device| {
+            physical_device
+                .queue_family_properties()
+                .iter()
+                .position(|family| family.queue_flags.intersects(QueueFlags::COMPUTE))
+                .map(|index| (physical_device.clone(), index as u32))
+        })
+        .expect("No device with a compute queue.");
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        DeviceCreateInfo {
+            queue_create_infos: vec![QueueCreateInfo {
+                queue_family_index,
+                ..Default::default()
+            }],
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let queue = queues.next().unwrap();
+
+    let font = ImtFont::from_bytes_gpu(
+        "Bench",
+        ImtWeight::Normal,
+        ImtRasterOpts::default(),
+        device,
+        queue,
+        bytes,
+    )
+    .unwrap();
+
+    let text: String = (0x21_u8..0x7f).map(char::from).collect();
+    let glyph_count = text.chars().count() * ITERATIONS;
+
+    // Each iteration uses a new text height so every glyph misses the cache.
+    let mut text_height = 12.0;
+
+    let blocking = time(|| {
+        for _ in 0..ITERATIONS {
+            text_height += 0.25;
+
+            font.glyphs_for_text(text_height, ImtShapeOpts::default(), &text)
+                .unwrap();
+        }
+    });
+
+    let batched = time(|| {
+        for _ in 0..ITERATIONS {
+            text_height += 0.25;
+
+            font.prewarm(&[text_height], &text, ImtShapeOpts::default())
+                .unwrap();
+        }
+    });
+
+    report("glyphs_for_text", glyph_count, blocking);
+    report("prewarm", glyph_count, batched);
+}
+
+fn time<F: FnOnce()>(f: F) -> Duration {
+    let start = Instant::now();
+    f();
+    start.elapsed()
+}
+
+fn report(name: &str, glyph_count: usize, elapsed: Duration) {
+    println!(
+        "{}: {} glyphs in {:.2} ms, {:.0} glyphs/s",
+        name,
+        glyph_count,
+        elapsed.as_secs_f64() * 1000.0,
+        glyph_count as f64 / elapsed.as_secs_f64(),
+    );
+}
@@ -0,0 +1,98 @@
+//! Renders text into an RGBA buffer that can be put onto a canvas with `ImageData`.
+//!
+//! ```text
+//! cargo build --example wasm_canvas --target wasm32-unknown-unknown --no-default-features
+//! ```
+//!
+//! The host copies the font & text into memory obtained from `alloc`, allocates a
+//! `width * height * 4` canvas buffer the same way and calls `render_text`.
+
+use ilmenite::{ImtBitmapData, ImtFont, ImtRasterOpts, ImtShapeOpts, ImtWeight};
+
+/// Allocate `len` bytes for the host to write into.
+#[no_mangle]
+pub extern "C" fn alloc(len: usize) -> *mut u8 {
+    let mut buf = Vec::with_capacity(len);
+    let ptr = buf.as_mut_ptr();
+    std::mem::forget(buf);
+    ptr
+}
+
+/// Free memory returned by `alloc`.
+///
+/// # Safety
+///
+/// `ptr` & `len` must be from a previous call to `alloc`.
+#[no_mangle]
+pub unsafe extern "C" fn dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Render `text` in black onto the canvas. Returns false if the font couldn't be loaded or the
+/// text couldn't be rastered.
+///
+/// # Safety
+///
+/// The pointers must be valid for their lengths, the canvas being `width * height * 4` bytes.
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn render_text(
+    font_ptr: *const u8,
+    font_len: usize,
+    text_ptr: *const u8,
+    text_len: usize,
+    text_height: f32,
+    canvas_ptr: *mut u8,
+    width: u32,
+    height: u32,
+) -> bool {
+    let font_bytes = std::slice::from_raw_parts(font_ptr, font_len).to_vec();
+    let text = String::from_utf8_lossy(std::slice::from_raw_parts(text_ptr, text_len));
+    let canvas = std::slice::from_raw_parts_mut(canvas_ptr, width as usize * height as usize * 4);
+
+    let font = match ImtFont::from_bytes_cpu(
+        "Canvas",
+        ImtWeight::Normal,
+        ImtRasterOpts::builder().coverage_only(true).build(),
+        font_bytes,
+    ) {
+        Ok(font) => font,
+        Err(_) => return false,
+    };
+
+    let shape_opts = ImtShapeOpts::builder()
+        .body_width(width as f32)
+        .body_height(height as f32)
+        .text_height(text_height)
+        .build();
+
+    let glyphs = match font.glyphs_for_text(text_height, shape_opts, text) {
+        Ok(glyphs) => glyphs,
+        Err(_) => return false,
+    };
+
+    for glyph in glyphs {
+        let coverage = match glyph.bitmap {
+            Some(ImtBitmapData::R8(coverage)) => coverage,
+            _ => continue,
+        };
+
+        for gy in 0..glyph.h {
+            for gx in 0..glyph.w {
+                let x = glyph.x as i64 + gx as i64;
+                let y = glyph.y as i64 + gy as i64;
+
+                if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                    continue;
+                }
+
+                let alpha = coverage[(gy * glyph.w + gx) as usize];
+                let i = ((y as usize * width as usize) + x as usize) * 4;
+                canvas[i..(i + 3)].fill(0);
+                canvas[i + 3] = canvas[i + 3].max(alpha);
+            }
+        }
+    }
+
+    true
+}
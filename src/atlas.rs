@@ -0,0 +1,462 @@
+use std::collections::HashMap;
+use std::iter;
+use std::sync::Arc;
+
+use ordered_float::OrderedFloat;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, BufferImageCopy, ClearColorImageInfo, CommandBufferUsage,
+    CopyBufferToImageInfo, CopyImageInfo, ImageCopy, PrimaryCommandBufferAbstract,
+};
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{ImageAccess, ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
+use vulkano::sync::GpuFuture;
+
+use crate::bitmap::ImtBitmapMetrics;
+use crate::{Ilmenite, ImtBitmapData, ImtError, ImtErrorSrc, ImtErrorTy, ImtImageView, ImtWeight};
+
+/// Space left between glyphs so that linear filtering doesn't sample neighboring glyphs.
+const ATLAS_PADDING: u32 = 1;
+
+/// Glyphs of a font at a size to add to an `ImtAtlas`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImtAtlasRequest {
+    pub family: String,
+    pub weight: ImtWeight,
+    pub text_height: f32,
+    /// Indexes of the glyphs, such as `ImtGlyph::index`.
+    pub glyphs: Vec<u16>,
+}
+
+/// Location of a glyph within an `ImtAtlas`.
+#[derive(Clone, Debug)]
+pub struct ImtAtlasEntry {
+    /// Index of the page containing the glyph. `None` for glyphs without any pixels such as
+    /// whitespace.
+    pub page: Option<usize>,
+    /// Region of the page in pixels as `[x, y, width, height]`.
+    pub rect: [u32; 4],
+    /// Region of the page in normalized coordinates as `[min_u, min_v, max_u, max_v]`.
+    pub uv: [f32; 4],
+    pub metrics: ImtBitmapMetrics,
+}
+
+type AtlasKey = (String, ImtWeight, u16, OrderedFloat<f32>);
+
+struct AtlasPage {
+    image: Arc<StorageImage>,
+    view: Arc<ImtImageView>,
+    format: Format,
+    shelves: Vec<AtlasShelf>,
+    cleared: bool,
+}
+
+/// A row of glyphs on a page. Glyphs are placed left to right on the shelf.
+struct AtlasShelf {
+    y: u32,
+    height: u32,
+    x: u32,
+}
+
+struct AtlasUpload {
+    key: AtlasKey,
+    page: usize,
+    rect: [u32; 4],
+    data: ImtBitmapData,
+}
+
+/// Packs rastered glyphs into pages of images for rendering many glyphs from one texture.
+///
+/// Glyphs are rastered with their font's raster, so they share its cache. Pages are created as
+/// needed, each holding glyphs of a single format: `LRGBA` data is stored as `R8G8B8A8_UNORM`,
+/// `R8` data as `R8_UNORM` and images keep their own format.
+pub struct ImtAtlas {
+    queue: Arc<Queue>,
+    mem_alloc: Arc<StandardMemoryAllocator>,
+    cmd_alloc: Arc<StandardCommandBufferAllocator>,
+    page_size: u32,
+    pages: Vec<AtlasPage>,
+    entries: HashMap<AtlasKey, ImtAtlasEntry>,
+}
+
+impl ImtAtlas {
+    /// Create an empty atlas whose pages are `page_size` pixels wide & high.
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>, page_size: u32) -> Self {
+        Self {
+            queue,
+            mem_alloc: Arc::new(StandardMemoryAllocator::new_default(device.clone())),
+            cmd_alloc: Arc::new(StandardCommandBufferAllocator::new(
+                device,
+                Default::default(),
+            )),
+            page_size,
+            pages: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Raster the requested glyphs that aren't in the atlas yet and pack them into the pages.
+    ///
+    /// Glyphs added before an error was encountered remain in the atlas, unless uploading them
+    /// to the pages fails.
+    pub fn add(
+        &mut self,
+        ilmenite: &Ilmenite,
+        requests: &[ImtAtlasRequest],
+    ) -> Result<(), ImtError> {
+        let mut uploads = Vec::new();
+        let mut result = Ok(());
+
+        for request in requests {
+            result = self.add_request(ilmenite, request, &mut uploads);
+
+            if result.is_err() {
+                break;
+            }
+        }
+
+        let upload_result = self.upload(uploads);
+        result.and(upload_result)
+    }
+
+    fn add_request(
+        &mut self,
+        ilmenite: &Ilmenite,
+        request: &ImtAtlasRequest,
+        uploads: &mut Vec<AtlasUpload>,
+    ) -> Result<(), ImtError> {
+        ilmenite.with_font(&request.family, request.weight, |font| {
+            for &index in request.glyphs.iter() {
+                let key = (
+                    request.family.clone(),
+                    request.weight,
+                    index,
+                    OrderedFloat::from(request.text_height),
+                );
+
+                if self.entries.contains_key(&key) {
+                    continue;
+                }
+
+                let bitmap = font.raster_glyph(index, request.text_height)?;
+                let metrics = bitmap.metrics();
+
+                let data = match bitmap.data() {
+                    Some(data) if !data.is_empty() => data,
+                    _ => {
+                        self.entries.insert(
+                            key,
+                            ImtAtlasEntry {
+                                page: None,
+                                rect: [0; 4],
+                                uv: [0.0; 4],
+                                metrics,
+                            },
+                        );
+
+                        continue;
+                    },
+                };
+
+                let (page, x, y) =
+                    self.allocate(page_format(&data)?, metrics.width, metrics.height)?;
+                let rect = [x, y, metrics.width, metrics.height];
+                let page_size = self.page_size as f32;
+
+                self.entries.insert(
+                    key.clone(),
+                    ImtAtlasEntry {
+                        page: Some(page),
+                        rect,
+                        uv: [
+                            x as f32 / page_size,
+                            y as f32 / page_size,
+                            (x + metrics.width) as f32 / page_size,
+                            (y + metrics.height) as f32 / page_size,
+                        ],
+                        metrics,
+                    },
+                );
+
+                uploads.push(AtlasUpload {
+                    key,
+                    page,
+                    rect,
+                    data,
+                });
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Find space for a `width` by `height` glyph on a page of `format`, creating a new page if
+    /// none of the existing pages have space. Returns the page & position.
+    fn allocate(
+        &mut self,
+        format: Format,
+        width: u32,
+        height: u32,
+    ) -> Result<(usize, u32, u32), ImtError> {
+        let padded_w = width + ATLAS_PADDING;
+        let padded_h = height + ATLAS_PADDING;
+
+        if padded_w > self.page_size || padded_h > self.page_size {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Bitmap,
+                ImtErrorTy::BitmapTooLarge {
+                    width,
+                    height,
+                    max_dimension: self.page_size.saturating_sub(ATLAS_PADDING),
+                },
+            ));
+        }
+
+        for (page_i, page) in self.pages.iter_mut().enumerate() {
+            if page.format != format {
+                continue;
+            }
+
+            // Use the existing shelf that wastes the least height.
+            let shelf = page
+                .shelves
+                .iter_mut()
+                .filter(|shelf| shelf.height >= padded_h && shelf.x + padded_w <= self.page_size)
+                .min_by_key(|shelf| shelf.height - padded_h);
+
+            if let Some(shelf) = shelf {
+                let x = shelf.x;
+                shelf.x += padded_w;
+                return Ok((page_i, x, shelf.y));
+            }
+
+            let y = page
+                .shelves
+                .last()
+                .map(|shelf| shelf.y + shelf.height)
+                .unwrap_or(0);
+
+            if y + padded_h <= self.page_size {
+                page.shelves.push(AtlasShelf {
+                    y,
+                    height: padded_h,
+                    x: padded_w,
+                });
+
+                return Ok((page_i, 0, y));
+            }
+        }
+
+        let image = StorageImage::with_usage(
+            &*self.mem_alloc,
+            ImageDimensions::Dim2d {
+                width: self.page_size,
+                height: self.page_size,
+                array_layers: 1,
+            },
+            format,
+            ImageUsage::TRANSFER_SRC | ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED,
+            ImageCreateFlags::empty(),
+            iter::once(self.queue.queue_family_index()),
+        )
+        .map_err(ImtError::gpu)?;
+
+        self.pages.push(AtlasPage {
+            view: ImtImageView::from_storage(image.clone()).map_err(ImtError::gpu)?,
+            image,
+            format,
+            shelves: vec![AtlasShelf {
+                y: 0,
+                height: padded_h,
+                x: padded_w,
+            }],
+            cleared: false,
+        });
+
+        Ok((self.pages.len() - 1, 0, 0))
+    }
+
+    /// Clear new pages and copy the glyphs into their pages.
+    ///
+    /// If this fails the glyphs are removed from the atlas so a later `add` rasters them again.
+    /// The space allocated to them on their pages isn't reused.
+    fn upload(&mut self, uploads: Vec<AtlasUpload>) -> Result<(), ImtError> {
+        let uncleared: Vec<usize> = self
+            .pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| !page.cleared)
+            .map(|(page_i, _)| page_i)
+            .collect();
+
+        if uploads.is_empty() && uncleared.is_empty() {
+            return Ok(());
+        }
+
+        let keys: Vec<AtlasKey> = uploads.iter().map(|upload| upload.key.clone()).collect();
+
+        match self.submit_upload(&uncleared, uploads) {
+            Ok(()) => {
+                for page_i in uncleared {
+                    self.pages[page_i].cleared = true;
+                }
+
+                Ok(())
+            },
+            Err(e) => {
+                for key in keys {
+                    self.entries.remove(&key);
+                }
+
+                Err(e)
+            },
+        }
+    }
+
+    /// Clear the `uncleared` pages & copy `uploads` into their pages, waiting for the commands to
+    /// complete.
+    fn submit_upload(
+        &self,
+        uncleared: &[usize],
+        uploads: Vec<AtlasUpload>,
+    ) -> Result<(), ImtError> {
+        let mut cmd_buf = AutoCommandBufferBuilder::primary(
+            &*self.cmd_alloc,
+            self.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
+        )
+        .map_err(ImtError::gpu)?;
+
+        for &page_i in uncleared {
+            cmd_buf
+                .clear_color_image(ClearColorImageInfo::image(self.pages[page_i].image.clone()))
+                .map_err(ImtError::gpu)?;
+        }
+
+        for upload in uploads {
+            let page = &self.pages[upload.page];
+            let [x, y, width, height] = upload.rect;
+
+            match upload.data {
+                ImtBitmapData::Image(src) => {
+                    cmd_buf
+                        .copy_image(CopyImageInfo {
+                            regions: vec![ImageCopy {
+                                src_subresource: src.subresource_layers(),
+                                src_offset: [0, 0, 0],
+                                dst_subresource: page.image.subresource_layers(),
+                                dst_offset: [x, y, 0],
+                                extent: [width, height, 1],
+                                ..Default::default()
+                            }]
+                            .into(),
+                            ..CopyImageInfo::images(src, page.image.clone())
+                        })
+                        .map_err(ImtError::gpu)?;
+                },
+                data => {
+                    let bytes: Vec<u8> = match data {
+                        ImtBitmapData::LRGBA(data) => {
+                            data.iter()
+                                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+                                .collect()
+                        },
+                        ImtBitmapData::R8(data) => data.to_vec(),
+                        _ => continue,
+                    };
+
+                    let staging_buf = Buffer::from_iter(
+                        &*self.mem_alloc,
+                        BufferCreateInfo {
+                            usage: BufferUsage::TRANSFER_SRC,
+                            ..Default::default()
+                        },
+                        AllocationCreateInfo {
+                            usage: MemoryUsage::Upload,
+                            ..Default::default()
+                        },
+                        bytes,
+                    )
+                    .map_err(ImtError::gpu)?;
+
+                    cmd_buf
+                        .copy_buffer_to_image(CopyBufferToImageInfo {
+                            regions: vec![BufferImageCopy {
+                                image_subresource: page.image.subresource_layers(),
+                                image_offset: [x, y, 0],
+                                image_extent: [width, height, 1],
+                                ..Default::default()
+                            }]
+                            .into(),
+                            ..CopyBufferToImageInfo::buffer_image(staging_buf, page.image.clone())
+                        })
+                        .map_err(ImtError::gpu)?;
+                },
+            }
+        }
+
+        cmd_buf
+            .build()
+            .map_err(ImtError::gpu)?
+            .execute(self.queue.clone())
+            .map_err(ImtError::gpu)?
+            .then_signal_fence_and_flush()
+            .map_err(ImtError::gpu)?
+            .wait(None)
+            .map_err(ImtError::gpu)
+    }
+
+    /// Location of a glyph previously added with `add`.
+    pub fn get(
+        &self,
+        family: &str,
+        weight: ImtWeight,
+        index: u16,
+        text_height: f32,
+    ) -> Option<&ImtAtlasEntry> {
+        self.entries.get(&(
+            family.to_string(),
+            weight,
+            index,
+            OrderedFloat::from(text_height),
+        ))
+    }
+
+    /// The image of a page. Pages are in `ImageLayout::General`.
+    pub fn page(&self, page: usize) -> Option<Arc<ImtImageView>> {
+        self.pages.get(page).map(|page| page.view.clone())
+    }
+
+    /// The images of all pages in order.
+    pub fn pages(&self) -> Vec<Arc<ImtImageView>> {
+        self.pages.iter().map(|page| page.view.clone()).collect()
+    }
+
+    /// Width & height of each page in pixels.
+    pub fn page_size(&self) -> u32 {
+        self.page_size
+    }
+
+    /// Remove all glyphs & pages.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.entries.clear();
+    }
+}
+
+/// Format of the page a bitmap's data is stored on.
+fn page_format(data: &ImtBitmapData) -> Result<Format, ImtError> {
+    match data {
+        ImtBitmapData::R8(_) => Ok(Format::R8_UNORM),
+        ImtBitmapData::Image(view) => {
+            view.image_view_ref().format().ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Bitmap,
+                ImtErrorTy::UnimplementedDataTy,
+            ))
+        },
+        _ => Ok(Format::R8G8B8A8_UNORM),
+    }
+}
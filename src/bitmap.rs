@@ -1,32 +1,148 @@
+use std::fmt;
+#[cfg(feature = "gpu")]
 use std::iter;
 use std::sync::Arc;
 
+use allsorts::gsub::{GlyphOrigin, RawGlyph};
+#[cfg(feature = "gpu")]
 use vulkano::buffer::subbuffer::Subbuffer;
+#[cfg(feature = "gpu")]
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
+#[cfg(feature = "gpu")]
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo,
+    AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
     PrimaryCommandBufferAbstract,
 };
+#[cfg(feature = "gpu")]
 use vulkano::descriptor_set::persistent::PersistentDescriptorSet;
+#[cfg(feature = "gpu")]
 use vulkano::descriptor_set::WriteDescriptorSet;
+#[cfg(feature = "gpu")]
+use vulkano::format::Format;
+#[cfg(feature = "gpu")]
 use vulkano::image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
+#[cfg(feature = "gpu")]
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage};
+#[cfg(feature = "gpu")]
 use vulkano::pipeline::{Pipeline, PipelineBindPoint};
+#[cfg(feature = "gpu")]
 use vulkano::sync::GpuFuture;
 
-use crate::raster::{CpuRasterContext, GpuRasterContext};
+use crate::parse::SYNTHETIC_BOLD_EM;
+use crate::raster::CpuRasterContext;
+#[cfg(feature = "gpu")]
+use crate::raster::GpuRasterContext;
+#[cfg(feature = "gpu")]
 use crate::shaders::glyph_cs;
+#[cfg(feature = "gpu")]
+use crate::ImtImageView;
 use crate::{
-    ImtError, ImtGeometry, ImtImageView, ImtParsedGlyph, ImtParser, ImtPoint, ImtRasterOpts,
+    ImtAlphaMode, ImtError, ImtFillRule, ImtFontProps, ImtGeometry, ImtParsedGlyph, ImtParser,
+    ImtPoint, ImtRasterOpts,
 };
 
+/// What the pixels of a bitmap describe, see `ImtBitmapData::content`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImtBitmapContent {
+    /// A single channel of coverage to be used as an alpha mask.
+    Coverage,
+    /// Coverage of the red, green & blue subpixels in linear RGB with the overall coverage in
+    /// alpha, stored as per `ImtRasterOpts::alpha_mode`. Requires per channel blending.
+    SubpixelCoverage,
+}
+
+/// Rastered pixels of a glyph. Non-exhaustive as `Image` only exists with the `gpu` feature.
 #[derive(Clone)]
+#[non_exhaustive]
 pub enum ImtBitmapData {
     Empty,
+    /// Four values per pixel of subpixel coverage, see `ImtBitmapContent::SubpixelCoverage`.
     LRGBA(Arc<Vec<f32>>),
+    /// Single channel coverage, see `ImtRasterOpts::coverage_only`.
+    R8(Arc<Vec<u8>>),
+    /// Output image in `ImageLayout::General`, see `ImtRasterOpts::raster_to_image`.
+    #[cfg(feature = "gpu")]
     Image(Arc<ImtImageView>),
 }
 
+impl ImtBitmapData {
+    /// Format of the pixel data. `None` if empty.
+    #[cfg(feature = "gpu")]
+    pub fn pixel_format(&self) -> Option<Format> {
+        match self {
+            Self::Empty => None,
+            Self::LRGBA(_) => Some(Format::R32G32B32A32_SFLOAT),
+            Self::R8(_) => Some(Format::R8_UNORM),
+            Self::Image(view) => view.image_view_ref().format(),
+        }
+    }
+
+    /// What the pixels describe. `None` if empty.
+    ///
+    /// Images are `Coverage` when single channel, such as with `ImtRasterOpts::coverage_only`.
+    pub fn content(&self) -> Option<ImtBitmapContent> {
+        match self {
+            Self::Empty => None,
+            Self::LRGBA(_) => Some(ImtBitmapContent::SubpixelCoverage),
+            Self::R8(_) => Some(ImtBitmapContent::Coverage),
+            #[cfg(feature = "gpu")]
+            Self::Image(view) => {
+                match view.image_view_ref().format() {
+                    Some(format) if format.components()[1] == 0 => Some(ImtBitmapContent::Coverage),
+                    _ => Some(ImtBitmapContent::SubpixelCoverage),
+                }
+            },
+        }
+    }
+
+    /// Whether there are no pixels, such as for whitespace.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Self::Empty => true,
+            Self::LRGBA(data) => data.is_empty(),
+            Self::R8(data) => data.is_empty(),
+            #[cfg(feature = "gpu")]
+            Self::Image(_) => false,
+        }
+    }
+
+    /// Pixel data as bytes without copying, such as for uploading to a texture. `LRGBA` values
+    /// are native endian `f32`. `None` if the data resides in an image.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Empty => Some(&[]),
+            Self::LRGBA(data) => Some(bytemuck::cast_slice(data.as_slice())),
+            Self::R8(data) => Some(data.as_slice()),
+            #[cfg(feature = "gpu")]
+            Self::Image(_) => None,
+        }
+    }
+
+    /// Length of the pixel data in bytes. `None` if the data resides in an image.
+    pub fn data_len(&self) -> Option<usize> {
+        match self {
+            Self::Empty => Some(0),
+            Self::LRGBA(data) => Some(data.len() * 4),
+            Self::R8(data) => Some(data.len()),
+            #[cfg(feature = "gpu")]
+            Self::Image(_) => None,
+        }
+    }
+}
+
+// Pixel data is left out as it is rarely useful & often large.
+impl fmt::Debug for ImtBitmapData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("Empty"),
+            Self::LRGBA(data) => write!(f, "LRGBA({} values)", data.len()),
+            Self::R8(data) => write!(f, "R8({} values)", data.len()),
+            #[cfg(feature = "gpu")]
+            Self::Image(view) => f.debug_tuple("Image").field(&view.dimensions()).finish(),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ImtBitmapMetrics {
     pub width: u32,
@@ -41,10 +157,378 @@ pub struct ImtGlyphBitmap {
     parsed: Arc<ImtParsedGlyph>,
     metrics: ImtBitmapMetrics,
     lines: Vec<(ImtPoint, ImtPoint)>,
+    line_bands: Vec<LineBand>,
+    hint_grid: Option<HintGrid>,
     scaler: f32,
     offset_x: f32,
     offset_y: f32,
+    dilate: f32,
+    alpha_mode: ImtAlphaMode,
+    coverage_only: bool,
+    aliased: bool,
+    curve_steps: Option<u32>,
+    fill_rule: ImtFillRule,
     data: Option<ImtBitmapData>,
+    #[cfg(feature = "gpu")]
+    readback_buf: Option<Subbuffer<[u8]>>,
+}
+
+impl fmt::Debug for ImtGlyphBitmap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ImtGlyphBitmap")
+            .field("index", &self.parsed.inner.glyph_index)
+            .field("metrics", &self.metrics)
+            .field("alpha_mode", &self.alpha_mode)
+            .field("coverage_only", &self.coverage_only)
+            .field("data", &self.data)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Pixel grid the outline is snapped to, see `ImtRasterOpts::hinting`.
+#[derive(Clone)]
+struct HintGrid {
+    /// Heights in font units that are snapped vertically, starting with the baseline.
+    zones: Vec<f32>,
+    /// Vertical position of the pixel grid relative to the baseline in pixels. Bitmaps are
+    /// aligned relative to the top of the line, so this is the fraction of the ascender.
+    y_grid_offset: f32,
+    /// Whether vertical stems are snapped, which requires sub-pixel phases to be disabled.
+    snap_x: bool,
+}
+
+impl HintGrid {
+    fn apply(&self, lines: &mut [(ImtPoint, ImtPoint)], scaler: f32) {
+        let y_to: Vec<f32> = self
+            .zones
+            .iter()
+            .map(|y| (((y * scaler) - self.y_grid_offset).round() + self.y_grid_offset) / scaler)
+            .collect();
+
+        let mut x_from = Vec::new();
+        let mut x_to = Vec::new();
+
+        if self.snap_x {
+            // Edges of stems are the near vertical lines at least a pixel tall.
+            x_from = lines
+                .iter()
+                .filter(|(a, b)| {
+                    let dy = (b.y - a.y).abs();
+                    dy * scaler >= 1.0 && (b.x - a.x).abs() <= dy * 0.05
+                })
+                .map(|(a, b)| (a.x + b.x) / 2.0)
+                .filter(|x| x.is_finite())
+                .collect();
+
+            x_from.sort_by(f32::total_cmp);
+            x_from.dedup_by(|a, b| (*a - *b) * scaler < 0.25);
+
+            for (i, x) in x_from.iter().enumerate() {
+                let mut snapped = (x * scaler).round();
+
+                // Keep stems from collapsing to nothing.
+                if i > 0 {
+                    let prev = x_to[i - 1] * scaler;
+
+                    if snapped <= prev && (x - x_from[i - 1]) * scaler >= 0.5 {
+                        snapped = prev + 1.0;
+                    }
+                }
+
+                x_to.push(snapped / scaler);
+            }
+        }
+
+        for (a, b) in lines.iter_mut() {
+            for point in [a, b] {
+                point.x = interpolate_hint(point.x, &x_from, &x_to);
+                point.y = interpolate_hint(point.y, &self.zones, &y_to);
+            }
+        }
+    }
+}
+
+/// Map `val` through the piecewise linear function moving each of `from` to `to`. Values
+/// outside of `from` move along with the nearest point. `from` must be sorted.
+fn interpolate_hint(val: f32, from: &[f32], to: &[f32]) -> f32 {
+    if from.is_empty() {
+        return val;
+    }
+
+    if val <= from[0] {
+        return val + to[0] - from[0];
+    }
+
+    for i in 1..from.len() {
+        if val <= from[i] {
+            let t = (val - from[i - 1]) / (from[i] - from[i - 1]);
+            return to[i - 1] + (t * (to[i] - to[i - 1]));
+        }
+    }
+
+    val + to[from.len() - 1] - from[from.len() - 1]
+}
+
+const GROUP_LEN: usize = 4;
+
+/// Line segments stored by component in groups of `GROUP_LEN`, so that the ray intersection
+/// tests of a group are done together, with sse2 on x86_64. Unused slots are filled with NaN
+/// which never intersect.
+#[derive(Clone, Copy)]
+struct LineGroup {
+    x: [f32; GROUP_LEN],
+    y: [f32; GROUP_LEN],
+    dx: [f32; GROUP_LEN],
+    dy: [f32; GROUP_LEN],
+}
+
+impl LineGroup {
+    fn from_lines(lines: &[(ImtPoint, ImtPoint)]) -> Vec<Self> {
+        lines
+            .chunks(GROUP_LEN)
+            .map(|chunk| {
+                let mut group = LineGroup {
+                    x: [f32::NAN; GROUP_LEN],
+                    y: [f32::NAN; GROUP_LEN],
+                    dx: [f32::NAN; GROUP_LEN],
+                    dy: [f32::NAN; GROUP_LEN],
+                };
+
+                for (i, line) in chunk.iter().enumerate() {
+                    group.x[i] = line.0.x;
+                    group.y[i] = line.0.y;
+                    group.dx[i] = line.1.x - line.0.x;
+                    group.dy[i] = line.1.y - line.0.y;
+                }
+
+                group
+            })
+            .collect()
+    }
+
+    /// Test a ray against each line of the group. Returns the intersection distance of each line
+    /// that hit along with the direction the line crosses the ray, either `1` or `-1`.
+    #[inline]
+    fn intersect(
+        &self,
+        ray_src: [f32; 2],
+        ray_dest: [f32; 2],
+    ) -> [Option<(f32, isize)>; GROUP_LEN] {
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.intersect_sse2(ray_src, ray_dest)
+        }
+
+        #[cfg(not(target_arch = "x86_64"))]
+        {
+            self.intersect_scalar(ray_src, ray_dest)
+        }
+    }
+
+    /// `intersect` with the lines of the group tested at once in sse2 registers.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    fn intersect_sse2(
+        &self,
+        ray_src: [f32; 2],
+        ray_dest: [f32; 2],
+    ) -> [Option<(f32, isize)>; GROUP_LEN] {
+        use std::arch::x86_64::*;
+
+        let mut det = [0.0; GROUP_LEN];
+        let mut dist = [0.0; GROUP_LEN];
+
+        // SAFETY: sse2 is part of the x86_64 baseline, so it is always available. The loads &
+        // stores are of `GROUP_LEN` (four) f32 arrays & use the unaligned variants.
+        let hit_mask = unsafe {
+            let rx = _mm_set1_ps(ray_dest[0] - ray_src[0]);
+            let ry = _mm_set1_ps(ray_dest[1] - ray_src[1]);
+            let dx = _mm_loadu_ps(self.dx.as_ptr());
+            let dy = _mm_loadu_ps(self.dy.as_ptr());
+            let ox = _mm_sub_ps(_mm_loadu_ps(self.x.as_ptr()), _mm_set1_ps(ray_src[0]));
+            let oy = _mm_sub_ps(_mm_loadu_ps(self.y.as_ptr()), _mm_set1_ps(ray_src[1]));
+            let det_v = _mm_sub_ps(_mm_mul_ps(rx, dy), _mm_mul_ps(ry, dx));
+            let u = _mm_div_ps(_mm_sub_ps(_mm_mul_ps(ox, ry), _mm_mul_ps(oy, rx)), det_v);
+            let t = _mm_div_ps(_mm_sub_ps(_mm_mul_ps(ox, dy), _mm_mul_ps(oy, dx)), det_v);
+            let zero = _mm_setzero_ps();
+            let one = _mm_set1_ps(1.0);
+
+            // Comparisons with NaN are false, so unused slots never hit.
+            let hit = _mm_and_ps(
+                _mm_and_ps(_mm_cmpge_ps(t, zero), _mm_cmple_ps(t, one)),
+                _mm_and_ps(_mm_cmpge_ps(u, zero), _mm_cmple_ps(u, one)),
+            );
+
+            let ix = _mm_mul_ps(rx, t);
+            let iy = _mm_mul_ps(ry, t);
+            let dist_v = _mm_sqrt_ps(_mm_add_ps(_mm_mul_ps(ix, ix), _mm_mul_ps(iy, iy)));
+            _mm_storeu_ps(det.as_mut_ptr(), det_v);
+            _mm_storeu_ps(dist.as_mut_ptr(), dist_v);
+            _mm_movemask_ps(hit)
+        };
+
+        let mut dists = [None; GROUP_LEN];
+
+        for (i, hit) in dists.iter_mut().enumerate() {
+            if hit_mask & (1 << i) != 0 {
+                *hit = Some((dist[i], if det[i] > 0.0 { 1 } else { -1 }));
+            }
+        }
+
+        dists
+    }
+
+    /// `intersect` with the lines of the group tested one after another.
+    #[cfg(any(test, not(target_arch = "x86_64")))]
+    fn intersect_scalar(
+        &self,
+        ray_src: [f32; 2],
+        ray_dest: [f32; 2],
+    ) -> [Option<(f32, isize)>; GROUP_LEN] {
+        let r = [ray_dest[0] - ray_src[0], ray_dest[1] - ray_src[1]];
+        let mut u = [0.0; GROUP_LEN];
+        let mut t = [0.0; GROUP_LEN];
+        let mut det = [0.0; GROUP_LEN];
+
+        for i in 0..GROUP_LEN {
+            det[i] = (r[0] * self.dy[i]) - (r[1] * self.dx[i]);
+            let ox = self.x[i] - ray_src[0];
+            let oy = self.y[i] - ray_src[1];
+            u[i] = ((ox * r[1]) - (oy * r[0])) / det[i];
+            t[i] = ((ox * self.dy[i]) - (oy * self.dx[i])) / det[i];
+        }
+
+        let mut dists = [None; GROUP_LEN];
+
+        for i in 0..GROUP_LEN {
+            if t[i] >= 0.0 && t[i] <= 1.0 && u[i] >= 0.0 && u[i] <= 1.0 {
+                let intersect_point = [ray_src[0] + (r[0] * t[i]), ray_src[1] + (r[1] * t[i])];
+
+                dists[i] = Some((
+                    ((ray_src[0] - intersect_point[0]).powi(2)
+                        + (ray_src[1] - intersect_point[1]).powi(2))
+                    .sqrt(),
+                    if det[i] > 0.0 { 1 } else { -1 },
+                ));
+            }
+        }
+
+        dists
+    }
+}
+
+/// Approximate number of lines per `LineBand`.
+const LINES_PER_BAND: usize = 16;
+
+/// Lines grouped by their minimum y so that rays only need to test the lines they could cross.
+/// Each line belongs to exactly one band.
+#[derive(Clone)]
+struct LineBand {
+    min_y: f32,
+    max_y: f32,
+    groups: Vec<LineGroup>,
+}
+
+impl LineBand {
+    fn from_lines(lines: &[(ImtPoint, ImtPoint)]) -> Vec<Self> {
+        if lines.is_empty() {
+            return Vec::new();
+        }
+
+        let line_min_y = |line: &(ImtPoint, ImtPoint)| line.0.y.min(line.1.y);
+        let line_max_y = |line: &(ImtPoint, ImtPoint)| line.0.y.max(line.1.y);
+        // Bands divide the range of the lines' minimum y.
+        let start_y = lines.iter().map(line_min_y).fold(f32::INFINITY, f32::min);
+        let end_y = lines
+            .iter()
+            .map(line_min_y)
+            .fold(f32::NEG_INFINITY, f32::max);
+        let band_count = (lines.len() / LINES_PER_BAND).max(1);
+        let band_height = (end_y - start_y) / band_count as f32;
+        let mut band_lines = vec![Vec::new(); band_count];
+
+        for line in lines {
+            let band_i = if band_height > 0.0 {
+                (((line_min_y(line) - start_y) / band_height) as usize).min(band_count - 1)
+            } else {
+                0
+            };
+
+            band_lines[band_i].push(line.clone());
+        }
+
+        band_lines
+            .into_iter()
+            .filter(|lines| !lines.is_empty())
+            .map(|lines| {
+                LineBand {
+                    min_y: lines.iter().map(line_min_y).fold(f32::INFINITY, f32::min),
+                    max_y: lines
+                        .iter()
+                        .map(line_max_y)
+                        .fold(f32::NEG_INFINITY, f32::max),
+                    groups: LineGroup::from_lines(&lines),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn lrgba_to_rgba_image(
+    width: u32,
+    height: u32,
+    alpha_mode: ImtAlphaMode,
+    data: &[f32],
+) -> image::RgbaImage {
+    let linear_to_srgb = |v: f32| -> u8 {
+        let v = v.clamp(0.0, 1.0);
+
+        let srgb = if v <= 0.0031308 {
+            v * 12.92
+        } else {
+            (1.055 * v.powf(1.0 / 2.4)) - 0.055
+        };
+
+        (srgb * 255.0).round() as u8
+    };
+
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        let i = (((y * width) + x) * 4) as usize;
+        let a = data[i + 3];
+
+        let unmultiply = |v: f32| -> f32 {
+            match alpha_mode {
+                ImtAlphaMode::Straight => v,
+                ImtAlphaMode::Premultiplied if a > 0.0 => v / a,
+                ImtAlphaMode::Premultiplied => 0.0,
+            }
+        };
+
+        image::Rgba([
+            linear_to_srgb(unmultiply(data[i])),
+            linear_to_srgb(unmultiply(data[i + 1])),
+            linear_to_srgb(unmultiply(data[i + 2])),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// Replace NaN & infinite values with zero so they don't propagate into the output.
+#[inline]
+fn finite_or_zero(val: f32) -> f32 {
+    if val.is_finite() {
+        val
+    } else {
+        0.0
+    }
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn r8_to_rgba_image(width: u32, height: u32, data: &[u8]) -> image::RgbaImage {
+    image::RgbaImage::from_fn(width, height, |x, y| {
+        image::Rgba([255, 255, 255, data[((y * width) + x) as usize]])
+    })
 }
 
 fn expand_round(val: f32, direction: bool) -> f32 {
@@ -69,14 +553,79 @@ impl ImtGlyphBitmap {
         parsed: Arc<ImtParsedGlyph>,
         text_height: f32,
         raster_opts: &ImtRasterOpts,
-    ) -> ImtGlyphBitmap {
-        let font_props = parser.font_props();
+    ) -> Result<ImtGlyphBitmap, ImtError> {
+        let font_props = parser.font_props()?;
         let scaler = font_props.scaler * text_height;
+        let (metrics, offset_x, offset_y) =
+            Self::placement(&parsed, &font_props, text_height, raster_opts);
+
+        let hint_grid = if raster_opts.hinting && raster_opts.align_whole_pixels {
+            let mut zones = vec![0.0];
+            zones.extend(font_props.x_height.filter(|height| *height > 0.0));
+            zones.extend(font_props.cap_height.filter(|height| *height > 0.0));
+            zones.sort_by(f32::total_cmp);
+            zones.dedup();
+
+            Some(HintGrid {
+                zones,
+                y_grid_offset: (font_props.ascender * scaler).fract(),
+                snap_x: raster_opts.subpixel_phases <= 1,
+            })
+        } else {
+            None
+        };
+
+        // Synthetic bold dilates the coverage to the right by this many pixels.
+        let dilate = if parsed.inner.fake_bold {
+            text_height * SYNTHETIC_BOLD_EM
+        } else {
+            0.0
+        };
 
+        Ok(ImtGlyphBitmap {
+            parsed,
+            metrics,
+            offset_x,
+            offset_y,
+            dilate,
+            alpha_mode: raster_opts.alpha_mode,
+            coverage_only: raster_opts.coverage_only,
+            aliased: raster_opts.aliased,
+            curve_steps: raster_opts.curve_steps,
+            fill_rule: raster_opts.fill_rule,
+            data: None,
+            #[cfg(feature = "gpu")]
+            readback_buf: None,
+            lines: Vec::new(),
+            line_bands: Vec::new(),
+            hint_grid,
+            scaler,
+        })
+    }
+
+    /// Metrics of the bitmap `ImtGlyphBitmap::new` creates for `parsed`, before any sub-pixel
+    /// phase widens it. See `ImtGlyphBitmap::apply_x_phase`.
+    pub(crate) fn extent(
+        parsed: &ImtParsedGlyph,
+        font_props: &ImtFontProps,
+        text_height: f32,
+        raster_opts: &ImtRasterOpts,
+    ) -> ImtBitmapMetrics {
+        Self::placement(parsed, font_props, text_height, raster_opts).0
+    }
+
+    /// Metrics of the bitmap along with the offset of the outline within it.
+    fn placement(
+        parsed: &ImtParsedGlyph,
+        font_props: &ImtFontProps,
+        text_height: f32,
+        raster_opts: &ImtRasterOpts,
+    ) -> (ImtBitmapMetrics, f32, f32) {
+        let scaler = font_props.scaler * text_height;
         let mut bearing_x = parsed.min_x * scaler;
         let mut bearing_y = (font_props.ascender - parsed.max_y) * scaler;
 
-        let (offset_x, offset_y) = if raster_opts.align_whole_pixels {
+        let (mut offset_x, mut offset_y) = if raster_opts.align_whole_pixels {
             let offset_x = (bearing_x - bearing_x.ceil()) + 1.0;
             bearing_x = bearing_x.ceil();
             let offset_y = -(bearing_y - bearing_y.ceil()) - 1.0;
@@ -86,16 +635,27 @@ impl ImtGlyphBitmap {
             (0.0, 0.0)
         };
 
-        let height = (expand_round(parsed.max_y * scaler, true)
-            - expand_round(parsed.min_y * scaler, false)) as u32
-            + 1;
-        let width = (expand_round(parsed.max_x * scaler, true)
-            - expand_round(parsed.min_x * scaler, false)) as u32
-            + 1;
+        // Saturated so that absurd sizes are rejected by the raster rather than overflowing.
+        let mut height = ((expand_round(parsed.max_y * scaler, true)
+            - expand_round(parsed.min_y * scaler, false)) as u32)
+            .saturating_add(1);
+        let mut width = ((expand_round(parsed.max_x * scaler, true)
+            - expand_round(parsed.min_x * scaler, false)) as u32)
+            .saturating_add(1);
 
-        ImtGlyphBitmap {
-            parsed,
-            metrics: ImtBitmapMetrics {
+        if raster_opts.padding > 0 {
+            let padding = raster_opts.padding as f32;
+            width = width.saturating_add(raster_opts.padding.saturating_mul(2));
+            height = height.saturating_add(raster_opts.padding.saturating_mul(2));
+            bearing_x -= padding;
+            bearing_y -= padding;
+            // Sample coordinates have y pointing up.
+            offset_x += padding;
+            offset_y -= padding;
+        }
+
+        (
+            ImtBitmapMetrics {
                 width,
                 height,
                 bearing_x,
@@ -103,9 +663,14 @@ impl ImtGlyphBitmap {
             },
             offset_x,
             offset_y,
-            data: None,
-            lines: Vec::new(),
-            scaler,
+        )
+    }
+
+    /// Shift the coverage right by a fraction of a pixel, widening the bitmap to fit.
+    pub(crate) fn apply_x_phase(&mut self, phase_offset: f32) {
+        if phase_offset > 0.0 {
+            self.offset_x += phase_offset;
+            self.metrics.width += 1;
         }
     }
 
@@ -117,30 +682,91 @@ impl ImtGlyphBitmap {
         self.metrics.clone()
     }
 
+    /// Create a bitmap from previously rastered data.
+    ///
+    /// The bitmap has no outline, so only its metrics and data are usable.
+    pub(crate) fn from_data(
+        index: u16,
+        fake_bold: bool,
+        fake_italic: bool,
+        small_caps: bool,
+        metrics: ImtBitmapMetrics,
+        data: ImtBitmapData,
+        raster_opts: &ImtRasterOpts,
+    ) -> ImtGlyphBitmap {
+        let parsed = Arc::new(ImtParsedGlyph {
+            inner: RawGlyph {
+                unicodes: Default::default(),
+                glyph_index: index,
+                liga_component_pos: 0,
+                glyph_origin: GlyphOrigin::Direct,
+                small_caps,
+                multi_subst_dup: false,
+                is_vert_alt: false,
+                fake_bold,
+                fake_italic,
+                extra_data: (),
+                variation: None,
+            },
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: 0.0,
+            max_y: 0.0,
+            hori_adv: 0.0,
+            is_mark: false,
+            contours: Vec::new(),
+        });
+
+        ImtGlyphBitmap {
+            parsed,
+            metrics,
+            lines: Vec::new(),
+            line_bands: Vec::new(),
+            hint_grid: None,
+            scaler: 0.0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            dilate: 0.0,
+            alpha_mode: raster_opts.alpha_mode,
+            coverage_only: raster_opts.coverage_only,
+            aliased: raster_opts.aliased,
+            curve_steps: raster_opts.curve_steps,
+            fill_rule: raster_opts.fill_rule,
+            data: Some(data),
+            #[cfg(feature = "gpu")]
+            readback_buf: None,
+        }
+    }
+
+    /// How color is stored relative to alpha in the data.
+    pub fn alpha_mode(&self) -> ImtAlphaMode {
+        self.alpha_mode
+    }
+
+    /// See `ImtBitmapData::pixel_format`. `None` if not yet rastered.
+    #[cfg(feature = "gpu")]
+    pub fn pixel_format(&self) -> Option<Format> {
+        self.data.as_ref().and_then(ImtBitmapData::pixel_format)
+    }
+
+    /// See `ImtBitmapData::data_len`. `None` if not yet rastered.
+    pub fn data_len(&self) -> Option<usize> {
+        self.data.as_ref().and_then(ImtBitmapData::data_len)
+    }
+
+    /// See `ImtBitmapData::is_empty`. `false` if not yet rastered.
+    pub fn is_empty(&self) -> bool {
+        matches!(&self.data, Some(data) if data.is_empty())
+    }
+
     pub(crate) fn raster_cpu(&mut self, context: &CpuRasterContext) -> Result<(), ImtError> {
-        if self.metrics.width == 0 || self.metrics.height == 0 || self.lines.is_empty() {
-            self.data = Some(ImtBitmapData::Empty);
+        if self.raster_empty() {
             return Ok(());
         }
 
         let ray_count = context.rays.len();
         let sample_count = context.samples.len();
 
-        let ray_intersects =
-            |l1p1: [f32; 2], l1p2: [f32; 2], l2p1: [f32; 2], l2p2: [f32; 2]| -> Option<[f32; 2]> {
-                let r = [l1p2[0] - l1p1[0], l1p2[1] - l1p1[1]];
-                let s = [l2p2[0] - l2p1[0], l2p2[1] - l2p1[1]];
-                let det = (r[0] * s[1]) - (r[1] * s[0]);
-                let u = (((l2p1[0] - l1p1[0]) * r[1]) - ((l2p1[1] - l1p1[1]) * r[0])) / det;
-                let t = (((l2p1[0] - l1p1[0]) * s[1]) - ((l2p1[1] - l1p1[1]) * s[0])) / det;
-
-                if t >= 0.0 && t <= 1.0 && u >= 0.0 && u <= 1.0 {
-                    Some([(l1p1[0] + r[0]) * t, (l1p1[1] + r[1]) * t])
-                } else {
-                    None
-                }
-            };
-
         let cell_height = self.scaler / (sample_count as f32).sqrt();
         let cell_width = cell_height / 3.0;
 
@@ -150,6 +776,7 @@ impl ImtGlyphBitmap {
 
             for ray in context.rays.iter() {
                 let mut hits = 0_isize;
+                let mut winding = 0_isize;
 
                 let ray_dest = [
                     ray_src[0] + (ray[0] * ray_len),
@@ -165,29 +792,33 @@ impl ImtGlyphBitmap {
 
                 let mut ray_min_dist = ray_max_dist;
 
-                for line in self.lines.iter() {
-                    match ray_intersects(
-                        ray_src,
-                        ray_dest,
-                        [line.0.x, line.0.y],
-                        [line.1.x, line.1.y],
-                    ) {
-                        Some(intersect_point) => {
-                            let dist = ((ray_src[0] - intersect_point[0]).powi(2)
-                                + (ray_src[1] - intersect_point[1]).powi(2))
-                            .sqrt();
+                let ray_min_y = ray_src[1].min(ray_dest[1]);
+                let ray_max_y = ray_src[1].max(ray_dest[1]);
 
+                for band in self.line_bands.iter() {
+                    if band.min_y > ray_max_y || band.max_y < ray_min_y {
+                        continue;
+                    }
+
+                    for group in band.groups.iter() {
+                        for (dist, dir) in group.intersect(ray_src, ray_dest).into_iter().flatten()
+                        {
                             if dist < ray_min_dist {
                                 ray_min_dist = dist;
                             }
 
                             hits += 1;
-                        },
-                        None => (),
+                            winding += dir;
+                        }
                     }
                 }
 
-                if hits % 2 != 0 {
+                let inside = match self.fill_rule {
+                    ImtFillRule::EvenOdd => hits % 2 != 0,
+                    ImtFillRule::NonZero => winding != 0,
+                };
+
+                if inside {
                     rays_filled += 1;
                     ray_fill_amt += ray_min_dist / ray_max_dist;
                 }
@@ -229,23 +860,103 @@ impl ImtGlyphBitmap {
             fill_amt_sum / sample_count as f32
         };
 
-        let mut bitmap: Vec<f32> =
-            Vec::with_capacity((self.metrics.width * self.metrics.height * 4) as usize);
-        bitmap.resize((self.metrics.width * self.metrics.height * 4) as usize, 0.0);
+        let get_dilated_value = |coords: [usize; 2], offset: [f32; 2], ray_len: f32| -> f32 {
+            let value = get_value(coords, offset, ray_len);
+
+            if self.dilate > 0.0 {
+                value.max(get_value(
+                    coords,
+                    [offset[0] - self.dilate, offset[1]],
+                    ray_len,
+                ))
+            } else {
+                value
+            }
+        };
+
         let ray_len = ((self.metrics.width as f32 / self.scaler).powi(2)
             + (self.metrics.height as f32 / self.scaler).powi(2))
         .sqrt();
 
+        if self.aliased {
+            let mut covered = vec![false; (self.metrics.width * self.metrics.height) as usize];
+
+            for x in 0..self.metrics.width {
+                for y in 0..self.metrics.height {
+                    covered[((y * self.metrics.width) + x) as usize] = finite_or_zero(
+                        get_dilated_value([x as usize, y as usize], [0.5, 0.0], ray_len),
+                    ) >= 0.5;
+                }
+            }
+
+            // Covered pixels are white with full alpha in either alpha mode.
+            self.data = Some(
+                if self.coverage_only {
+                    ImtBitmapData::R8(Arc::new(
+                        covered
+                            .into_iter()
+                            .map(|covered| covered as u8 * 255)
+                            .collect(),
+                    ))
+                } else {
+                    ImtBitmapData::LRGBA(Arc::new(
+                        covered
+                            .into_iter()
+                            .flat_map(|covered| [covered as u8 as f32; 4])
+                            .collect(),
+                    ))
+                },
+            );
+
+            return Ok(());
+        }
+
+        if self.coverage_only {
+            let mut bitmap: Vec<u8> = vec![0; (self.metrics.width * self.metrics.height) as usize];
+
+            for x in 0..self.metrics.width {
+                for y in 0..self.metrics.height {
+                    let coverage = finite_or_zero(get_dilated_value(
+                        [x as usize, y as usize],
+                        [0.5, 0.0],
+                        ray_len,
+                    ));
+
+                    bitmap[((y * self.metrics.width) + x) as usize] =
+                        (coverage.clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+            }
+
+            self.data = Some(ImtBitmapData::R8(Arc::new(bitmap)));
+            return Ok(());
+        }
+
+        let mut bitmap: Vec<f32> =
+            Vec::with_capacity((self.metrics.width * self.metrics.height * 4) as usize);
+        bitmap.resize((self.metrics.width * self.metrics.height * 4) as usize, 0.0);
+
         for x in 0..self.metrics.width {
             for y in 0..self.metrics.height {
                 let rindex = (((y * self.metrics.width) + x) * 4) as usize;
-                let r = get_value([x as usize, y as usize], [1.0 / 6.0, 0.0], ray_len);
-                let g = get_value([x as usize, y as usize], [3.0 / 6.0, 0.0], ray_len);
-                let b = get_value([x as usize, y as usize], [5.0 / 6.0, 0.0], ray_len);
-                let a = (r + g + b) / 3.0;
-                bitmap[rindex] = r / a;
-                bitmap[rindex + 1] = g / a;
-                bitmap[rindex + 2] = b / a;
+                let r = get_dilated_value([x as usize, y as usize], [1.0 / 6.0, 0.0], ray_len);
+                let g = get_dilated_value([x as usize, y as usize], [3.0 / 6.0, 0.0], ray_len);
+                let b = get_dilated_value([x as usize, y as usize], [5.0 / 6.0, 0.0], ray_len);
+                let (r, g, b) = (finite_or_zero(r), finite_or_zero(g), finite_or_zero(b));
+                let a = r.max(g).max(b);
+
+                // Transparent pixels are left as [0, 0, 0, 0] rather than dividing by zero.
+                if a <= 0.0 {
+                    continue;
+                }
+
+                let (r, g, b) = match self.alpha_mode {
+                    ImtAlphaMode::Premultiplied => (r, g, b),
+                    ImtAlphaMode::Straight => (r / a, g / a, b / a),
+                };
+
+                bitmap[rindex] = r;
+                bitmap[rindex + 1] = g;
+                bitmap[rindex + 2] = b;
                 bitmap[rindex + 3] = a;
             }
         }
@@ -254,41 +965,103 @@ impl ImtGlyphBitmap {
         Ok(())
     }
 
-    pub(crate) fn raster_gpu(&mut self, context: &GpuRasterContext) -> Result<(), ImtError> {
+    /// If there is nothing to raster set the data to empty and return `true`.
+    pub(crate) fn raster_empty(&mut self) -> bool {
         if self.metrics.width == 0 || self.metrics.height == 0 || self.lines.is_empty() {
             self.data = Some(ImtBitmapData::Empty);
-            return Ok(());
+            true
+        } else {
+            false
         }
+    }
 
-        let glyph_buf: Subbuffer<glyph_cs::Glyph> = Buffer::from_data(
-            &context.mem_alloc,
-            BufferCreateInfo {
-                usage: BufferUsage::UNIFORM_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                usage: MemoryUsage::Upload,
-                ..Default::default()
-            },
-            glyph_cs::Glyph {
-                scaler: self.scaler,
-                width: self.metrics.width,
-                height: self.metrics.height,
-                line_count: self.lines.len() as u32,
-                bounds: [
-                    self.parsed.min_x,
-                    self.parsed.max_x,
-                    self.parsed.min_y,
-                    self.parsed.max_y,
-                ],
-                offset: [self.offset_x, self.offset_y],
-            },
+    /// Raster this glyph on the gpu. Returns the gpu time in milliseconds if timing is enabled.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn raster_gpu(
+        &mut self,
+        context: &GpuRasterContext,
+    ) -> Result<Option<f64>, ImtError> {
+        if self.raster_empty() {
+            return Ok(None);
+        }
+
+        let mut cmd_buf = AutoCommandBufferBuilder::primary(
+            &*context.cmd_alloc,
+            context.queue.queue_family_index(),
+            CommandBufferUsage::OneTimeSubmit,
         )
-        .unwrap();
+        .map_err(ImtError::gpu)?;
+
+        let timing = context.begin_timing(&mut cmd_buf)?;
+        self.record_gpu(context, &mut cmd_buf)?;
+
+        if let Some(timing) = timing.as_ref() {
+            timing.end(&mut cmd_buf)?;
+        }
+
+        cmd_buf
+            .build()
+            .map_err(ImtError::gpu)?
+            .execute(context.queue.clone())
+            .map_err(ImtError::gpu)?
+            .then_signal_fence_and_flush()
+            .map_err(ImtError::gpu)?
+            .wait(None)
+            .map_err(ImtError::gpu)?;
+
+        self.finish_gpu();
+        timing.map(|timing| timing.elapsed_ms()).transpose()
+    }
+
+    /// Record the commands to raster this glyph into `cmd_buf`.
+    ///
+    /// `finish_gpu` must be called once the commands have completed executing.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn record_gpu(
+        &mut self,
+        context: &GpuRasterContext,
+        cmd_buf: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), ImtError> {
+        let (glyph_buf, line_buf) = {
+            let buf_alloc = context.buf_alloc.lock();
+
+            (
+                buf_alloc
+                    .allocate_sized::<glyph_cs::Glyph>()
+                    .map_err(ImtError::gpu)?,
+                buf_alloc
+                    .allocate_slice::<[f32; 4]>(self.lines.len().max(1) as u64)
+                    .map_err(ImtError::gpu)?,
+            )
+        };
+
+        *glyph_buf.write().map_err(ImtError::gpu)? = glyph_cs::Glyph {
+            scaler: self.scaler,
+            width: self.metrics.width,
+            height: self.metrics.height,
+            line_count: self.lines.len() as u32,
+            bounds: [
+                self.parsed.min_x,
+                self.parsed.max_x,
+                self.parsed.min_y,
+                self.parsed.max_y,
+            ],
+            offset: [self.offset_x, self.offset_y],
+            dilate: self.dilate,
+        };
+
+        for (dst, line) in line_buf
+            .write()
+            .map_err(ImtError::gpu)?
+            .iter_mut()
+            .zip(self.lines.iter())
+        {
+            *dst = [line.0.x, line.0.y, line.1.x, line.1.y];
+        }
 
         let bitmap_img = ImtImageView::from_storage(
             StorageImage::with_usage(
-                &context.mem_alloc,
+                &*context.mem_alloc,
                 ImageDimensions::Dim2d {
                     width: self.metrics.width,
                     height: self.metrics.height,
@@ -299,45 +1072,21 @@ impl ImtGlyphBitmap {
                 ImageCreateFlags::empty(),
                 iter::once(context.queue.queue_family_index()),
             )
-            .unwrap(),
+            .map_err(ImtError::gpu)?,
         )
-        .unwrap();
+        .map_err(ImtError::gpu)?;
 
-        let line_buf: Subbuffer<[[f32; 4]]> = Buffer::from_iter(
-            &context.mem_alloc,
-            BufferCreateInfo {
-                usage: BufferUsage::STORAGE_BUFFER,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                usage: MemoryUsage::Upload,
-                ..Default::default()
-            },
-            self.lines
-                .iter()
-                .map(|line| [line.0.x, line.0.y, line.1.x, line.1.y]),
+        // Sets are returned to the allocator for reuse once the submission is cleaned up.
+        let glyph_set = PersistentDescriptorSet::new(
+            &*context.set_alloc,
+            context.pipeline.layout().set_layouts()[1].clone(),
+            [
+                WriteDescriptorSet::buffer(0, glyph_buf),
+                WriteDescriptorSet::image_view(1, bitmap_img.clone()),
+                WriteDescriptorSet::buffer(2, line_buf),
+            ],
         )
-        .unwrap();
-
-        let descriptor_set = PersistentDescriptorSet::new(
-            &context.set_alloc,
-            context.pipeline.layout().set_layouts()[0].clone(),
-            vec![
-                WriteDescriptorSet::buffer(0, context.common_buf.clone()),
-                WriteDescriptorSet::buffer(1, glyph_buf),
-                WriteDescriptorSet::image_view(2, bitmap_img.clone()),
-                WriteDescriptorSet::buffer(3, line_buf),
-            ]
-            .into_iter(),
-        )
-        .unwrap();
-
-        let mut cmd_buf = AutoCommandBufferBuilder::primary(
-            &context.cmd_alloc,
-            context.queue.queue_family_index(),
-            CommandBufferUsage::OneTimeSubmit,
-        )
-        .unwrap();
+        .map_err(ImtError::gpu)?;
 
         cmd_buf
             .bind_pipeline_compute(context.pipeline.clone())
@@ -345,26 +1094,17 @@ impl ImtGlyphBitmap {
                 PipelineBindPoint::Compute,
                 context.pipeline.layout().clone(),
                 0,
-                descriptor_set,
+                vec![context.common_set.clone(), glyph_set],
             )
             .dispatch([self.metrics.width, self.metrics.height, 1])
-            .unwrap();
-
-        cmd_buf
-            .build()
-            .unwrap()
-            .execute(context.queue.clone())
-            .unwrap()
-            .then_signal_fence_and_flush()
-            .unwrap()
-            .wait(None)
-            .unwrap();
+            .map_err(ImtError::gpu)?;
 
         if !context.raster_to_image {
-            let len = (self.metrics.width * self.metrics.height * 4) as u64;
+            let channels = if context.coverage_only { 1 } else { 4 };
+            let len = (self.metrics.width * self.metrics.height * channels) as u64;
 
             let bitmap_buf: Subbuffer<[u8]> = Buffer::new_unsized(
-                &context.mem_alloc,
+                &*context.mem_alloc,
                 BufferCreateInfo {
                     usage: BufferUsage::TRANSFER_DST,
                     ..Default::default()
@@ -375,31 +1115,33 @@ impl ImtGlyphBitmap {
                 },
                 len,
             )
-            .unwrap();
-
-            let mut cmd_buf = AutoCommandBufferBuilder::primary(
-                &context.cmd_alloc,
-                context.queue.queue_family_index(),
-                CommandBufferUsage::OneTimeSubmit,
-            )
-            .unwrap();
+            .map_err(ImtError::gpu)?;
 
             cmd_buf
                 .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
                     bitmap_img,
                     bitmap_buf.clone(),
                 ))
-                .unwrap();
+                .map_err(ImtError::gpu)?;
 
-            cmd_buf
-                .build()
-                .unwrap()
-                .execute(context.queue.clone())
-                .unwrap()
-                .then_signal_fence_and_flush()
-                .unwrap()
-                .wait(None)
-                .unwrap();
+            self.readback_buf = Some(bitmap_buf);
+        } else {
+            self.data = Some(ImtBitmapData::Image(bitmap_img));
+        }
+
+        Ok(())
+    }
+
+    /// Read back the raw data if the bitmap isn't being output to an image.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn finish_gpu(&mut self) {
+        if let Some(bitmap_buf) = self.readback_buf.take() {
+            if self.coverage_only {
+                self.data = Some(ImtBitmapData::R8(Arc::new(
+                    bitmap_buf.read().unwrap().to_vec(),
+                )));
+                return;
+            }
 
             self.data = Some(ImtBitmapData::LRGBA(Arc::new(
                 bitmap_buf
@@ -409,17 +1151,55 @@ impl ImtGlyphBitmap {
                     .map(|v| *v as f32 / u8::max_value() as f32)
                     .collect(),
             )));
-        } else {
-            self.data = Some(ImtBitmapData::Image(bitmap_img));
         }
+    }
 
-        Ok(())
+    /// Convert `LRGBA` or `R8` data into a sRGB encoded `image::RgbaImage` with straight alpha.
+    /// `R8` coverage is output as white.
+    ///
+    /// Returns `None` if the bitmap has no data or was output to an image. See
+    /// `ImtRaster::bitmap_to_rgba_image` for reading back the latter.
+    #[cfg(feature = "image")]
+    pub fn to_rgba_image(&self) -> Option<image::RgbaImage> {
+        match self.data.as_ref()? {
+            ImtBitmapData::Empty => {
+                Some(image::RgbaImage::new(
+                    self.metrics.width,
+                    self.metrics.height,
+                ))
+            },
+            ImtBitmapData::LRGBA(data) => {
+                Some(lrgba_to_rgba_image(
+                    self.metrics.width,
+                    self.metrics.height,
+                    self.alpha_mode,
+                    data,
+                ))
+            },
+            ImtBitmapData::R8(data) => {
+                Some(r8_to_rgba_image(
+                    self.metrics.width,
+                    self.metrics.height,
+                    data,
+                ))
+            },
+            #[cfg(feature = "gpu")]
+            ImtBitmapData::Image(_) => None,
+        }
     }
 
     pub(crate) fn create_outline(&mut self) {
-        for geometry in self.parsed.geometry.clone() {
-            self.draw_geometry(&geometry);
+        let parsed = self.parsed.clone();
+
+        for geometry in parsed.geometry() {
+            self.draw_geometry(geometry);
         }
+
+        if let Some(hint_grid) = self.hint_grid.as_ref() {
+            hint_grid.apply(&mut self.lines, self.scaler);
+        }
+
+        self.line_bands = LineBand::from_lines(&self.lines);
     }
 
     fn draw_geometry(&mut self, geo: &ImtGeometry) {
@@ -443,9 +1223,12 @@ impl ImtGlyphBitmap {
     }
 
     fn draw_curve(&mut self, point_a: &ImtPoint, point_b: &ImtPoint, point_c: &ImtPoint) {
-        let mut length = 0.0;
+        let steps = match self.curve_steps {
+            Some(steps) => steps.max(1) as usize,
+            None => self.curve_steps_for_length(point_a, point_b, point_c),
+        };
+
         let mut last_point = point_a.clone();
-        let mut steps = 10_usize;
 
         for s in 1..=steps {
             let t = s as f32 / steps as f32;
@@ -458,17 +1241,21 @@ impl ImtGlyphBitmap {
                     + (t.powi(2) * point_c.y),
             };
 
-            length += last_point.dist(&next_point);
+            self.draw_line(&last_point, &next_point);
             last_point = next_point;
         }
+    }
 
-        steps = (length * self.scaler * 2.0).ceil() as usize;
-
-        if steps < 3 {
-            steps = 3;
-        }
-
-        last_point = point_a.clone();
+    /// Number of steps to subdivide a curve into based on its approximate length.
+    fn curve_steps_for_length(
+        &self,
+        point_a: &ImtPoint,
+        point_b: &ImtPoint,
+        point_c: &ImtPoint,
+    ) -> usize {
+        let mut length = 0.0;
+        let mut last_point = point_a.clone();
+        let mut steps = 10_usize;
 
         for s in 1..=steps {
             let t = s as f32 / steps as f32;
@@ -481,8 +1268,358 @@ impl ImtGlyphBitmap {
                     + (t.powi(2) * point_c.y),
             };
 
-            self.draw_line(&last_point, &next_point);
+            length += last_point.dist(&next_point);
             last_point = next_point;
         }
+
+        steps = (length * self.scaler * 2.0).ceil() as usize;
+
+        if steps < 3 {
+            steps = 3;
+        }
+
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hint::black_box;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::test_font::TestFont;
+    use crate::{ImtFont, ImtWeight};
+
+    fn line(x1: f32, y1: f32, x2: f32, y2: f32) -> (ImtPoint, ImtPoint) {
+        (
+            ImtPoint {
+                x: x1,
+                y: y1,
+            },
+            ImtPoint {
+                x: x2,
+                y: y2,
+            },
+        )
+    }
+
+    /// Intersection of a ray with a single line, as done by the gpu.
+    fn scalar_intersect(
+        ray_src: [f32; 2],
+        ray_dest: [f32; 2],
+        line: &(ImtPoint, ImtPoint),
+    ) -> Option<(f32, isize)> {
+        let r = [ray_dest[0] - ray_src[0], ray_dest[1] - ray_src[1]];
+        let s = [line.1.x - line.0.x, line.1.y - line.0.y];
+        let det = (r[0] * s[1]) - (r[1] * s[0]);
+        let u = (((line.0.x - ray_src[0]) * r[1]) - ((line.0.y - ray_src[1]) * r[0])) / det;
+        let t = (((line.0.x - ray_src[0]) * s[1]) - ((line.0.y - ray_src[1]) * s[0])) / det;
+
+        if !((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)) {
+            return None;
+        }
+
+        let point = [ray_src[0] + (r[0] * t), ray_src[1] + (r[1] * t)];
+        let dist = ((point[0] - ray_src[0]).powi(2) + (point[1] - ray_src[1]).powi(2)).sqrt();
+        Some((dist, if det > 0.0 { 1 } else { -1 }))
+    }
+
+    #[test]
+    fn line_group_intersect() {
+        // A vertical line three units along a horizontal ray.
+        let groups = LineGroup::from_lines(&[line(4.0, -5.0, 4.0, 5.0)]);
+        let hits = groups[0].intersect([1.0, 1.0], [11.0, 1.0]);
+        let (dist, dir) = hits[0].unwrap();
+        assert!((dist - 3.0).abs() < 1e-5);
+        assert_eq!(dir, 1);
+
+        // Unused slots never intersect.
+        assert!(hits[1..].iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn line_group_matches_scalar() {
+        let lines = [
+            line(4.0, -5.0, 4.0, 5.0),
+            line(4.0, 5.0, -4.0, 5.0),
+            line(-4.0, 5.0, -4.0, -5.0),
+            line(-4.0, -5.0, 4.0, -5.0),
+            // Parallel to the first ray.
+            line(0.0, 1.0, 10.0, 1.0),
+            line(1.0, -2.0, 3.0, 7.0),
+        ];
+
+        let rays = [
+            ([0.0, 0.0], [10.0, 0.0]),
+            ([0.0, 0.0], [-10.0, 3.0]),
+            ([2.5, -7.0], [2.5, 8.0]),
+            ([-6.0, -6.0], [6.0, 6.0]),
+            ([0.0, 0.0], [1.0, 1.0]),
+        ];
+
+        let groups = LineGroup::from_lines(&lines);
+        assert_eq!(groups.len(), 2);
+
+        for (ray_src, ray_dest) in rays {
+            let grouped: Vec<_> = groups
+                .iter()
+                .flat_map(|group| group.intersect(ray_src, ray_dest))
+                .collect();
+
+            for (i, line) in lines.iter().enumerate() {
+                match (grouped[i], scalar_intersect(ray_src, ray_dest, line)) {
+                    (None, None) => (),
+                    (Some((dist, dir)), Some((expected_dist, expected_dir))) => {
+                        assert!((dist - expected_dist).abs() < 1e-4, "line {}", i);
+                        assert_eq!(dir, expected_dir, "line {}", i);
+                    },
+                    (grouped, scalar) => {
+                        panic!("line {}: grouped {:?}, scalar {:?}", i, grouped, scalar)
+                    },
+                }
+            }
+        }
+    }
+
+    /// Lines of a ring with an outer & inner contour of `segments` lines each, as many as a
+    /// complex glyph has.
+    fn ring_lines(segments: usize) -> Vec<(ImtPoint, ImtPoint)> {
+        let mut lines = Vec::with_capacity(segments * 2);
+
+        for radius in [10.0, 6.0] {
+            let point = |i: usize| {
+                let angle = (i as f32 / segments as f32) * std::f32::consts::TAU;
+                ImtPoint {
+                    x: angle.cos() * radius,
+                    y: angle.sin() * radius,
+                }
+            };
+
+            for i in 0..segments {
+                lines.push((point(i), point(i + 1)));
+            }
+        }
+
+        lines
+    }
+
+    /// Rays in eight directions from each point of a grid covering the ring.
+    fn ring_rays() -> Vec<([f32; 2], [f32; 2])> {
+        let mut rays = Vec::new();
+
+        for y in 0..32 {
+            for x in 0..32 {
+                let src = [(x as f32 * 0.75) - 12.0, (y as f32 * 0.75) - 12.0];
+
+                for dir in 0..8 {
+                    let angle = (dir as f32 / 8.0) * std::f32::consts::TAU;
+                    rays.push((
+                        src,
+                        [src[0] + (angle.cos() * 25.0), src[1] + (angle.sin() * 25.0)],
+                    ));
+                }
+            }
+        }
+
+        rays
+    }
+
+    #[test]
+    fn line_group_intersect_matches_scalar_path() {
+        let groups = LineGroup::from_lines(&ring_lines(64));
+
+        for (ray_src, ray_dest) in ring_rays() {
+            for group in groups.iter() {
+                let hits = group.intersect(ray_src, ray_dest);
+                let scalar_hits = group.intersect_scalar(ray_src, ray_dest);
+
+                for (hit, scalar_hit) in hits.into_iter().zip(scalar_hits) {
+                    match (hit, scalar_hit) {
+                        (None, None) => (),
+                        (Some((dist, dir)), Some((expected_dist, expected_dir))) => {
+                            assert!((dist - expected_dist).abs() < 1e-4);
+                            assert_eq!(dir, expected_dir);
+                        },
+                        (hit, scalar_hit) => {
+                            panic!("hit {:?}, scalar hit {:?}", hit, scalar_hit)
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    /// Throughput of `LineGroup::intersect` against the scalar path on the lines of a complex
+    /// glyph.
+    ///
+    /// Run with `cargo test --release line_group_intersect_bench -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn line_group_intersect_bench() {
+        const ITERATIONS: usize = 20;
+
+        type Intersect = fn(&LineGroup, [f32; 2], [f32; 2]) -> [Option<(f32, isize)>; GROUP_LEN];
+
+        let groups = LineGroup::from_lines(&ring_lines(256));
+        let rays = ring_rays();
+
+        let bench = |intersect: Intersect| {
+            let start = Instant::now();
+            let mut hits = 0;
+
+            for _ in 0..ITERATIONS {
+                for &(ray_src, ray_dest) in rays.iter() {
+                    for group in groups.iter() {
+                        hits += black_box(intersect(group, ray_src, ray_dest))
+                            .into_iter()
+                            .flatten()
+                            .count();
+                    }
+                }
+            }
+
+            (start.elapsed(), hits)
+        };
+
+        let (elapsed, hits) = bench(LineGroup::intersect);
+        let (scalar_elapsed, scalar_hits) = bench(LineGroup::intersect_scalar);
+        assert_eq!(hits, scalar_hits);
+        let tests = (ITERATIONS * rays.len() * groups.len() * GROUP_LEN) as f64;
+
+        for (name, elapsed) in [("intersect", elapsed), ("intersect_scalar", scalar_elapsed)] {
+            println!(
+                "{}: {:.0} line tests/ms",
+                name,
+                tests / (elapsed.as_secs_f64() * 1000.0)
+            );
+        }
+
+        println!(
+            "speedup: {:.2}x",
+            scalar_elapsed.as_secs_f64() / elapsed.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn interpolate_hint_points() {
+        let from = [0.0, 500.0, 700.0];
+        let to = [10.0, 480.0, 720.0];
+
+        // Without points values are left as is.
+        assert_eq!(interpolate_hint(123.0, &[], &[]), 123.0);
+
+        // Points move exactly to their targets.
+        for (from_val, to_val) in from.iter().zip(to.iter()) {
+            assert_eq!(interpolate_hint(*from_val, &from, &to), *to_val);
+        }
+
+        // Values between points are interpolated.
+        assert_eq!(interpolate_hint(250.0, &from, &to), 245.0);
+        assert_eq!(interpolate_hint(600.0, &from, &to), 600.0);
+
+        // Values outside move with the nearest point.
+        assert_eq!(interpolate_hint(-100.0, &from, &to), -90.0);
+        assert_eq!(interpolate_hint(800.0, &from, &to), 820.0);
+    }
+
+    #[test]
+    fn interpolate_hint_monotonic() {
+        let from = [-200.0, 0.0, 510.0, 730.0];
+        let to = [-192.0, 0.0, 512.0, 768.0];
+        let mut prev = f32::NEG_INFINITY;
+
+        for i in -400..1000 {
+            let val = interpolate_hint(i as f32, &from, &to);
+            assert!(val >= prev);
+            prev = val;
+        }
+    }
+
+    #[test]
+    fn hint_grid_degenerate_lines() {
+        let grid = HintGrid {
+            zones: vec![0.0, 500.0],
+            y_grid_offset: 0.0,
+            snap_x: true,
+        };
+
+        let mut lines = vec![
+            line(100.0, 0.0, 100.0, 500.0),
+            line(f32::NAN, 0.0, f32::NAN, 500.0),
+            line(300.0, 0.0, 300.0, 500.0),
+        ];
+
+        // A NaN stem must neither panic nor spread to the other lines.
+        grid.apply(&mut lines, 0.01);
+
+        for (a, b) in [&lines[0], &lines[2]] {
+            assert!(a.x.is_finite() && a.y.is_finite());
+            assert!(b.x.is_finite() && b.y.is_finite());
+        }
+
+        assert_eq!(lines[0].0.x, 100.0);
+        assert_eq!(lines[2].1.y, 500.0);
+    }
+
+    /// `LRGBA` data of the `O` of `TestFont` rastered on the cpu at 40px.
+    fn raster_o(alpha_mode: ImtAlphaMode) -> (ImtBitmapMetrics, Vec<f32>) {
+        let test_font = TestFont::new();
+
+        let font = ImtFont::from_bytes_cpu(
+            "Test",
+            ImtWeight::Normal,
+            ImtRasterOpts {
+                alpha_mode,
+                ..ImtRasterOpts::default()
+            },
+            test_font.build(),
+        )
+        .unwrap();
+
+        let bitmap = font.raster_glyph(test_font.glyph_index('O'), 40.0).unwrap();
+
+        match bitmap.data() {
+            Some(ImtBitmapData::LRGBA(data)) => (bitmap.metrics(), data.to_vec()),
+            _ => panic!("expected LRGBA data"),
+        }
+    }
+
+    #[test]
+    fn hole_is_transparent() {
+        for alpha_mode in [ImtAlphaMode::Straight, ImtAlphaMode::Premultiplied] {
+            let (metrics, data) = raster_o(alpha_mode);
+            assert!(data.iter().all(|value| value.is_finite()));
+
+            for pixel in data.chunks(4) {
+                if pixel[3] == 0.0 {
+                    assert_eq!(pixel, [0.0; 4]);
+                }
+            }
+
+            // The middle of the O is inside its hole.
+            let center = (((metrics.height / 2) * metrics.width) + (metrics.width / 2)) as usize;
+            assert_eq!(data[center * 4..(center + 1) * 4], [0.0; 4]);
+        }
+    }
+
+    #[test]
+    fn alpha_modes() {
+        let (_, straight) = raster_o(ImtAlphaMode::Straight);
+        let (_, premultiplied) = raster_o(ImtAlphaMode::Premultiplied);
+        assert_eq!(straight.len(), premultiplied.len());
+
+        for (straight, premultiplied) in straight.chunks(4).zip(premultiplied.chunks(4)) {
+            let alpha = premultiplied[3];
+            assert_eq!(straight[3], alpha);
+            assert_eq!(
+                premultiplied[0].max(premultiplied[1]).max(premultiplied[2]),
+                alpha
+            );
+
+            for (straight, premultiplied) in straight[..3].iter().zip(&premultiplied[..3]) {
+                assert!(((straight * alpha) - premultiplied).abs() < 1e-5);
+            }
+        }
     }
 }
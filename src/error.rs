@@ -24,14 +24,19 @@ pub enum ImtErrorSrc {
     Glyph,
     Bitmap,
     Vhea,
+    Os2,
+    Post,
     Ilmenite,
     Shaper,
+    Cache,
+    Gpu,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum ImtErrorTy {
     Unimplemented,
     FileRead,
+    FileWrite,
     FileGeneric,
     FileBadEof,
     FileBadValue,
@@ -42,11 +47,21 @@ pub enum ImtErrorTy {
     FileMissingValue,
     FileCompressionError,
     FileUnsupportedFormat,
+    FileUnsupportedEncoding,
     FileMissingTable,
     FileMissingSubTable,
     MissingIndex,
     MissingGlyph,
     MissingFont,
+    MissingRaster,
+    ParserTimeout,
+    ParserPanicked,
+    CacheStale,
+    BitmapTooLarge {
+        width: u32,
+        height: u32,
+        max_dimension: u32,
+    },
     UnimplementedDataTy,
     Other(String),
 }
@@ -80,11 +95,18 @@ impl ImtError {
         }
     }
 
+    /// An error returned by vulkano while rasterizing on the gpu.
+    #[cfg(feature = "gpu")]
+    pub(crate) fn gpu<E: std::fmt::Display>(err: E) -> Self {
+        Self::src_and_ty(ImtErrorSrc::Gpu, ImtErrorTy::Other(err.to_string()))
+    }
+
     // TODO: Implement mapping of ShapingError
     pub fn allsorts_shaping(src: ImtErrorSrc, err: ShapingError) -> Self {
-        println!(
-            "Basalt Text: Returning unimplemented error! src: {:?}, err: {:?}",
-            src, err
+        log::warn!(
+            "Returning unimplemented error! src: {:?}, err: {:?}",
+            src,
+            err
         );
 
         Self::src_and_ty(src, ImtErrorTy::Unimplemented)
@@ -1,13 +1,20 @@
+use std::borrow::Borrow;
 use std::fs::File;
-use std::io::Read;
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
 use std::path::Path;
 use std::sync::Arc;
 
+#[cfg(feature = "gpu")]
 use vulkano::device::{Device, Queue};
 
+use crate::parse::is_variation_selector;
+use crate::shape::bidi_runs;
 use crate::{
-    ImtError, ImtErrorSrc, ImtErrorTy, ImtGlyph, ImtLang, ImtParser, ImtRaster, ImtRasterOpts,
-    ImtScript, ImtShapeOpts, ImtShaper,
+    ImtError, ImtErrorSrc, ImtErrorTy, ImtFeatureTag, ImtFillQuality, ImtGlyph, ImtGlyphBitmap,
+    ImtGlyphPosition, ImtLang, ImtParsedGlyph, ImtParser, ImtPoint, ImtPosition, ImtRaster,
+    ImtRasterOpts, ImtSampleQuality, ImtScript, ImtShapeOpts, ImtShapedGlyph, ImtShapedLines,
+    ImtShaper, ImtTextOpts,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Hash, Eq)]
@@ -23,21 +30,126 @@ pub enum ImtWeight {
     UltraBold,
 }
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone)]
 pub(crate) struct ImtFontKey {
     pub family: String,
     pub weight: ImtWeight,
 }
 
+/// Allows an `ImtFontKey` to be looked up with a borrowed family, e.g. `(&str, ImtWeight)`.
+pub(crate) trait ImtFontKeyRef {
+    fn family(&self) -> &str;
+    fn weight(&self) -> ImtWeight;
+}
+
+impl ImtFontKeyRef for ImtFontKey {
+    fn family(&self) -> &str {
+        &self.family
+    }
+
+    fn weight(&self) -> ImtWeight {
+        self.weight
+    }
+}
+
+impl ImtFontKeyRef for (&str, ImtWeight) {
+    fn family(&self) -> &str {
+        self.0
+    }
+
+    fn weight(&self) -> ImtWeight {
+        self.1
+    }
+}
+
+impl<'a> Borrow<dyn ImtFontKeyRef + 'a> for ImtFontKey {
+    fn borrow(&self) -> &(dyn ImtFontKeyRef + 'a) {
+        self
+    }
+}
+
+impl Hash for dyn ImtFontKeyRef + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.family().hash(state);
+        self.weight().hash(state);
+    }
+}
+
+impl PartialEq for dyn ImtFontKeyRef + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.family() == other.family() && self.weight() == other.weight()
+    }
+}
+
+impl Eq for dyn ImtFontKeyRef + '_ {}
+
+// Must match the hash of `dyn ImtFontKeyRef` for borrowed lookups to work.
+impl Hash for ImtFontKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as &dyn ImtFontKeyRef).hash(state);
+    }
+}
+
+impl PartialEq for ImtFontKey {
+    fn eq(&self, other: &Self) -> bool {
+        (self as &dyn ImtFontKeyRef) == (other as &dyn ImtFontKeyRef)
+    }
+}
+
+impl Eq for ImtFontKey {}
+
+/// A font with its parser, shaper & optional rasterizer.
+///
+/// `ImtFont` is `Send + Sync` and may be shared between threads, as `Ilmenite` does. The font's
+/// tables, which borrow from its bytes, are owned by the parser's worker thread and are only
+/// accessed through requests sent to it. On wasm or with `ImtParserOpts::synchronous` there is
+/// no worker and requests are answered on the calling thread instead.
 pub struct ImtFont {
     family: String,
     weight: ImtWeight,
     parser: ImtParser,
     shaper: ImtShaper,
-    raster: ImtRaster,
+    raster: Option<ImtRaster>,
+}
+
+// Fonts are shared between threads by `Ilmenite`, so losing either bound would be breaking.
+const _: () = {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[allow(dead_code)]
+    fn assert_all() {
+        assert_send_sync::<ImtFont>();
+        assert_send_sync::<ImtParser>();
+        assert_send_sync::<ImtRaster>();
+        assert_send_sync::<crate::Ilmenite>();
+    }
+};
+
+fn read_font_bytes<R: Read>(mut reader: R) -> Result<Vec<u8>, ImtError> {
+    let mut bytes = Vec::new();
+
+    reader
+        .read_to_end(&mut bytes)
+        .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
+
+    Ok(bytes)
 }
 
 impl ImtFont {
+    /// Same as `from_file_gpu`.
+    #[cfg(feature = "gpu")]
+    pub fn from_file<F: Into<String>, P: AsRef<Path>>(
+        family: F,
+        weight: ImtWeight,
+        raster_ops: ImtRasterOpts,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        path: P,
+    ) -> Result<ImtFont, ImtError> {
+        Self::from_file_gpu(family, weight, raster_ops, device, queue, path)
+    }
+
+    #[cfg(feature = "gpu")]
     pub fn from_file_gpu<F: Into<String>, P: AsRef<Path>>(
         family: F,
         weight: ImtWeight,
@@ -46,12 +158,22 @@ impl ImtFont {
         queue: Arc<Queue>,
         path: P,
     ) -> Result<ImtFont, ImtError> {
-        let mut handle = File::open(path.as_ref())
-            .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
-        let mut bytes = Vec::new();
-        handle
-            .read_to_end(&mut bytes)
+        let handle = File::open(path.as_ref())
             .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
+        Self::from_reader_gpu(family, weight, raster_ops, device, queue, handle)
+    }
+
+    /// Same as `from_bytes_gpu`, but reads the bytes from `reader` until its end.
+    #[cfg(feature = "gpu")]
+    pub fn from_reader_gpu<F: Into<String>, R: Read>(
+        family: F,
+        weight: ImtWeight,
+        raster_ops: ImtRasterOpts,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        reader: R,
+    ) -> Result<ImtFont, ImtError> {
+        let bytes = read_font_bytes(reader)?;
         Self::from_bytes_gpu(family, weight, raster_ops, device, queue, bytes)
     }
 
@@ -61,22 +183,53 @@ impl ImtFont {
         raster_ops: ImtRasterOpts,
         path: P,
     ) -> Result<ImtFont, ImtError> {
-        let mut handle = File::open(path.as_ref())
-            .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
-        let mut bytes = Vec::new();
-        handle
-            .read_to_end(&mut bytes)
+        let handle = File::open(path.as_ref())
             .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
+        Self::from_reader_cpu(family, weight, raster_ops, handle)
+    }
+
+    /// Same as `from_bytes_cpu`, but reads the bytes from `reader` until its end.
+    pub fn from_reader_cpu<F: Into<String>, R: Read>(
+        family: F,
+        weight: ImtWeight,
+        raster_ops: ImtRasterOpts,
+        reader: R,
+    ) -> Result<ImtFont, ImtError> {
+        let bytes = read_font_bytes(reader)?;
         Self::from_bytes_cpu(family, weight, raster_ops, bytes)
     }
 
-    pub fn from_bytes_gpu<F: Into<String>>(
+    /// Load a font from a file without any rasterization state.
+    ///
+    /// See `from_bytes_outline`.
+    pub fn from_file_outline<F: Into<String>, P: AsRef<Path>>(
+        family: F,
+        weight: ImtWeight,
+        path: P,
+    ) -> Result<ImtFont, ImtError> {
+        let handle = File::open(path.as_ref())
+            .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::File, ImtErrorTy::FileRead))?;
+        Self::from_reader_outline(family, weight, handle)
+    }
+
+    /// Same as `from_bytes_outline`, but reads the bytes from `reader` until its end.
+    pub fn from_reader_outline<F: Into<String>, R: Read>(
+        family: F,
+        weight: ImtWeight,
+        reader: R,
+    ) -> Result<ImtFont, ImtError> {
+        let bytes = read_font_bytes(reader)?;
+        Self::from_bytes_outline(family, weight, bytes)
+    }
+
+    #[cfg(feature = "gpu")]
+    pub fn from_bytes_gpu<F: Into<String>, B: AsRef<[u8]> + Send + 'static>(
         family: F,
         weight: ImtWeight,
         raster_ops: ImtRasterOpts,
         device: Arc<Device>,
         queue: Arc<Queue>,
-        bytes: Vec<u8>,
+        bytes: B,
     ) -> Result<ImtFont, ImtError> {
         let parser = ImtParser::new(bytes)?;
         let shaper = ImtShaper::new()?;
@@ -87,20 +240,82 @@ impl ImtFont {
             weight,
             parser,
             shaper,
-            raster,
+            raster: Some(raster),
         })
     }
 
-    pub fn from_bytes_cpu<F: Into<String>>(
+    pub fn from_bytes_cpu<F: Into<String>, B: AsRef<[u8]> + Send + 'static>(
         family: F,
         weight: ImtWeight,
         raster_ops: ImtRasterOpts,
-        bytes: Vec<u8>,
+        bytes: B,
     ) -> Result<ImtFont, ImtError> {
         let parser = ImtParser::new(bytes)?;
         let shaper = ImtShaper::new()?;
         let raster = ImtRaster::new_cpu(raster_ops)?;
 
+        Ok(ImtFont {
+            family: family.into(),
+            weight,
+            parser,
+            shaper,
+            raster: Some(raster),
+        })
+    }
+
+    /// Load a font using an already created raster.
+    ///
+    /// This allows for the raster to be created with `ImtRaster::new_gpu_with_allocators`.
+    pub fn from_bytes_with_raster<F: Into<String>, B: AsRef<[u8]> + Send + 'static>(
+        family: F,
+        weight: ImtWeight,
+        raster: ImtRaster,
+        bytes: B,
+    ) -> Result<ImtFont, ImtError> {
+        let parser = ImtParser::new(bytes)?;
+        let shaper = ImtShaper::new()?;
+
+        Ok(ImtFont {
+            family: family.into(),
+            weight,
+            parser,
+            shaper,
+            raster: Some(raster),
+        })
+    }
+
+    /// Load a font without any rasterization state for shaping and outlines only.
+    ///
+    /// `glyphs_for_text` will return a `MissingRaster` error, but `shape_text` and the outlines
+    /// available through `parser` work as normal.
+    pub fn from_bytes_outline<F: Into<String>, B: AsRef<[u8]> + Send + 'static>(
+        family: F,
+        weight: ImtWeight,
+        bytes: B,
+    ) -> Result<ImtFont, ImtError> {
+        let parser = ImtParser::new(bytes)?;
+        let shaper = ImtShaper::new()?;
+
+        Ok(ImtFont {
+            family: family.into(),
+            weight,
+            parser,
+            shaper,
+            raster: None,
+        })
+    }
+
+    /// Create a font from an already created parser & optional raster.
+    ///
+    /// This allows for the parser to be created with `ImtParser::new_shared`.
+    pub fn from_parser<F: Into<String>>(
+        family: F,
+        weight: ImtWeight,
+        parser: ImtParser,
+        raster: Option<ImtRaster>,
+    ) -> Result<ImtFont, ImtError> {
+        let shaper = ImtShaper::new()?;
+
         Ok(ImtFont {
             family: family.into(),
             weight,
@@ -117,42 +332,484 @@ impl ImtFont {
         }
     }
 
-    pub fn glyphs_for_text<T: AsRef<str>>(
+    /// Snapshot of the completed bitmaps in this font's raster cache.
+    ///
+    /// See `ImtRaster::cached_bitmaps`.
+    pub fn cached_bitmaps(&self) -> Vec<(u16, f32, Arc<ImtGlyphBitmap>)> {
+        match self.raster.as_ref() {
+            Some(raster) => raster.cached_bitmaps(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Write this font's raster cache to `writer`.
+    ///
+    /// See `ImtRaster::export_cache`.
+    pub fn export_cache<W: Write>(&self, writer: W) -> Result<(), ImtError> {
+        self.raster
+            .as_ref()
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::MissingRaster,
+            ))?
+            .export_cache(&self.parser, writer)
+    }
+
+    /// Load a raster cache previously written with `export_cache`.
+    ///
+    /// See `ImtRaster::import_cache`.
+    pub fn import_cache<R: Read>(&self, reader: R) -> Result<(), ImtError> {
+        self.raster
+            .as_ref()
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::MissingRaster,
+            ))?
+            .import_cache(&self.parser, reader)
+    }
+
+    /// The parser of this font, which provides font properties & glyph outlines.
+    pub fn parser(&self) -> &ImtParser {
+        &self.parser
+    }
+
+    /// Whether every character of `text` is mapped to a glyph by this font, see `missing_chars`.
+    pub fn covers<T: AsRef<str>>(&self, text: T) -> bool {
+        self.missing_chars(text).is_empty()
+    }
+
+    /// Characters of `text` this font has no glyph for, in order of first occurrence. Control
+    /// characters are ignored. If the parser has failed all characters are considered missing.
+    pub fn missing_chars<T: AsRef<str>>(&self, text: T) -> Vec<char> {
+        let text = text.as_ref();
+
+        self.parser.missing_chars(text).unwrap_or_else(|_| {
+            let mut missing: Vec<char> = Vec::new();
+
+            for c in text.chars() {
+                if !c.is_control() && !missing.contains(&c) {
+                    missing.push(c);
+                }
+            }
+
+            missing
+        })
+    }
+
+    /// Whether this font was created with a rasterizer.
+    pub fn has_raster(&self) -> bool {
+        self.raster.is_some()
+    }
+
+    /// Change the quality of this font's raster, see `ImtRaster::set_quality`.
+    pub fn set_raster_quality(
+        &mut self,
+        fill_quality: ImtFillQuality,
+        sample_quality: ImtSampleQuality,
+    ) -> Result<(), ImtError> {
+        self.raster
+            .as_mut()
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::MissingRaster,
+            ))?
+            .set_quality(fill_quality, sample_quality)
+    }
+
+    /// Rasterize the glyph at `index` without mapping or shaping any text. The bitmap is shared
+    /// with the raster cache, so glyphs not reachable through the cmap can be rastered as well.
+    pub fn raster_glyph(
+        &self,
+        index: u16,
+        text_height: f32,
+    ) -> Result<Arc<ImtGlyphBitmap>, ImtError> {
+        let raster = self.raster.as_ref().ok_or(ImtError::src_and_ty(
+            ImtErrorSrc::Ilmenite,
+            ImtErrorTy::MissingRaster,
+        ))?;
+
+        Ok(raster
+            .raster_shaped_glyphs(
+                &self.parser,
+                text_height,
+                vec![self.glyph_at_origin(index)?],
+            )?
+            .pop()
+            .unwrap()
+            .bitmap)
+    }
+
+    /// Same as `raster_glyph`, but with the outline transformed by `transform`. See
+    /// `ImtParsedGlyph::transformed` for the layout of `transform`. The bitmap isn't cached.
+    pub fn raster_glyph_transformed(
         &self,
+        index: u16,
         text_height: f32,
+        transform: &[f32; 6],
+    ) -> Result<Arc<ImtGlyphBitmap>, ImtError> {
+        let raster = self.raster.as_ref().ok_or(ImtError::src_and_ty(
+            ImtErrorSrc::Ilmenite,
+            ImtErrorTy::MissingRaster,
+        ))?;
+
+        Ok(raster
+            .raster_transformed_glyph(
+                &self.parser,
+                text_height,
+                self.glyph_at_origin(index)?,
+                transform,
+            )?
+            .bitmap)
+    }
+
+    fn glyph_at_origin(&self, index: u16) -> Result<ImtShapedGlyph, ImtError> {
+        Ok(ImtShapedGlyph {
+            parsed: self.parser.retrieve_glyph(index)?,
+            position: ImtPosition {
+                x: 0.0,
+                y: 0.0,
+            },
+            x_overflow: 0.0,
+            y_overflow: 0.0,
+            x_underflow: 0.0,
+            y_underflow: 0.0,
+            unicodes: Vec::new(),
+        })
+    }
+
+    /// Parse and shape text without rasterizing it.
+    ///
+    /// Positions are in font units, see `ImtFontProps::font_units_to_px`. Lines mixing
+    /// left-to-right & right-to-left text are reordered for display before being wrapped.
+    pub fn shape_text<T: AsRef<str>>(
+        &self,
         shape_ops: ImtShapeOpts,
         text: T,
-    ) -> Result<Vec<ImtGlyph>, ImtError> {
-        // TODO: Auto detect script/lang or require params to specify?
-        let script = ImtScript::Default;
-        let lang = ImtLang::Default;
-        let parsed_glyphs = self.parser.retrieve_text(text, script, lang)?;
-        let shaped_glyphs = self.shaper.shape_parsed_glyphs(
+    ) -> Result<Vec<ImtShapedGlyph>, ImtError> {
+        let mut shaped_glyphs = Vec::new();
+
+        for line in self.shape_text_by_line(shape_ops, text)? {
+            shaped_glyphs.append(&mut line?);
+        }
+
+        Ok(shaped_glyphs)
+    }
+
+    /// Same as `shape_text`, but lines are shaped one at a time as the returned iterator is
+    /// advanced, allowing long text to be consumed incrementally.
+    pub fn shape_text_by_line<T: AsRef<str>>(
+        &self,
+        shape_ops: ImtShapeOpts,
+        text: T,
+    ) -> Result<ImtShapedLines<'_>, ImtError> {
+        let (script, lang, parsed_glyphs) = self.parse_text_visual(&shape_ops, text)?;
+
+        let lines = self.shaper.shape_parsed_glyphs_by_line(
             &self.parser,
             script,
             lang,
             shape_ops,
             parsed_glyphs,
         )?;
+
+        // Clip against the bitmaps this font's raster will create.
+        Ok(match self.raster.as_ref() {
+            Some(raster) => lines.with_raster_opts(raster.opts().clone()),
+            None => lines,
+        })
+    }
+
+    /// Parse and shape text returning advances & offsets relative to the pen instead of laid out
+    /// positions. Only the glyph related options of `shape_ops` are used. Glyphs are in logical
+    /// order.
+    ///
+    /// See `ImtShaper::glyph_positions`.
+    pub fn glyph_positions<T: AsRef<str>>(
+        &self,
+        shape_ops: ImtShapeOpts,
+        text: T,
+    ) -> Result<Vec<ImtGlyphPosition>, ImtError> {
+        let (script, lang, parsed_glyphs) = self.parse_text(&shape_ops, text)?;
+
+        self.shaper.glyph_positions(
+            &self.parser,
+            script,
+            lang,
+            shape_ops.features,
+            parsed_glyphs,
+        )
+    }
+
+    /// Horizontal position in pixels of a caret placed before the byte at `byte_index` of a
+    /// single line of `text`. Only the glyph related options of `shape_ops` are used.
+    ///
+    /// The line is laid out in visual order like `shape_text`. The caret is placed at the
+    /// leading edge of the character at or after `byte_index`, which is its right edge in
+    /// right-to-left text. Within a ligature the glyph's advance is divided evenly between its
+    /// characters. A `byte_index` of `text.len()` or greater places the caret at the trailing
+    /// edge of the logically last character, which is the width of a left-to-right line.
+    pub fn caret_x<T: AsRef<str>>(
+        &self,
+        text: T,
+        text_height: f32,
+        shape_ops: ImtShapeOpts,
+        byte_index: usize,
+    ) -> Result<f32, ImtError> {
+        let text = text.as_ref();
+        let clusters = self.visual_clusters(text, text_height, shape_ops)?;
+
+        let cluster = match clusters
+            .iter()
+            .filter(|cluster| cluster.end > byte_index)
+            .min_by_key(|cluster| cluster.end)
+        {
+            Some(cluster) => cluster,
+            None => {
+                return Ok(match clusters.iter().max_by_key(|cluster| cluster.end) {
+                    Some(last) if last.rtl => last.x,
+                    Some(last) => last.x + last.advance,
+                    None => 0.0,
+                });
+            },
+        };
+
+        let cluster_text = &text[cluster.start..cluster.end];
+        let chars = cluster_text.chars().count().max(1);
+        let before = cluster_text
+            .char_indices()
+            .take_while(|(i, _)| cluster.start + i < byte_index)
+            .count();
+
+        let before_adv = cluster.advance * (before as f32 / chars as f32);
+
+        Ok(if cluster.rtl {
+            cluster.x + cluster.advance - before_adv
+        } else {
+            cluster.x + before_adv
+        })
+    }
+
+    /// Byte index of `text` nearest to `point`, in pixels relative to the start of the line, for
+    /// placing a caret. `text` is a single line so only `point.x` is considered. Only the glyph
+    /// related options of `shape_ops` are used.
+    ///
+    /// The line is laid out in visual order like `caret_x`. The caret goes before the character
+    /// whose horizontal midpoint `point.x` is left of, or after it in right-to-left text, with
+    /// the characters of a ligature sharing its advance. A point past the right edge of the line
+    /// gives the index next to its rightmost character, excluding a trailing line break. An
+    /// empty line gives `0`.
+    pub fn hit_test<T: AsRef<str>>(
+        &self,
+        text: T,
+        text_height: f32,
+        shape_ops: ImtShapeOpts,
+        point: ImtPoint,
+    ) -> Result<usize, ImtError> {
+        let text = text.as_ref();
+        let line_end = text.trim_end_matches(['\r', '\n']).len();
+        let mut right_index = line_end;
+
+        for cluster in self.visual_clusters(text, text_height, shape_ops)? {
+            if cluster.start >= line_end || cluster.start == cluster.end {
+                continue;
+            }
+
+            let mut chars: Vec<(usize, usize)> = text[cluster.start..cluster.end]
+                .char_indices()
+                .map(|(i, c)| (cluster.start + i, cluster.start + i + c.len_utf8()))
+                .collect();
+
+            if cluster.rtl {
+                chars.reverse();
+            }
+
+            let advance = cluster.advance / chars.len() as f32;
+            let mut x = cluster.x;
+
+            // The indices before & after a character are on its right & left in right-to-left
+            // text.
+            for (start, end) in chars {
+                let (left_index, next_right_index) = if cluster.rtl {
+                    (end, start)
+                } else {
+                    (start, end)
+                };
+
+                if point.x < x + (advance / 2.0) {
+                    return Ok(left_index);
+                }
+
+                right_index = next_right_index;
+                x += advance;
+            }
+        }
+
+        Ok(right_index)
+    }
+
+    /// Glyph clusters of a single line of `text` in visual order, see `glyph_clusters`.
+    fn visual_clusters(
+        &self,
+        text: &str,
+        text_height: f32,
+        shape_ops: ImtShapeOpts,
+    ) -> Result<Vec<VisualCluster>, ImtError> {
+        let runs = bidi_runs(text).unwrap_or_else(|| vec![(0..text.len(), false)]);
+        let font_props = self.parser.font_props()?;
+        let mut clusters = Vec::new();
+        let mut x = 0.0;
+
+        for (range, rtl) in runs {
+            let run_text = &text[range.clone()];
+            let positions = self.glyph_positions(shape_ops.clone(), run_text)?;
+            let mut run_clusters: Vec<_> = positions
+                .iter()
+                .zip(glyph_clusters(run_text, &positions))
+                .collect();
+
+            if rtl {
+                run_clusters.reverse();
+            }
+
+            for (position, (start, end)) in run_clusters {
+                let advance = font_props.font_units_to_px(position.x_advance, text_height);
+
+                clusters.push(VisualCluster {
+                    start: range.start + start,
+                    end: range.start + end,
+                    x,
+                    advance,
+                    rtl,
+                });
+
+                x += advance;
+            }
+        }
+
+        Ok(clusters)
+    }
+
+    /// Same as `parse_text`, but glyphs are in visual order for text mixing directions.
+    fn parse_text_visual<T: AsRef<str>>(
+        &self,
+        shape_ops: &ImtShapeOpts,
+        text: T,
+    ) -> Result<(ImtScript, ImtLang, Vec<Arc<ImtParsedGlyph>>), ImtError> {
+        let text = text.as_ref();
+
+        let runs = match bidi_runs(text) {
+            Some(runs) => runs,
+            None => return self.parse_text(shape_ops, text),
+        };
+
+        let mut parsed_glyphs = Vec::new();
+
+        for (range, rtl) in runs {
+            // Runs are parsed in logical order so substitutions such as ligatures still apply.
+            let (_, _, mut run_glyphs) = self.parse_text(shape_ops, &text[range])?;
+
+            if rtl {
+                run_glyphs.reverse();
+            }
+
+            parsed_glyphs.append(&mut run_glyphs);
+        }
+
+        Ok((ImtScript::Default, ImtLang::Default, parsed_glyphs))
+    }
+
+    fn parse_text<T: AsRef<str>>(
+        &self,
+        shape_ops: &ImtShapeOpts,
+        text: T,
+    ) -> Result<(ImtScript, ImtLang, Vec<Arc<ImtParsedGlyph>>), ImtError> {
+        // TODO: Auto detect script/lang or require params to specify?
+        let script = ImtScript::Default;
+        let lang = ImtLang::Default;
+        let mut features = Vec::with_capacity(shape_ops.features.len() + 2);
+
+        if !shape_ops.ligatures {
+            features.push((ImtFeatureTag::LIGA, false));
+            features.push((ImtFeatureTag::CLIG, false));
+        }
+
+        features.extend_from_slice(&shape_ops.features);
+
+        let parsed_glyphs = self.parser.retrieve_text_with_opts(
+            text,
+            script,
+            lang,
+            ImtTextOpts {
+                fake_bold: shape_ops.synthetic_bold,
+                fake_italic: shape_ops.synthetic_italic,
+                small_caps: shape_ops.small_caps,
+                features,
+            },
+        )?;
+
+        Ok((script, lang, parsed_glyphs))
+    }
+
+    pub fn glyphs_for_text<T: AsRef<str>>(
+        &self,
+        text_height: f32,
+        shape_ops: ImtShapeOpts,
+        text: T,
+    ) -> Result<Vec<ImtGlyph>, ImtError> {
+        let shaped_glyphs = self.shape_text(shape_ops, text)?;
+        self.glyphs_for_shaped(text_height, shaped_glyphs)
+    }
+
+    /// Raster shaped glyphs, whose positions are in this font's units, into `ImtGlyph`'s.
+    pub(crate) fn glyphs_for_shaped(
+        &self,
+        text_height: f32,
+        shaped_glyphs: Vec<ImtShapedGlyph>,
+    ) -> Result<Vec<ImtGlyph>, ImtError> {
+        let raster = self.raster.as_ref().ok_or(ImtError::src_and_ty(
+            ImtErrorSrc::Ilmenite,
+            ImtErrorTy::MissingRaster,
+        ))?;
+
         let rastered_glyphs =
-            self.raster
-                .raster_shaped_glyphs(&self.parser, text_height, shaped_glyphs)?;
-        let font_props = self.parser.font_props();
+            raster.raster_shaped_glyphs(&self.parser, text_height, shaped_glyphs)?;
+        let font_props = self.parser.font_props()?;
 
         Ok(rastered_glyphs
             .into_iter()
             .map(|g| {
                 let bitmap_metrics = g.bitmap.metrics();
+                let px_scaler = font_props.scaler * text_height;
+
+                let clip = if g.shaped.x_overflow != 0.0
+                    || g.shaped.y_overflow != 0.0
+                    || g.shaped.x_underflow != 0.0
+                    || g.shaped.y_underflow != 0.0
+                {
+                    Some([
+                        g.shaped.x_underflow * px_scaler,
+                        g.shaped.y_underflow * px_scaler,
+                        bitmap_metrics.width as f32 - (g.shaped.x_overflow * px_scaler),
+                        bitmap_metrics.height as f32 - (g.shaped.y_overflow * px_scaler),
+                    ])
+                } else {
+                    None
+                };
+
+                // The sub-pixel phase is baked into the bitmap.
+                let (pen_x, pen_y, _) = raster.opts().pen_position(
+                    g.shaped.position.x * px_scaler,
+                    g.shaped.position.y * px_scaler,
+                );
 
                 ImtGlyph {
-                    x: (g.shaped.position.x * font_props.scaler * text_height)
-                        + bitmap_metrics.bearing_x,
-                    y: (g.shaped.position.y * font_props.scaler * text_height)
-                        + bitmap_metrics.bearing_y,
+                    x: pen_x + bitmap_metrics.bearing_x,
+                    y: pen_y + bitmap_metrics.bearing_y,
                     w: bitmap_metrics.width,
                     h: bitmap_metrics.height,
                     crop_x: g.shaped.x_overflow * font_props.scaler * text_height,
                     crop_y: g.shaped.y_overflow * font_props.scaler * text_height,
+                    clip,
                     family: self.family.clone(),
                     weight: self.weight.clone(),
                     index: g.shaped.parsed.inner.glyph_index,
@@ -161,4 +818,254 @@ impl ImtFont {
             })
             .collect())
     }
+
+    /// Rasterize the glyphs of `chars` at each of `text_heights` ahead of time, so later calls to
+    /// `glyphs_for_text` with the same `shape_ops` are served from the cache.
+    ///
+    /// All sub-pixel phases are rasterized. The work for each text height is recorded into a
+    /// single submission and this returns once all of it has completed.
+    pub fn prewarm<T: AsRef<str>>(
+        &self,
+        text_heights: &[f32],
+        chars: T,
+        shape_ops: ImtShapeOpts,
+    ) -> Result<(), ImtError> {
+        let raster = self.raster.as_ref().ok_or(ImtError::src_and_ty(
+            ImtErrorSrc::Ilmenite,
+            ImtErrorTy::MissingRaster,
+        ))?;
+
+        let (_, _, parsed_glyphs) = self.parse_text(&shape_ops, chars)?;
+        let mut unique_glyphs: Vec<Arc<ImtParsedGlyph>> = Vec::new();
+
+        for parsed in parsed_glyphs {
+            if parsed.inner.unicodes.first() == Some(&'\n') {
+                continue;
+            }
+
+            if !unique_glyphs.iter().any(|unique| {
+                unique.inner.glyph_index == parsed.inner.glyph_index
+                    && unique.inner.fake_bold == parsed.inner.fake_bold
+                    && unique.inner.fake_italic == parsed.inner.fake_italic
+                    && unique.inner.small_caps == parsed.inner.small_caps
+            }) {
+                unique_glyphs.push(parsed);
+            }
+        }
+
+        let font_props = self.parser.font_props()?;
+        let phases = raster.opts().subpixel_phases.max(1);
+        let mut jobs = Vec::with_capacity(text_heights.len());
+
+        for &text_height in text_heights {
+            let px_scaler = font_props.scaler * text_height;
+            let mut shaped_glyphs = Vec::with_capacity(unique_glyphs.len() * phases as usize);
+
+            for parsed in unique_glyphs.iter() {
+                for phase in 0..phases {
+                    shaped_glyphs.push(ImtShapedGlyph {
+                        parsed: parsed.clone(),
+                        position: ImtPosition {
+                            x: (phase as f32 / phases as f32) / px_scaler,
+                            y: 0.0,
+                        },
+                        x_overflow: 0.0,
+                        y_overflow: 0.0,
+                        x_underflow: 0.0,
+                        y_underflow: 0.0,
+                        unicodes: Vec::new(),
+                    });
+                }
+            }
+
+            jobs.push(raster.raster_shaped_glyphs_deferred(
+                &self.parser,
+                text_height,
+                shaped_glyphs,
+            )?);
+        }
+
+        for job in jobs {
+            job.wait()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A glyph of a line placed in visual order, see `ImtFont::visual_clusters`.
+struct VisualCluster {
+    /// Byte range of the text the glyph was shaped from.
+    start: usize,
+    end: usize,
+    /// Left edge in pixels.
+    x: f32,
+    /// Advance in pixels.
+    advance: f32,
+    /// Whether the glyph is part of a right-to-left run.
+    rtl: bool,
+}
+
+/// Byte range of `text` each glyph of `positions` was shaped from as `(start, end)`.
+///
+/// Ligatures span all of their characters, while the additional glyphs of a multiple
+/// substitution share the range of the glyph they were substituted with.
+fn glyph_clusters(text: &str, positions: &[ImtGlyphPosition]) -> Vec<(usize, usize)> {
+    // Carriage returns are removed before shaping.
+    let mut chars = text.char_indices().filter(|(_, c)| *c != '\r').peekable();
+    let mut clusters: Vec<(usize, usize)> = Vec::with_capacity(positions.len());
+
+    for position in positions {
+        if position.unicodes.is_empty() {
+            if let Some(&cluster) = clusters.last() {
+                clusters.push(cluster);
+                continue;
+            }
+        }
+
+        let start = chars.peek().map(|(i, _)| *i).unwrap_or(text.len());
+        let mut end = start;
+
+        for _ in 0..position.unicodes.len() {
+            match chars.next() {
+                Some((i, c)) => end = i + c.len_utf8(),
+                None => break,
+            }
+        }
+
+        // Variation selectors don't produce glyphs of their own.
+        while let Some((i, c)) = chars.next_if(|(_, c)| is_variation_selector(*c)) {
+            end = i + c.len_utf8();
+        }
+
+        clusters.push((start, end));
+    }
+
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_font::TestFont;
+
+    /// Letters of `TestFont` are 5px wide & its space is 2.5px wide at a text height of 10px.
+    fn test_font() -> ImtFont {
+        ImtFont::from_bytes_outline("Test", ImtWeight::Normal, TestFont::new().build()).unwrap()
+    }
+
+    fn assert_carets(font: &ImtFont, text: &str, carets: &[(usize, f32)]) {
+        for &(byte_index, x) in carets {
+            let caret_x = font
+                .caret_x(text, 10.0, ImtShapeOpts::default(), byte_index)
+                .unwrap();
+
+            assert!(
+                (caret_x - x).abs() < 1e-3,
+                "caret_x({:?}, {}) = {}, expected {}",
+                text,
+                byte_index,
+                caret_x,
+                x
+            );
+        }
+    }
+
+    #[test]
+    fn caret_x_ltr() {
+        let font = test_font();
+        assert_carets(&font, "", &[(0, 0.0)]);
+        assert_carets(
+            &font,
+            "ab c",
+            &[(0, 0.0), (1, 5.0), (3, 12.5), (4, 17.5), (9, 17.5)],
+        );
+    }
+
+    #[test]
+    fn caret_x_rtl() {
+        let font = test_font();
+
+        // Alef, bet & gimel are displayed right to left, so the caret before alef is at the
+        // right edge of the line and the caret after gimel is at its left edge.
+        assert_carets(
+            &font,
+            "\u{5D0}\u{5D1}\u{5D2}",
+            &[(0, 15.0), (2, 10.0), (4, 5.0), (6, 0.0)],
+        );
+
+        // The right-to-left run follows "ab " visually, with alef on its right.
+        assert_carets(
+            &font,
+            "ab \u{5D0}\u{5D1}",
+            &[(2, 10.0), (3, 22.5), (5, 17.5), (7, 12.5)],
+        );
+    }
+
+    fn assert_hits(font: &ImtFont, text: &str, hits: &[(f32, usize)]) {
+        for &(x, byte_index) in hits {
+            let hit = font
+                .hit_test(
+                    text,
+                    10.0,
+                    ImtShapeOpts::default(),
+                    ImtPoint {
+                        x,
+                        y: 0.0,
+                    },
+                )
+                .unwrap();
+
+            assert_eq!(hit, byte_index, "hit_test({:?}, {})", text, x);
+        }
+    }
+
+    #[test]
+    fn hit_test_ltr() {
+        let font = test_font();
+        assert_hits(&font, "", &[(-5.0, 0), (0.0, 0), (100.0, 0)]);
+        assert_hits(
+            &font,
+            "ab",
+            &[(-5.0, 0), (2.0, 0), (3.0, 1), (8.0, 2), (100.0, 2)],
+        );
+
+        // A trailing line break can't be hit.
+        assert_hits(&font, "ab\n", &[(100.0, 2)]);
+    }
+
+    #[test]
+    fn hit_test_rtl() {
+        let font = test_font();
+
+        // Gimel, bet & alef from left to right, the left half of a character is after it.
+        assert_hits(
+            &font,
+            "\u{5D0}\u{5D1}\u{5D2}",
+            &[
+                (-5.0, 6),
+                (1.0, 6),
+                (4.0, 4),
+                (6.0, 4),
+                (9.0, 2),
+                (14.0, 0),
+                (100.0, 0),
+            ],
+        );
+
+        // Bet & alef follow "ab " visually.
+        assert_hits(
+            &font,
+            "ab \u{5D0}\u{5D1}",
+            &[
+                (-5.0, 0),
+                (11.0, 2),
+                (14.0, 7),
+                (16.0, 5),
+                (19.0, 5),
+                (21.0, 3),
+                (100.0, 3),
+            ],
+        );
+    }
 }
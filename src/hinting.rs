@@ -0,0 +1,1868 @@
+//! Interpreter of the TrueType instruction set used to grid-fit glyph outlines.
+//!
+//! Coordinates are 26.6 fixed point pixels while the vectors are kept as floats. Anything the
+//! interpreter doesn't understand aborts the program, leaving the glyph unhinted.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+pub(crate) const TAG_FPGM: u32 = u32::from_be_bytes(*b"fpgm");
+pub(crate) const TAG_PREP: u32 = u32::from_be_bytes(*b"prep");
+pub(crate) const TAG_CVT: u32 = u32::from_be_bytes(*b"cvt ");
+
+/// Instructions executed by a single program before it is considered stuck.
+const MAX_INSTRUCTIONS: usize = 1_000_000;
+/// Maximum depth of nested function calls.
+const MAX_CALL_DEPTH: usize = 64;
+/// Maximum size of the stack, storage, cvt & twilight zone.
+const MAX_ELEMENTS: usize = u16::MAX as usize;
+
+const TOUCH_X: u8 = 1;
+const TOUCH_Y: u8 = 2;
+
+/// The program being executed is malformed or uses something unsupported.
+#[derive(Debug)]
+pub(crate) struct HintError;
+
+type HintResult<T> = Result<T, HintError>;
+
+/// Functions & instruction definitions of the font program.
+type Definitions = (HashMap<i32, Rc<[u8]>>, HashMap<u8, Rc<[u8]>>);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Vector {
+    x: f32,
+    y: f32,
+}
+
+impl Vector {
+    const X_AXIS: Vector = Vector {
+        x: 1.0,
+        y: 0.0,
+    };
+    const Y_AXIS: Vector = Vector {
+        x: 0.0,
+        y: 1.0,
+    };
+
+    fn axis(x: bool) -> Self {
+        if x {
+            Self::X_AXIS
+        } else {
+            Self::Y_AXIS
+        }
+    }
+
+    fn normalized(x: f32, y: f32) -> Self {
+        let len = (x * x + y * y).sqrt();
+
+        if len == 0.0 {
+            Self::X_AXIS
+        } else {
+            Vector {
+                x: x / len,
+                y: y / len,
+            }
+        }
+    }
+
+    fn dot(&self, other: &Vector) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
+    fn project(&self, x: i32, y: i32) -> i32 {
+        (x as f32 * self.x + y as f32 * self.y).round() as i32
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Round {
+    Grid,
+    HalfGrid,
+    DoubleGrid,
+    DownToGrid,
+    UpToGrid,
+    Off,
+    Super {
+        period: i32,
+        phase: i32,
+        threshold: i32,
+    },
+}
+
+impl Round {
+    /// Values come from the font's instructions, so the arithmetic wraps rather than
+    /// overflowing.
+    fn apply(&self, d: i32) -> i32 {
+        let sign = if d < 0 { -1 } else { 1 };
+        let abs = d.wrapping_abs();
+
+        let rounded = match *self {
+            Round::Grid => abs.wrapping_add(32) & !63,
+            Round::HalfGrid => (abs & !63).wrapping_add(32),
+            Round::DoubleGrid => abs.wrapping_add(16) & !31,
+            Round::DownToGrid => abs & !63,
+            Round::UpToGrid => abs.wrapping_add(63) & !63,
+            Round::Off => abs,
+            Round::Super {
+                period,
+                phase,
+                threshold,
+            } => {
+                let val = abs.wrapping_sub(phase).wrapping_add(threshold);
+
+                if val < 0 {
+                    phase
+                } else {
+                    (val / period).wrapping_mul(period).wrapping_add(phase)
+                }
+            },
+        };
+
+        rounded.wrapping_mul(sign)
+    }
+
+    fn super_round(selector: i32, grid_period: i32) -> Self {
+        let period = match (selector >> 6) & 3 {
+            0 => grid_period / 2,
+            1 => grid_period,
+            _ => grid_period * 2,
+        };
+
+        let phase = match (selector >> 4) & 3 {
+            0 => 0,
+            1 => period / 4,
+            2 => period / 2,
+            _ => period * 3 / 4,
+        };
+
+        let threshold = match selector & 15 {
+            0 => period - 1,
+            t => (t - 4) * period / 8,
+        };
+
+        Round::Super {
+            period: period.max(1),
+            phase,
+            threshold,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct GraphicsState {
+    auto_flip: bool,
+    cvt_cutin: i32,
+    delta_base: i32,
+    delta_shift: i32,
+    dual_vector: Vector,
+    freedom_vector: Vector,
+    projection_vector: Vector,
+    instruct_control: i32,
+    loop_count: i32,
+    min_distance: i32,
+    round: Round,
+    rp0: usize,
+    rp1: usize,
+    rp2: usize,
+    single_width_cutin: i32,
+    single_width: i32,
+    zp0: usize,
+    zp1: usize,
+    zp2: usize,
+}
+
+impl Default for GraphicsState {
+    fn default() -> Self {
+        GraphicsState {
+            auto_flip: true,
+            cvt_cutin: 68,
+            delta_base: 9,
+            delta_shift: 3,
+            dual_vector: Vector::X_AXIS,
+            freedom_vector: Vector::X_AXIS,
+            projection_vector: Vector::X_AXIS,
+            instruct_control: 0,
+            loop_count: 1,
+            min_distance: 64,
+            round: Round::Grid,
+            rp0: 0,
+            rp1: 0,
+            rp2: 0,
+            single_width_cutin: 0,
+            single_width: 0,
+            zp0: 1,
+            zp1: 1,
+            zp2: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Zone {
+    org: Vec<Point>,
+    cur: Vec<Point>,
+    touched: Vec<u8>,
+    on_curve: Vec<bool>,
+    ends: Vec<usize>,
+}
+
+impl Zone {
+    fn with_len(len: usize) -> Self {
+        Zone {
+            org: vec![Point::default(); len],
+            cur: vec![Point::default(); len],
+            touched: vec![0; len],
+            on_curve: vec![true; len],
+            ends: Vec::new(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.cur.len()
+    }
+}
+
+/// State of the font after the control value program has run at a particular size.
+struct SizedState {
+    text_height: u32,
+    usable: bool,
+    cvt: Vec<i32>,
+    storage: Vec<i32>,
+    gs: GraphicsState,
+    twilight: Zone,
+    functions: HashMap<i32, Rc<[u8]>>,
+    idefs: HashMap<u8, Rc<[u8]>>,
+}
+
+/// Runs the font & control value programs and grid-fits glyphs with their instructions.
+pub(crate) struct Hinter {
+    upem: f32,
+    fpgm: Vec<u8>,
+    prep: Vec<u8>,
+    cvt: Vec<i16>,
+    functions: Option<Definitions>,
+    fpgm_failed: bool,
+    sized: Option<SizedState>,
+}
+
+/// A hinted point in font units.
+pub(crate) struct HintedPoint {
+    pub x: f32,
+    pub y: f32,
+    pub on_curve: bool,
+}
+
+impl Hinter {
+    pub(crate) fn new(units_per_em: u16, fpgm: Vec<u8>, prep: Vec<u8>, cvt: &[u8]) -> Self {
+        Hinter {
+            upem: units_per_em.max(1) as f32,
+            fpgm,
+            prep,
+            cvt: cvt
+                .chunks_exact(2)
+                .map(|b| i16::from_be_bytes([b[0], b[1]]))
+                .collect(),
+            functions: None,
+            fpgm_failed: false,
+            sized: None,
+        }
+    }
+
+    /// Run the font program once, returning false if it failed.
+    fn prepare_functions(&mut self) -> bool {
+        if self.fpgm_failed {
+            return false;
+        }
+
+        if self.functions.is_none() {
+            let mut cvt = Vec::new();
+            let mut storage = Vec::new();
+            let mut functions = HashMap::new();
+            let mut idefs = HashMap::new();
+
+            let result = Exec::new(
+                GraphicsState::default(),
+                Zone::default(),
+                Zone::default(),
+                &mut cvt,
+                &mut storage,
+                &mut functions,
+                &mut idefs,
+                0,
+                0.0,
+            )
+            .run(&self.fpgm);
+
+            if result.is_err() {
+                self.fpgm_failed = true;
+                return false;
+            }
+
+            self.functions = Some((functions, idefs));
+        }
+
+        true
+    }
+
+    /// Run the control value program for `text_height` unless it is the last size prepared.
+    fn prepare_size(&mut self, text_height: f32) -> bool {
+        if !self.prepare_functions() {
+            return false;
+        }
+
+        if let Some(sized) = self.sized.as_ref() {
+            if sized.text_height == text_height.to_bits() {
+                return sized.usable;
+            }
+        }
+
+        let scale = text_height * 64.0 / self.upem;
+        let ppem = text_height.round() as i32;
+        let (mut functions, mut idefs) = self.functions.clone().unwrap();
+        let mut cvt: Vec<i32> = self
+            .cvt
+            .iter()
+            .map(|v| (*v as f32 * scale).round() as i32)
+            .collect();
+        let mut storage = Vec::new();
+
+        let mut exec = Exec::new(
+            GraphicsState::default(),
+            Zone::default(),
+            Zone::default(),
+            &mut cvt,
+            &mut storage,
+            &mut functions,
+            &mut idefs,
+            ppem,
+            scale,
+        );
+
+        exec.in_prep = true;
+        let completed = exec.run(&self.prep).is_ok();
+        let mut gs = exec.gs.clone();
+        let twilight = exec.zones[0].clone();
+
+        // The glyph programs start with the state the control value program left behind, except
+        // for the state that only lasts for a single instruction or program.
+        if gs.instruct_control & 2 != 0 {
+            gs = GraphicsState {
+                instruct_control: gs.instruct_control,
+                ..GraphicsState::default()
+            };
+        } else {
+            gs.loop_count = 1;
+            gs.rp0 = 0;
+            gs.rp1 = 0;
+            gs.rp2 = 0;
+            gs.zp0 = 1;
+            gs.zp1 = 1;
+            gs.zp2 = 1;
+        }
+
+        self.sized = Some(SizedState {
+            text_height: text_height.to_bits(),
+            usable: completed && gs.instruct_control & 1 == 0,
+            cvt,
+            storage,
+            gs,
+            twilight,
+            functions,
+            idefs,
+        });
+
+        self.sized.as_ref().unwrap().usable
+    }
+
+    /// Grid-fit the points of a simple glyph at `text_height` with its `instructions`.
+    ///
+    /// Returns `None` when the glyph has no instructions or they couldn't be executed.
+    pub(crate) fn hint(
+        &mut self,
+        text_height: f32,
+        points: &[(f32, f32)],
+        on_curve: &[bool],
+        end_pts: &[u16],
+        instructions: &[u8],
+        hori_adv: f32,
+    ) -> Option<Vec<HintedPoint>> {
+        if instructions.is_empty() || points.is_empty() || !self.prepare_size(text_height) {
+            return None;
+        }
+
+        let sized = self.sized.as_ref().unwrap();
+        let scale = text_height * 64.0 / self.upem;
+        let ppem = text_height.round() as i32;
+        let mut glyph = Zone::with_len(points.len() + 4);
+
+        for (i, (x, y)) in points.iter().enumerate() {
+            glyph.org[i] = Point {
+                x: (x * scale).round() as i32,
+                y: (y * scale).round() as i32,
+            };
+            glyph.on_curve[i] = on_curve.get(i).copied().unwrap_or(true);
+        }
+
+        // Phantom points: the origin & advance followed by the vertical equivalents.
+        let adv = (hori_adv * scale).round() as i32;
+        let top = (self.upem * scale).round() as i32;
+        glyph.org[points.len() + 1].x = (adv + 32) & !63;
+        glyph.org[points.len() + 2].y = top;
+        glyph.cur = glyph.org.clone();
+        glyph.ends = end_pts.iter().map(|end| *end as usize).collect();
+
+        if glyph.ends.iter().any(|end| *end >= points.len()) {
+            return None;
+        }
+
+        let mut storage = sized.storage.clone();
+        let mut cvt = sized.cvt.clone();
+        let mut functions = sized.functions.clone();
+        let mut idefs = sized.idefs.clone();
+
+        let mut exec = Exec::new(
+            sized.gs.clone(),
+            sized.twilight.clone(),
+            glyph,
+            &mut cvt,
+            &mut storage,
+            &mut functions,
+            &mut idefs,
+            ppem,
+            scale,
+        );
+
+        exec.run(instructions).ok()?;
+        let glyph = &exec.zones[1];
+        let origin_x = glyph.cur[points.len()].x;
+
+        Some(
+            (0..points.len())
+                .map(|i| {
+                    HintedPoint {
+                        x: glyph.cur[i].x.wrapping_sub(origin_x) as f32 / scale,
+                        y: glyph.cur[i].y as f32 / scale,
+                        on_curve: glyph.on_curve[i],
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+struct Exec<'a> {
+    gs: GraphicsState,
+    stack: Vec<i32>,
+    zones: [Zone; 2],
+    cvt: &'a mut Vec<i32>,
+    storage: &'a mut Vec<i32>,
+    functions: &'a mut HashMap<i32, Rc<[u8]>>,
+    idefs: &'a mut HashMap<u8, Rc<[u8]>>,
+    ppem: i32,
+    scale: f32,
+    in_prep: bool,
+    executed: usize,
+    depth: usize,
+}
+
+impl<'a> Exec<'a> {
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        gs: GraphicsState,
+        twilight: Zone,
+        glyph: Zone,
+        cvt: &'a mut Vec<i32>,
+        storage: &'a mut Vec<i32>,
+        functions: &'a mut HashMap<i32, Rc<[u8]>>,
+        idefs: &'a mut HashMap<u8, Rc<[u8]>>,
+        ppem: i32,
+        scale: f32,
+    ) -> Self {
+        Exec {
+            gs,
+            stack: Vec::new(),
+            zones: [twilight, glyph],
+            cvt,
+            storage,
+            functions,
+            idefs,
+            ppem,
+            scale,
+            in_prep: false,
+            executed: 0,
+            depth: 0,
+        }
+    }
+
+    fn pop(&mut self) -> HintResult<i32> {
+        self.stack.pop().ok_or(HintError)
+    }
+
+    fn push(&mut self, value: i32) -> HintResult<()> {
+        if self.stack.len() >= MAX_ELEMENTS {
+            return Err(HintError);
+        }
+
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop_bool(&mut self) -> HintResult<bool> {
+        Ok(self.pop()? != 0)
+    }
+
+    fn pop_zone(&mut self) -> HintResult<usize> {
+        match self.pop()? {
+            0 => Ok(0),
+            1 => Ok(1),
+            _ => Err(HintError),
+        }
+    }
+
+    /// Validate a point index of `zone`. The twilight zone grows to fit as its size isn't known.
+    fn point(&mut self, zone: usize, index: i32) -> HintResult<usize> {
+        if index < 0 {
+            return Err(HintError);
+        }
+
+        let index = index as usize;
+
+        if index >= self.zones[zone].len() {
+            if zone != 0 || index >= MAX_ELEMENTS {
+                return Err(HintError);
+            }
+
+            let len = index + 1;
+            let twilight = &mut self.zones[0];
+            twilight.org.resize(len, Point::default());
+            twilight.cur.resize(len, Point::default());
+            twilight.touched.resize(len, 0);
+            twilight.on_curve.resize(len, true);
+        }
+
+        Ok(index)
+    }
+
+    fn pop_point(&mut self, zone: usize) -> HintResult<usize> {
+        let index = self.pop()?;
+        self.point(zone, index)
+    }
+
+    fn cur(&self, zone: usize, index: usize) -> Point {
+        self.zones[zone].cur[index]
+    }
+
+    fn org(&self, zone: usize, index: usize) -> Point {
+        self.zones[zone].org[index]
+    }
+
+    fn project(&self, a: Point, b: Point) -> i32 {
+        self.gs
+            .projection_vector
+            .project(a.x.wrapping_sub(b.x), a.y.wrapping_sub(b.y))
+    }
+
+    fn dual_project(&self, a: Point, b: Point) -> i32 {
+        self.gs
+            .dual_vector
+            .project(a.x.wrapping_sub(b.x), a.y.wrapping_sub(b.y))
+    }
+
+    fn touch_flags(&self) -> u8 {
+        let mut flags = 0;
+
+        if self.gs.freedom_vector.x != 0.0 {
+            flags |= TOUCH_X;
+        }
+
+        if self.gs.freedom_vector.y != 0.0 {
+            flags |= TOUCH_Y;
+        }
+
+        flags
+    }
+
+    /// Move a point along the freedom vector so its projection changes by `distance`.
+    fn move_point(&mut self, zone: usize, index: usize, distance: i32, touch: bool) {
+        let fv = self.gs.freedom_vector;
+        let mut dot = fv.dot(&self.gs.projection_vector);
+
+        if dot.abs() < 1.0 / 16384.0 {
+            dot = 1.0;
+        }
+
+        let dx = (distance as f32 * fv.x / dot).round() as i32;
+        let dy = (distance as f32 * fv.y / dot).round() as i32;
+        self.shift_point(zone, index, dx, dy, touch);
+    }
+
+    fn shift_point(&mut self, zone: usize, index: usize, dx: i32, dy: i32, touch: bool) {
+        let flags = self.touch_flags();
+        let zone = &mut self.zones[zone];
+        zone.cur[index].x = zone.cur[index].x.wrapping_add(dx);
+        zone.cur[index].y = zone.cur[index].y.wrapping_add(dy);
+
+        if touch {
+            zone.touched[index] |= flags;
+        }
+    }
+
+    /// Displacement of the reference point used by `SHP`, `SHC` & `SHZ`.
+    fn reference_displacement(&mut self, use_rp1: bool) -> HintResult<(usize, usize, i32, i32)> {
+        let (zone, rp) = if use_rp1 {
+            (self.gs.zp0, self.gs.rp1)
+        } else {
+            (self.gs.zp1, self.gs.rp2)
+        };
+
+        let rp = self.point(zone, rp as i32)?;
+        let d = self
+            .project(self.cur(zone, rp), Point::default())
+            .wrapping_sub(self.dual_project(self.org(zone, rp), Point::default()));
+        let fv = self.gs.freedom_vector;
+        let mut dot = fv.dot(&self.gs.projection_vector);
+
+        if dot.abs() < 1.0 / 16384.0 {
+            dot = 1.0;
+        }
+
+        Ok((
+            zone,
+            rp,
+            (d as f32 * fv.x / dot).round() as i32,
+            (d as f32 * fv.y / dot).round() as i32,
+        ))
+    }
+
+    fn cvt(&self, index: i32) -> i32 {
+        if index < 0 {
+            return 0;
+        }
+
+        self.cvt.get(index as usize).copied().unwrap_or(0)
+    }
+
+    fn set_cvt(&mut self, index: i32, value: i32) -> HintResult<()> {
+        if index < 0 || index as usize >= MAX_ELEMENTS {
+            return Err(HintError);
+        }
+
+        if index as usize >= self.cvt.len() {
+            self.cvt.resize(index as usize + 1, 0);
+        }
+
+        self.cvt[index as usize] = value;
+        Ok(())
+    }
+
+    /// Vectors along the line between two points of the current & original outline.
+    fn vector_from_line(&mut self, perpendicular: bool) -> HintResult<(Vector, Vector)> {
+        let p2 = self.pop()?;
+        let p1 = self.pop()?;
+        let (zp1, zp2) = (self.gs.zp1, self.gs.zp2);
+        let p2 = self.point(zp2, p2)?;
+        let p1 = self.point(zp1, p1)?;
+
+        let from_points = |a: Point, b: Point| {
+            let (mut x, mut y) = (a.x as f32 - b.x as f32, a.y as f32 - b.y as f32);
+
+            if perpendicular {
+                let t = x;
+                x = -y;
+                y = t;
+            }
+
+            Vector::normalized(x, y)
+        };
+
+        Ok((
+            from_points(self.cur(zp1, p1), self.cur(zp2, p2)),
+            from_points(self.org(zp1, p1), self.org(zp2, p2)),
+        ))
+    }
+
+    fn set_vector(&mut self, vector: Vector, projection: bool) {
+        if projection {
+            self.gs.projection_vector = vector;
+            self.gs.dual_vector = vector;
+        } else {
+            self.gs.freedom_vector = vector;
+        }
+    }
+
+    fn loop_count(&mut self) -> i32 {
+        let count = self.gs.loop_count.max(1);
+        self.gs.loop_count = 1;
+        count
+    }
+
+    /// Length of the instruction at `pc` including its inline data.
+    fn instruction_len(code: &[u8], pc: usize) -> HintResult<usize> {
+        let op = code[pc];
+
+        let len = match op {
+            0x40 => 2 + *code.get(pc + 1).ok_or(HintError)? as usize,
+            0x41 => 2 + *code.get(pc + 1).ok_or(HintError)? as usize * 2,
+            0xB0..=0xB7 => 2 + (op - 0xB0) as usize,
+            0xB8..=0xBF => 1 + (op - 0xB8 + 1) as usize * 2,
+            _ => 1,
+        };
+
+        if pc + len > code.len() {
+            return Err(HintError);
+        }
+
+        Ok(len)
+    }
+
+    /// Find the instruction ending the block that starts at `pc`, returning its position.
+    fn skip_block(code: &[u8], mut pc: usize, stop_at_else: bool) -> HintResult<usize> {
+        let mut nesting = 0;
+
+        while pc < code.len() {
+            match code[pc] {
+                0x58 => nesting += 1,
+                0x1B if nesting == 0 && stop_at_else => return Ok(pc),
+                0x59 => {
+                    if nesting == 0 {
+                        return Ok(pc);
+                    }
+
+                    nesting -= 1;
+                },
+                _ => (),
+            }
+
+            pc += Self::instruction_len(code, pc)?;
+        }
+
+        Err(HintError)
+    }
+
+    fn find_endf(code: &[u8], mut pc: usize) -> HintResult<usize> {
+        while pc < code.len() {
+            match code[pc] {
+                0x2D => return Ok(pc),
+                0x2C | 0x89 => return Err(HintError),
+                _ => pc += Self::instruction_len(code, pc)?,
+            }
+        }
+
+        Err(HintError)
+    }
+
+    fn call(&mut self, body: Rc<[u8]>) -> HintResult<()> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(HintError);
+        }
+
+        self.depth += 1;
+        let result = self.run(&body);
+        self.depth -= 1;
+        result
+    }
+
+    fn run(&mut self, code: &[u8]) -> HintResult<()> {
+        let mut pc = 0;
+
+        while pc < code.len() {
+            self.executed += 1;
+
+            if self.executed > MAX_INSTRUCTIONS {
+                return Err(HintError);
+            }
+
+            let op = code[pc];
+            let len = Self::instruction_len(code, pc)?;
+            let mut next = pc + len;
+
+            match op {
+                // SVTCA, SPVTCA & SFVTCA
+                0x00..=0x05 => {
+                    let vector = Vector::axis(op & 1 == 1);
+
+                    if op <= 0x03 {
+                        self.set_vector(vector, true);
+                    }
+
+                    if op <= 0x01 || op >= 0x04 {
+                        self.set_vector(vector, false);
+                    }
+                },
+                // SPVTL & SFVTL
+                0x06..=0x09 => {
+                    let (vector, _) = self.vector_from_line(op & 1 == 1)?;
+                    self.set_vector(vector, op <= 0x07);
+                },
+                // SPVFS & SFVFS
+                0x0A | 0x0B => {
+                    let y = self.pop()? as i16 as f32;
+                    let x = self.pop()? as i16 as f32;
+                    self.set_vector(Vector::normalized(x, y), op == 0x0A);
+                },
+                // GPV & GFV
+                0x0C | 0x0D => {
+                    let vector = if op == 0x0C {
+                        self.gs.projection_vector
+                    } else {
+                        self.gs.freedom_vector
+                    };
+
+                    self.push((vector.x * 16384.0).round() as i32)?;
+                    self.push((vector.y * 16384.0).round() as i32)?;
+                },
+                // SFVTPV
+                0x0E => self.gs.freedom_vector = self.gs.projection_vector,
+                // ISECT
+                0x0F => {
+                    let (zp0, zp1, zp2) = (self.gs.zp0, self.gs.zp1, self.gs.zp2);
+                    let b1 = self.pop_point(zp0)?;
+                    let b0 = self.pop_point(zp0)?;
+                    let a1 = self.pop_point(zp1)?;
+                    let a0 = self.pop_point(zp1)?;
+                    let p = self.pop_point(zp2)?;
+                    let (a0, a1) = (self.cur(zp1, a0), self.cur(zp1, a1));
+                    let (b0, b1) = (self.cur(zp0, b0), self.cur(zp0, b1));
+                    let (dax, day) = (a1.x as f32 - a0.x as f32, a1.y as f32 - a0.y as f32);
+                    let (dbx, dby) = (b1.x as f32 - b0.x as f32, b1.y as f32 - b0.y as f32);
+                    let denom = dax * dby - day * dbx;
+
+                    let point = if denom.abs() < 1.0 {
+                        let mean = |a: i32, b: i32, c: i32, d: i32| {
+                            ((a as i64 + b as i64 + c as i64 + d as i64) / 4) as i32
+                        };
+
+                        Point {
+                            x: mean(a0.x, a1.x, b0.x, b1.x),
+                            y: mean(a0.y, a1.y, b0.y, b1.y),
+                        }
+                    } else {
+                        let t = ((b0.x as f32 - a0.x as f32) * dby
+                            - (b0.y as f32 - a0.y as f32) * dbx)
+                            / denom;
+
+                        Point {
+                            x: (a0.x as f32 + t * dax).round() as i32,
+                            y: (a0.y as f32 + t * day).round() as i32,
+                        }
+                    };
+
+                    self.zones[zp2].cur[p] = point;
+                    self.zones[zp2].touched[p] |= TOUCH_X | TOUCH_Y;
+                },
+                // SRP0, SRP1 & SRP2
+                0x10 => self.gs.rp0 = self.pop()?.max(0) as usize,
+                0x11 => self.gs.rp1 = self.pop()?.max(0) as usize,
+                0x12 => self.gs.rp2 = self.pop()?.max(0) as usize,
+                // SZP0, SZP1, SZP2 & SZPS
+                0x13 => self.gs.zp0 = self.pop_zone()?,
+                0x14 => self.gs.zp1 = self.pop_zone()?,
+                0x15 => self.gs.zp2 = self.pop_zone()?,
+                0x16 => {
+                    let zone = self.pop_zone()?;
+                    self.gs.zp0 = zone;
+                    self.gs.zp1 = zone;
+                    self.gs.zp2 = zone;
+                },
+                // SLOOP
+                0x17 => self.gs.loop_count = self.pop()?,
+                // RTG & RTHG
+                0x18 => self.gs.round = Round::Grid,
+                0x19 => self.gs.round = Round::HalfGrid,
+                // SMD
+                0x1A => self.gs.min_distance = self.pop()?,
+                // ELSE, reached at the end of a taken IF
+                0x1B => next = Self::skip_block(code, pc + 1, false)? + 1,
+                // JMPR
+                0x1C => {
+                    let offset = self.pop()?;
+                    next = jump(pc, offset, code.len())?;
+                },
+                // SCVTCI, SSWCI & SSW
+                0x1D => self.gs.cvt_cutin = self.pop()?,
+                0x1E => self.gs.single_width_cutin = self.pop()?,
+                0x1F => {
+                    let value = self.pop()?;
+                    self.gs.single_width = (value as f32 * self.scale).round() as i32;
+                },
+                // DUP
+                0x20 => {
+                    let value = *self.stack.last().ok_or(HintError)?;
+                    self.push(value)?;
+                },
+                // POP
+                0x21 => {
+                    self.pop()?;
+                },
+                // CLEAR
+                0x22 => self.stack.clear(),
+                // SWAP
+                0x23 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(a)?;
+                },
+                // DEPTH
+                0x24 => self.push(self.stack.len() as i32)?,
+                // CINDEX & MINDEX
+                0x25 | 0x26 => {
+                    let k = self.pop()?;
+
+                    if k <= 0 || k as usize > self.stack.len() {
+                        return Err(HintError);
+                    }
+
+                    let i = self.stack.len() - k as usize;
+
+                    let value = if op == 0x25 {
+                        self.stack[i]
+                    } else {
+                        self.stack.remove(i)
+                    };
+
+                    self.push(value)?;
+                },
+                // ALIGNPTS
+                0x27 => {
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let p2 = self.pop_point(zp0)?;
+                    let p1 = self.pop_point(zp1)?;
+                    let distance = self.project(self.cur(zp0, p2), self.cur(zp1, p1)) / 2;
+                    self.move_point(zp1, p1, distance, true);
+                    self.move_point(zp0, p2, distance.wrapping_neg(), true);
+                },
+                // UTP
+                0x29 => {
+                    let zp0 = self.gs.zp0;
+                    let p = self.pop_point(zp0)?;
+                    let flags = self.touch_flags();
+                    self.zones[zp0].touched[p] &= !flags;
+                },
+                // LOOPCALL & CALL
+                0x2A | 0x2B => {
+                    let f = self.pop()?;
+                    let count = if op == 0x2A { self.pop()? } else { 1 };
+                    let body = self.functions.get(&f).cloned().ok_or(HintError)?;
+
+                    for _ in 0..count {
+                        self.call(body.clone())?;
+                    }
+                },
+                // FDEF
+                0x2C => {
+                    let f = self.pop()?;
+                    let end = Self::find_endf(code, pc + 1)?;
+                    self.functions.insert(f, code[(pc + 1)..end].into());
+                    next = end + 1;
+                },
+                // ENDF
+                0x2D => return Ok(()),
+                // MDAP
+                0x2E | 0x2F => {
+                    let zp0 = self.gs.zp0;
+                    let p = self.pop_point(zp0)?;
+
+                    let distance = if op == 0x2F {
+                        let d = self.project(self.cur(zp0, p), Point::default());
+                        self.gs.round.apply(d).wrapping_sub(d)
+                    } else {
+                        0
+                    };
+
+                    self.move_point(zp0, p, distance, true);
+                    self.gs.rp0 = p;
+                    self.gs.rp1 = p;
+                },
+                // IUP
+                0x30 | 0x31 => self.interpolate_untouched(op == 0x31),
+                // SHP
+                0x32 | 0x33 => {
+                    let (ref_zone, rp, dx, dy) = self.reference_displacement(op == 0x33)?;
+                    let zp2 = self.gs.zp2;
+
+                    for _ in 0..self.loop_count() {
+                        let p = self.pop_point(zp2)?;
+
+                        if zp2 != ref_zone || p != rp {
+                            self.shift_point(zp2, p, dx, dy, true);
+                        }
+                    }
+                },
+                // SHC
+                0x34 | 0x35 => {
+                    let (ref_zone, rp, dx, dy) = self.reference_displacement(op == 0x35)?;
+                    let zp2 = self.gs.zp2;
+                    let contour = self.pop()?;
+
+                    if contour < 0 || contour as usize >= self.zones[zp2].ends.len() {
+                        return Err(HintError);
+                    }
+
+                    let end = self.zones[zp2].ends[contour as usize];
+                    let start = match contour {
+                        0 => 0,
+                        _ => self.zones[zp2].ends[contour as usize - 1] + 1,
+                    };
+
+                    for p in start..=end {
+                        if zp2 != ref_zone || p != rp {
+                            self.shift_point(zp2, p, dx, dy, true);
+                        }
+                    }
+                },
+                // SHZ
+                0x36 | 0x37 => {
+                    let (ref_zone, rp, dx, dy) = self.reference_displacement(op == 0x37)?;
+                    let zone = self.pop_zone()?;
+
+                    for p in 0..self.zones[zone].len() {
+                        if zone != ref_zone || p != rp {
+                            self.shift_point(zone, p, dx, dy, false);
+                        }
+                    }
+                },
+                // SHPIX
+                0x38 => {
+                    let amount = self.pop()? as f32;
+                    let fv = self.gs.freedom_vector;
+                    let (dx, dy) = (
+                        (amount * fv.x).round() as i32,
+                        (amount * fv.y).round() as i32,
+                    );
+                    let zp2 = self.gs.zp2;
+
+                    for _ in 0..self.loop_count() {
+                        let p = self.pop_point(zp2)?;
+                        self.shift_point(zp2, p, dx, dy, true);
+                    }
+                },
+                // IP
+                0x39 => {
+                    let (zp0, zp1, zp2) = (self.gs.zp0, self.gs.zp1, self.gs.zp2);
+                    let rp1 = self.point(zp0, self.gs.rp1 as i32)?;
+                    let rp2 = self.point(zp1, self.gs.rp2 as i32)?;
+                    let org_range = self.dual_project(self.org(zp1, rp2), self.org(zp0, rp1));
+                    let cur_range = self.project(self.cur(zp1, rp2), self.cur(zp0, rp1));
+
+                    for _ in 0..self.loop_count() {
+                        let p = self.pop_point(zp2)?;
+                        let org_dist = self.dual_project(self.org(zp2, p), self.org(zp0, rp1));
+                        let cur_dist = self.project(self.cur(zp2, p), self.cur(zp0, rp1));
+
+                        let new_dist = if org_range != 0 {
+                            (org_dist as i64 * cur_range as i64 / org_range as i64) as i32
+                        } else {
+                            org_dist
+                        };
+
+                        self.move_point(zp2, p, new_dist.wrapping_sub(cur_dist), true);
+                    }
+                },
+                // MSIRP
+                0x3A | 0x3B => {
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let distance = self.pop()?;
+                    let p = self.pop_point(zp1)?;
+                    let rp0 = self.point(zp0, self.gs.rp0 as i32)?;
+
+                    if zp1 == 0 {
+                        self.zones[0].org[p] = self.org(zp0, rp0);
+                        self.zones[0].cur[p] = self.org(zp0, rp0);
+                    }
+
+                    let cur_dist = self.project(self.cur(zp1, p), self.cur(zp0, rp0));
+                    self.move_point(zp1, p, distance.wrapping_sub(cur_dist), true);
+                    self.gs.rp1 = rp0;
+                    self.gs.rp2 = p;
+
+                    if op == 0x3B {
+                        self.gs.rp0 = p;
+                    }
+                },
+                // ALIGNRP
+                0x3C => {
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let rp0 = self.point(zp0, self.gs.rp0 as i32)?;
+
+                    for _ in 0..self.loop_count() {
+                        let p = self.pop_point(zp1)?;
+                        let distance = self.project(self.cur(zp1, p), self.cur(zp0, rp0));
+                        self.move_point(zp1, p, distance.wrapping_neg(), true);
+                    }
+                },
+                // RTDG
+                0x3D => self.gs.round = Round::DoubleGrid,
+                // MIAP
+                0x3E | 0x3F => {
+                    let zp0 = self.gs.zp0;
+                    let index = self.pop()?;
+                    let p = self.pop_point(zp0)?;
+                    let mut distance = self.cvt(index);
+
+                    if zp0 == 0 {
+                        let fv = self.gs.freedom_vector;
+                        let point = Point {
+                            x: (distance as f32 * fv.x).round() as i32,
+                            y: (distance as f32 * fv.y).round() as i32,
+                        };
+
+                        self.zones[0].org[p] = point;
+                        self.zones[0].cur[p] = point;
+                    }
+
+                    let org_dist = self.project(self.cur(zp0, p), Point::default());
+
+                    if op == 0x3F {
+                        if distance.wrapping_sub(org_dist).wrapping_abs() > self.gs.cvt_cutin {
+                            distance = org_dist;
+                        }
+
+                        distance = self.gs.round.apply(distance);
+                    }
+
+                    self.move_point(zp0, p, distance.wrapping_sub(org_dist), true);
+                    self.gs.rp0 = p;
+                    self.gs.rp1 = p;
+                },
+                // NPUSHB & NPUSHW
+                0x40 => {
+                    for &b in &code[(pc + 2)..next] {
+                        self.push(b as i32)?;
+                    }
+                },
+                0x41 => {
+                    for w in code[(pc + 2)..next].chunks_exact(2) {
+                        self.push(i16::from_be_bytes([w[0], w[1]]) as i32)?;
+                    }
+                },
+                // WS & RS
+                0x42 => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+
+                    if index < 0 || index as usize >= MAX_ELEMENTS {
+                        return Err(HintError);
+                    }
+
+                    if index as usize >= self.storage.len() {
+                        self.storage.resize(index as usize + 1, 0);
+                    }
+
+                    self.storage[index as usize] = value;
+                },
+                0x43 => {
+                    let index = self.pop()?;
+
+                    let value = if index < 0 {
+                        0
+                    } else {
+                        self.storage.get(index as usize).copied().unwrap_or(0)
+                    };
+
+                    self.push(value)?;
+                },
+                // WCVTP & RCVT
+                0x44 => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    self.set_cvt(index, value)?;
+                },
+                0x45 => {
+                    let index = self.pop()?;
+                    self.push(self.cvt(index))?;
+                },
+                // GC
+                0x46 | 0x47 => {
+                    let zp2 = self.gs.zp2;
+                    let p = self.pop_point(zp2)?;
+
+                    let value = if op == 0x46 {
+                        self.project(self.cur(zp2, p), Point::default())
+                    } else {
+                        self.dual_project(self.org(zp2, p), Point::default())
+                    };
+
+                    self.push(value)?;
+                },
+                // SCFS
+                0x48 => {
+                    let zp2 = self.gs.zp2;
+                    let value = self.pop()?;
+                    let p = self.pop_point(zp2)?;
+                    let distance = self.project(self.cur(zp2, p), Point::default());
+                    self.move_point(zp2, p, value.wrapping_sub(distance), true);
+
+                    if zp2 == 0 {
+                        self.zones[0].org[p] = self.zones[0].cur[p];
+                    }
+                },
+                // MD
+                0x49 | 0x4A => {
+                    let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+                    let k = self.pop_point(zp1)?;
+                    let l = self.pop_point(zp0)?;
+
+                    let distance = if op == 0x49 {
+                        self.project(self.cur(zp0, l), self.cur(zp1, k))
+                    } else {
+                        self.dual_project(self.org(zp0, l), self.org(zp1, k))
+                    };
+
+                    self.push(distance)?;
+                },
+                // MPPEM & MPS
+                0x4B | 0x4C => self.push(self.ppem)?,
+                // FLIPON & FLIPOFF
+                0x4D => self.gs.auto_flip = true,
+                0x4E => self.gs.auto_flip = false,
+                // DEBUG
+                0x4F => {
+                    self.pop()?;
+                },
+                // LT, LTEQ, GT, GTEQ, EQ & NEQ
+                0x50..=0x55 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+
+                    let result = match op {
+                        0x50 => a < b,
+                        0x51 => a <= b,
+                        0x52 => a > b,
+                        0x53 => a >= b,
+                        0x54 => a == b,
+                        _ => a != b,
+                    };
+
+                    self.push(result as i32)?;
+                },
+                // ODD & EVEN
+                0x56 | 0x57 => {
+                    let value = self.pop()?;
+                    let odd = self.gs.round.apply(value) & 127 == 64;
+                    self.push((odd == (op == 0x56)) as i32)?;
+                },
+                // IF
+                0x58 => {
+                    if !self.pop_bool()? {
+                        next = Self::skip_block(code, pc + 1, true)? + 1;
+                    }
+                },
+                // EIF
+                0x59 => (),
+                // AND, OR & NOT
+                0x5A => {
+                    let b = self.pop_bool()?;
+                    let a = self.pop_bool()?;
+                    self.push((a && b) as i32)?;
+                },
+                0x5B => {
+                    let b = self.pop_bool()?;
+                    let a = self.pop_bool()?;
+                    self.push((a || b) as i32)?;
+                },
+                0x5C => {
+                    let a = self.pop_bool()?;
+                    self.push(!a as i32)?;
+                },
+                // DELTAP1, DELTAP2 & DELTAP3
+                0x5D | 0x71 | 0x72 => {
+                    let base = match op {
+                        0x5D => 0,
+                        0x71 => 16,
+                        _ => 32,
+                    };
+
+                    let zp0 = self.gs.zp0;
+
+                    for _ in 0..self.pop()? {
+                        let p = self.pop_point(zp0)?;
+                        let arg = self.pop()?;
+
+                        if let Some(shift) = self.delta_shift(arg, base) {
+                            self.move_point(zp0, p, shift, true);
+                        }
+                    }
+                },
+                // SDB & SDS
+                0x5E => self.gs.delta_base = self.pop()?,
+                0x5F => self.gs.delta_shift = self.pop()?.clamp(0, 6),
+                // ADD, SUB, DIV & MUL
+                0x60..=0x63 => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+
+                    let result = match op {
+                        0x60 => a.wrapping_add(b),
+                        0x61 => a.wrapping_sub(b),
+                        0x62 => {
+                            if b == 0 {
+                                return Err(HintError);
+                            }
+
+                            (a as i64 * 64 / b as i64) as i32
+                        },
+                        _ => (a as i64 * b as i64 / 64) as i32,
+                    };
+
+                    self.push(result)?;
+                },
+                // ABS, NEG, FLOOR & CEILING
+                0x64..=0x67 => {
+                    let a = self.pop()?;
+
+                    let result = match op {
+                        0x64 => a.wrapping_abs(),
+                        0x65 => a.wrapping_neg(),
+                        0x66 => a & !63,
+                        _ => a.wrapping_add(63) & !63,
+                    };
+
+                    self.push(result)?;
+                },
+                // ROUND & NROUND, without engine compensation
+                0x68..=0x6F => {
+                    let value = self.pop()?;
+
+                    let result = if op <= 0x6B {
+                        self.gs.round.apply(value)
+                    } else {
+                        value
+                    };
+
+                    self.push(result)?;
+                },
+                // WCVTF
+                0x70 => {
+                    let value = self.pop()?;
+                    let index = self.pop()?;
+                    self.set_cvt(index, (value as f32 * self.scale).round() as i32)?;
+                },
+                // DELTAC1, DELTAC2 & DELTAC3
+                0x73..=0x75 => {
+                    let base = (op - 0x73) as i32 * 16;
+
+                    for _ in 0..self.pop()? {
+                        let index = self.pop()?;
+                        let arg = self.pop()?;
+
+                        if let Some(shift) = self.delta_shift(arg, base) {
+                            let value = self.cvt(index);
+                            self.set_cvt(index, value.wrapping_add(shift))?;
+                        }
+                    }
+                },
+                // SROUND & S45ROUND
+                0x76 => {
+                    let selector = self.pop()?;
+                    self.gs.round = Round::super_round(selector, 64);
+                },
+                0x77 => {
+                    let selector = self.pop()?;
+                    self.gs.round = Round::super_round(selector, 45);
+                },
+                // JROT & JROF
+                0x78 | 0x79 => {
+                    let condition = self.pop_bool()?;
+                    let offset = self.pop()?;
+
+                    if condition == (op == 0x78) {
+                        next = jump(pc, offset, code.len())?;
+                    }
+                },
+                // ROFF, RUTG & RDTG
+                0x7A => self.gs.round = Round::Off,
+                0x7C => self.gs.round = Round::UpToGrid,
+                0x7D => self.gs.round = Round::DownToGrid,
+                // SANGW & AA, obsolete
+                0x7E | 0x7F => {
+                    self.pop()?;
+                },
+                // FLIPPT
+                0x80 => {
+                    for _ in 0..self.loop_count() {
+                        let p = self.pop_point(1)?;
+                        self.zones[1].on_curve[p] = !self.zones[1].on_curve[p];
+                    }
+                },
+                // FLIPRGON & FLIPRGOFF
+                0x81 | 0x82 => {
+                    let hi = self.pop_point(1)?;
+                    let lo = self.pop_point(1)?;
+
+                    for p in lo..=hi {
+                        self.zones[1].on_curve[p] = op == 0x81;
+                    }
+                },
+                // SCANCTRL
+                0x85 => {
+                    self.pop()?;
+                },
+                // SDPVTL
+                0x86 | 0x87 => {
+                    let (vector, dual) = self.vector_from_line(op & 1 == 1)?;
+                    self.gs.projection_vector = vector;
+                    self.gs.dual_vector = dual;
+                },
+                // GETINFO, reporting the version of the Windows 3.1 rasterizer
+                0x88 => {
+                    let selector = self.pop()?;
+                    self.push(if selector & 1 != 0 { 35 } else { 0 })?;
+                },
+                // IDEF
+                0x89 => {
+                    let opcode = self.pop()?;
+                    let end = Self::find_endf(code, pc + 1)?;
+                    self.idefs.insert(opcode as u8, code[(pc + 1)..end].into());
+                    next = end + 1;
+                },
+                // ROLL
+                0x8A => {
+                    let c = self.pop()?;
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(b)?;
+                    self.push(c)?;
+                    self.push(a)?;
+                },
+                // MAX & MIN
+                0x8B | 0x8C => {
+                    let b = self.pop()?;
+                    let a = self.pop()?;
+                    self.push(if op == 0x8B { a.max(b) } else { a.min(b) })?;
+                },
+                // SCANTYPE
+                0x8D => {
+                    self.pop()?;
+                },
+                // INSTCTRL, only allowed in the control value program
+                0x8E => {
+                    let selector = self.pop()?;
+                    let value = self.pop()?;
+
+                    if self.in_prep && (1..=3).contains(&selector) {
+                        let bit = 1 << (selector - 1);
+                        self.gs.instruct_control =
+                            (self.gs.instruct_control & !bit) | (value & bit);
+                    }
+                },
+                // PUSHB & PUSHW
+                0xB0..=0xB7 => {
+                    for &b in &code[(pc + 1)..next] {
+                        self.push(b as i32)?;
+                    }
+                },
+                0xB8..=0xBF => {
+                    for w in code[(pc + 1)..next].chunks_exact(2) {
+                        self.push(i16::from_be_bytes([w[0], w[1]]) as i32)?;
+                    }
+                },
+                // MDRP
+                0xC0..=0xDF => self.relative_point(op, None)?,
+                // MIRP
+                0xE0..=0xFF => {
+                    let index = self.pop()?;
+                    self.relative_point(op, Some(index))?;
+                },
+                _ => {
+                    let body = self.idefs.get(&op).cloned().ok_or(HintError)?;
+                    self.call(body)?;
+                },
+            }
+
+            pc = next;
+        }
+
+        Ok(())
+    }
+
+    /// Shift of a delta instruction's argument, if it applies at the current size.
+    fn delta_shift(&self, arg: i32, base: i32) -> Option<i32> {
+        if self.gs.delta_base.wrapping_add(base + ((arg >> 4) & 15)) != self.ppem {
+            return None;
+        }
+
+        let mut step = (arg & 15) - 8;
+
+        if step >= 0 {
+            step += 1;
+        }
+
+        Some(step * 64 / (1 << self.gs.delta_shift))
+    }
+
+    /// `MDRP` when `cvt_index` is `None`, otherwise `MIRP`.
+    fn relative_point(&mut self, op: u8, cvt_index: Option<i32>) -> HintResult<()> {
+        let (zp0, zp1) = (self.gs.zp0, self.gs.zp1);
+        let p = self.pop_point(zp1)?;
+        let rp0 = self.point(zp0, self.gs.rp0 as i32)?;
+        let set_rp0 = op & 0x10 != 0;
+        let keep_min = op & 0x08 != 0;
+        let round = op & 0x04 != 0;
+
+        let single_width = |gs: &GraphicsState, distance: i32| {
+            let diff = distance.wrapping_abs().wrapping_sub(gs.single_width);
+
+            if diff.wrapping_abs() < gs.single_width_cutin {
+                if distance >= 0 {
+                    gs.single_width
+                } else {
+                    gs.single_width.wrapping_neg()
+                }
+            } else {
+                distance
+            }
+        };
+
+        let mut cvt_dist = cvt_index.map(|index| single_width(&self.gs, self.cvt(index)));
+
+        if let (Some(cvt_dist), 0) = (cvt_dist, zp1) {
+            let fv = self.gs.freedom_vector;
+            let origin = self.org(zp0, rp0);
+            let point = Point {
+                x: origin
+                    .x
+                    .wrapping_add((cvt_dist as f32 * fv.x).round() as i32),
+                y: origin
+                    .y
+                    .wrapping_add((cvt_dist as f32 * fv.y).round() as i32),
+            };
+
+            self.zones[0].org[p] = point;
+            self.zones[0].cur[p] = point;
+        }
+
+        let org_dist = self.dual_project(self.org(zp1, p), self.org(zp0, rp0));
+        let cur_dist = self.project(self.cur(zp1, p), self.cur(zp0, rp0));
+
+        let mut distance = match cvt_dist.as_mut() {
+            None => {
+                let org_dist = single_width(&self.gs, org_dist);
+
+                if round {
+                    self.gs.round.apply(org_dist)
+                } else {
+                    org_dist
+                }
+            },
+            Some(cvt_dist) => {
+                if self.gs.auto_flip && (org_dist ^ *cvt_dist) < 0 {
+                    *cvt_dist = cvt_dist.wrapping_neg();
+                }
+
+                if round {
+                    if zp0 == zp1
+                        && cvt_dist.wrapping_sub(org_dist).wrapping_abs() > self.gs.cvt_cutin
+                    {
+                        *cvt_dist = org_dist;
+                    }
+
+                    self.gs.round.apply(*cvt_dist)
+                } else {
+                    *cvt_dist
+                }
+            },
+        };
+
+        if keep_min {
+            if org_dist >= 0 {
+                distance = distance.max(self.gs.min_distance);
+            } else {
+                distance = distance.min(self.gs.min_distance.wrapping_neg());
+            }
+        }
+
+        self.move_point(zp1, p, distance.wrapping_sub(cur_dist), true);
+        self.gs.rp1 = rp0;
+        self.gs.rp2 = p;
+
+        if set_rp0 {
+            self.gs.rp0 = p;
+        }
+
+        Ok(())
+    }
+
+    /// Interpolate the points of the glyph that weren't touched along an axis between the
+    /// touched points surrounding them.
+    fn interpolate_untouched(&mut self, x_axis: bool) {
+        let flag = if x_axis { TOUCH_X } else { TOUCH_Y };
+        let zone = &mut self.zones[1];
+        let coord = |p: &Point| if x_axis { p.x } else { p.y };
+        let mut start = 0;
+
+        for &end in zone.ends.iter() {
+            let touched: Vec<usize> = (start..=end)
+                .filter(|p| zone.touched[*p] & flag != 0)
+                .collect();
+
+            if touched.len() == 1 {
+                let t = touched[0];
+                let delta = coord(&zone.cur[t]).wrapping_sub(coord(&zone.org[t]));
+
+                for p in (start..=end).filter(|p| *p != t) {
+                    set_coord(
+                        &mut zone.cur[p],
+                        x_axis,
+                        coord(&zone.org[p]).wrapping_add(delta),
+                    );
+                }
+            } else if touched.len() > 1 {
+                for (i, &t1) in touched.iter().enumerate() {
+                    let t2 = touched[(i + 1) % touched.len()];
+                    let mut p = if t1 == end { start } else { t1 + 1 };
+
+                    while p != t2 {
+                        let (mut o1, mut o2) = (coord(&zone.org[t1]), coord(&zone.org[t2]));
+                        let (mut c1, mut c2) = (coord(&zone.cur[t1]), coord(&zone.cur[t2]));
+
+                        if o1 > o2 {
+                            std::mem::swap(&mut o1, &mut o2);
+                            std::mem::swap(&mut c1, &mut c2);
+                        }
+
+                        let o = coord(&zone.org[p]);
+
+                        // Widened as the coordinates may be anything the font's instructions
+                        // moved them to.
+                        let (o, o1, o2) = (o as i128, o1 as i128, o2 as i128);
+                        let (c1, c2) = (c1 as i128, c2 as i128);
+
+                        let c = if o <= o1 {
+                            o + c1 - o1
+                        } else if o >= o2 {
+                            o + c2 - o2
+                        } else {
+                            c1 + (o - o1) * (c2 - c1) / (o2 - o1)
+                        };
+
+                        set_coord(&mut zone.cur[p], x_axis, c as i32);
+                        p = if p == end { start } else { p + 1 };
+                    }
+                }
+            }
+
+            start = end + 1;
+        }
+    }
+}
+
+fn set_coord(point: &mut Point, x_axis: bool, value: i32) {
+    if x_axis {
+        point.x = value;
+    } else {
+        point.y = value;
+    }
+}
+
+/// Target of a relative jump from the instruction at `pc`.
+fn jump(pc: usize, offset: i32, len: usize) -> HintResult<usize> {
+    let target = pc as i64 + offset as i64;
+
+    if offset == 0 || target < 0 || target as usize > len {
+        return Err(HintError);
+    }
+
+    Ok(target as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `code` at 12 ppem on a glyph of `points` with contours ending at `ends`, returning
+    /// the interpreter to inspect. Its tables are leaked as they outlive the call.
+    fn run(code: &[u8], points: &[(i32, i32)], ends: &[usize], cvt: &[i32]) -> Exec<'static> {
+        let mut glyph = Zone::with_len(points.len());
+
+        for (i, &(x, y)) in points.iter().enumerate() {
+            glyph.org[i] = Point {
+                x,
+                y,
+            };
+        }
+
+        glyph.cur = glyph.org.clone();
+        glyph.ends = ends.to_vec();
+
+        let mut exec = Exec::new(
+            GraphicsState::default(),
+            Zone::default(),
+            glyph,
+            Box::leak(Box::new(cvt.to_vec())),
+            Box::leak(Box::new(Vec::new())),
+            Box::leak(Box::new(HashMap::new())),
+            Box::leak(Box::new(HashMap::new())),
+            12,
+            1.0,
+        );
+
+        exec.run(code).unwrap();
+        exec
+    }
+
+    #[test]
+    fn stack_ops() {
+        // PUSHB[3] 1 2 3, SWAP, DUP, ADD
+        assert_eq!(
+            run(&[0xB2, 1, 2, 3, 0x23, 0x20, 0x60], &[], &[], &[]).stack,
+            [1, 3, 4]
+        );
+        // PUSHB[3] 1 2 3, ROLL, DEPTH
+        assert_eq!(
+            run(&[0xB2, 1, 2, 3, 0x8A, 0x24], &[], &[], &[]).stack,
+            [2, 3, 1, 3]
+        );
+        // PUSHB[4] 5 6 7 2, CINDEX
+        assert_eq!(
+            run(&[0xB3, 5, 6, 7, 2, 0x25], &[], &[], &[]).stack,
+            [5, 6, 7, 6]
+        );
+        // PUSHB[4] 5 6 7 3, MINDEX
+        assert_eq!(
+            run(&[0xB3, 5, 6, 7, 3, 0x26], &[], &[], &[]).stack,
+            [6, 7, 5]
+        );
+        // PUSHW[2] 128 192, MUL & DIV in 26.6
+        assert_eq!(
+            run(&[0xB9, 0, 128, 0, 192, 0x63], &[], &[], &[]).stack,
+            [384]
+        );
+        assert_eq!(
+            run(&[0xB9, 0, 128, 0, 64, 0x62], &[], &[], &[]).stack,
+            [128]
+        );
+        // PUSHW[1] -1, ABS, PUSHB[1] 2, NEG
+        assert_eq!(
+            run(&[0xB8, 0xFF, 0xFF, 0x64, 0xB0, 2, 0x65], &[], &[], &[]).stack,
+            [1, -2]
+        );
+    }
+
+    #[test]
+    fn stack_errors() {
+        let mut exec = run(&[], &[], &[], &[]);
+        // POP & CINDEX past the bottom of the stack
+        assert!(exec.run(&[0x21]).is_err());
+        assert!(exec.run(&[0xB0, 1, 0x25]).is_err());
+        // DIV by zero
+        assert!(exec.run(&[0xB1, 64, 0, 0x62]).is_err());
+    }
+
+    #[test]
+    fn rounding_modes() {
+        assert_eq!(Round::Grid.apply(95), 64);
+        assert_eq!(Round::Grid.apply(96), 128);
+        assert_eq!(Round::Grid.apply(-95), -64);
+        assert_eq!(Round::HalfGrid.apply(70), 96);
+        assert_eq!(Round::DoubleGrid.apply(40), 32);
+        assert_eq!(Round::DownToGrid.apply(127), 64);
+        assert_eq!(Round::UpToGrid.apply(65), 128);
+        assert_eq!(Round::Off.apply(65), 65);
+
+        // A period of one pixel, no phase & a threshold of half a pixel rounds to the grid.
+        let super_grid = Round::super_round(0x48, 64);
+
+        for d in [-200, -96, -95, 0, 31, 32, 95, 96, 200] {
+            assert_eq!(super_grid.apply(d), Round::Grid.apply(d));
+        }
+
+        // Extreme values wrap instead of overflowing.
+        for round in [Round::Grid, Round::HalfGrid, Round::UpToGrid, super_grid] {
+            round.apply(i32::MIN);
+            round.apply(i32::MAX);
+        }
+
+        // RTHG, PUSHB[1] 70, ROUND
+        assert_eq!(run(&[0x19, 0xB0, 70, 0x68], &[], &[], &[]).stack, [96]);
+        // RDTG, PUSHB[1] 127, ROUND
+        assert_eq!(run(&[0x7D, 0xB0, 127, 0x68], &[], &[], &[]).stack, [64]);
+    }
+
+    #[test]
+    fn delta_p() {
+        // The default delta base of 9 selects 12 ppem with 3, then the largest step of 8/8.
+        // PUSHB[3] arg 0 1, DELTAP1
+        let exec = run(&[0xB2, 0x3F, 0, 1, 0x5D], &[(10, 20)], &[0], &[]);
+        assert_eq!(
+            exec.zones[1].cur[0],
+            Point {
+                x: 74,
+                y: 20
+            }
+        );
+        assert_eq!(exec.zones[1].touched[0], TOUCH_X);
+
+        // Other sizes are unaffected.
+        let exec = run(&[0xB2, 0x2F, 0, 1, 0x5D], &[(10, 20)], &[0], &[]);
+        assert_eq!(
+            exec.zones[1].cur[0],
+            Point {
+                x: 10,
+                y: 20
+            }
+        );
+        assert_eq!(exec.zones[1].touched[0], 0);
+    }
+
+    #[test]
+    fn delta_c() {
+        // PUSHB[3] arg 0 1, DELTAC1 with the smallest step of -8/8
+        assert_eq!(*run(&[0xB2, 0x30, 0, 1, 0x73], &[], &[], &[100]).cvt, [36]);
+        // The largest step wraps at the edge of the range.
+        assert_eq!(
+            *run(&[0xB2, 0x3F, 0, 1, 0x73], &[], &[], &[i32::MAX]).cvt,
+            [i32::MIN + 63]
+        );
+    }
+
+    #[test]
+    fn interpolate_untouched() {
+        let points = [(0, 0), (64, 10), (128, 0)];
+
+        // PUSHB[2] 0 0, SHPIX, PUSHB[2] 2 128, SHPIX, IUP[x]
+        let exec = run(
+            &[0xB1, 0, 0, 0x38, 0xB1, 2, 128, 0x38, 0x31],
+            &points,
+            &[2],
+            &[],
+        );
+
+        let x: Vec<i32> = exec.zones[1].cur.iter().map(|p| p.x).collect();
+        assert_eq!(x, [0, 128, 256]);
+        assert_eq!(exec.zones[1].cur[1].y, 10);
+
+        // A single touched point shifts the rest of its contour.
+        // PUSHB[2] 2 64, SHPIX, IUP[x]
+        let exec = run(&[0xB1, 2, 64, 0x38, 0x31], &points, &[2], &[]);
+        let x: Vec<i32> = exec.zones[1].cur.iter().map(|p| p.x).collect();
+        assert_eq!(x, [64, 128, 192]);
+    }
+
+    #[test]
+    fn extreme_coordinates() {
+        // PUSHB[2] 0 1, MD[grid]
+        let exec = run(
+            &[0xB1, 0, 1, 0x49],
+            &[(i32::MAX, 0), (i32::MIN, 0)],
+            &[1],
+            &[],
+        );
+        assert_eq!(exec.stack.len(), 1);
+
+        // PUSHB[2] 0 0, SHPIX, IUP[x] between touched points far apart.
+        run(
+            &[0xB1, 0, 0, 0x38, 0x31],
+            &[(i32::MIN, 0), (0, 0), (i32::MAX, 0)],
+            &[2],
+            &[],
+        );
+    }
+}
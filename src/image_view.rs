@@ -1,3 +1,4 @@
+use std::fmt;
 use std::sync::Arc;
 
 use vulkano::device::{Device, DeviceOwned};
@@ -11,10 +12,21 @@ use vulkano::sampler::ycbcr::SamplerYcbcrConversion;
 use vulkano::sampler::ComponentMapping;
 use vulkano::VulkanObject;
 
-#[derive(Debug)]
 pub enum ImtImageVarient {
     Storage(Arc<StorageImage>),
     Attachment(Arc<AttachmentImage>),
+    /// An image created outside of ilmenite.
+    Custom(Arc<dyn ImageAccess>),
+}
+
+impl fmt::Debug for ImtImageVarient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Storage(i) => f.debug_tuple("Storage").field(i).finish(),
+            Self::Attachment(i) => f.debug_tuple("Attachment").field(i).finish(),
+            Self::Custom(i) => f.debug_tuple("Custom").field(i.inner().image).finish(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -39,6 +51,22 @@ impl ImtImageView {
         }))
     }
 
+    /// Create a `ImtImageView` from an image created elsewhere, such as a user owned atlas.
+    pub fn from_image(image: Arc<dyn ImageAccess>) -> Result<Arc<Self>, ImageViewCreationError> {
+        Ok(Arc::new(Self {
+            view: ImageView::new_default(Arc::new(ImtImageVarient::Custom(image)))?,
+        }))
+    }
+
+    /// Create a `ImtImageView` of the image behind an existing view.
+    ///
+    /// The new view covers the whole image with its default format & component mapping.
+    pub fn from_image_view_abstract(
+        view: Arc<dyn ImageViewAbstract>,
+    ) -> Result<Arc<Self>, ImageViewCreationError> {
+        Self::from_image(view.image())
+    }
+
     #[inline]
     pub fn image_view_ref(&self) -> &ImageView<ImtImageVarient> {
         &self.view
@@ -57,6 +85,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.inner(),
             Self::Attachment(i) => i.inner(),
+            Self::Custom(i) => i.inner(),
         }
     }
 
@@ -65,6 +94,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.initial_layout_requirement(),
             Self::Attachment(i) => i.initial_layout_requirement(),
+            Self::Custom(i) => i.initial_layout_requirement(),
         }
     }
 
@@ -73,6 +103,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.final_layout_requirement(),
             Self::Attachment(i) => i.final_layout_requirement(),
+            Self::Custom(i) => i.final_layout_requirement(),
         }
     }
 
@@ -81,6 +112,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.descriptor_layouts(),
             Self::Attachment(i) => i.descriptor_layouts(),
+            Self::Custom(i) => i.descriptor_layouts(),
         }
     }
 
@@ -89,6 +121,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.layout_initialized(),
             Self::Attachment(i) => i.layout_initialized(),
+            Self::Custom(i) => i.layout_initialized(),
         }
     }
 
@@ -97,6 +130,7 @@ unsafe impl ImageAccess for ImtImageVarient {
         match self {
             Self::Storage(i) => i.is_layout_initialized(),
             Self::Attachment(i) => i.is_layout_initialized(),
+            Self::Custom(i) => i.is_layout_initialized(),
         }
     }
 }
@@ -106,6 +140,7 @@ unsafe impl DeviceOwned for ImtImageVarient {
         match self {
             Self::Storage(i) => i.device(),
             Self::Attachment(i) => i.device(),
+            Self::Custom(i) => i.device(),
         }
     }
 }
@@ -1,4 +1,10 @@
-//! ```rust
+//! ```no_run
+//! # use std::sync::Arc;
+//! # use ilmenite::*;
+//! # #[cfg(feature = "gpu")]
+//! # use vulkano::device::{Device, Queue};
+//! # #[cfg(feature = "gpu")]
+//! # fn example(device: Arc<Device>, queue: Arc<Queue>) {
 //! let ilmenite = Ilmenite::new();
 //!
 //! ilmenite.add_font(
@@ -16,36 +22,63 @@
 //! let glyphs = ilmenite
 //!     .glyphs_for_text("MyFont", ImtWeight::Normal, 12.0, None, "Hello World!")
 //!     .unwrap();
+//! # }
 //! ```
+//!
+//! With the default `gpu` feature disabled vulkano isn't depended upon and only cpu
+//! rasterization is available, e.g. with `ImtFont::from_file_cpu`.
 
+#[cfg(feature = "gpu")]
+pub mod atlas;
 pub mod bitmap;
 pub mod error;
 pub mod font;
+mod hinting;
+#[cfg(feature = "gpu")]
 pub mod image_view;
 pub mod parse;
 pub mod primative;
 pub mod raster;
 pub mod script;
+#[cfg(feature = "gpu")]
 pub mod shaders;
 pub mod shape;
+#[cfg(test)]
+mod test_font;
 
 use std::collections::HashMap;
 
-pub use bitmap::{ImtBitmapData, ImtGlyphBitmap};
+#[cfg(feature = "gpu")]
+pub use atlas::{ImtAtlas, ImtAtlasEntry, ImtAtlasRequest};
+pub use bitmap::{ImtBitmapContent, ImtBitmapData, ImtGlyphBitmap};
 use crossbeam::sync::ShardedLock;
 pub use error::{ImtError, ImtErrorSrc, ImtErrorTy};
-pub(crate) use font::ImtFontKey;
 pub use font::{ImtFont, ImtWeight};
+pub(crate) use font::{ImtFontKey, ImtFontKeyRef};
+#[cfg(feature = "gpu")]
 pub use image_view::{ImtImageVarient, ImtImageView};
-pub use parse::{ImtFontProps, ImtParsedGlyph, ImtParser};
-pub use primative::{ImtGeometry, ImtPoint, ImtPosition};
-pub use raster::{ImtFillQuality, ImtRaster, ImtRasterOpts, ImtRasteredGlyph, ImtSampleQuality};
-pub use script::{ImtLang, ImtScript};
+pub use parse::{
+    ImtCmapEncoding, ImtCmapSubtable, ImtFontProps, ImtGlyphFallback, ImtParsedGlyph, ImtParser,
+    ImtParserOpts, ImtTextOpts,
+};
+pub use primative::{ImtContour, ImtGeometry, ImtPathCmd, ImtPoint, ImtPosition, ImtWinding};
+#[cfg(feature = "gpu")]
+pub use raster::ImtRasterAllocators;
+pub use raster::{
+    ImtAlphaMode, ImtFillQuality, ImtFillRule, ImtImageFormat, ImtRaster, ImtRasterJob,
+    ImtRasterOpts, ImtRasterOptsBuilder, ImtRasterPattern, ImtRasterStats, ImtRasteredGlyph,
+    ImtSampleQuality,
+};
+pub use script::{ImtFeatureTag, ImtLang, ImtScript};
+use shape::snap_to_grid;
 pub use shape::{
-    ImtGlyphInfo, ImtHoriAlign, ImtShapeOpts, ImtShapedGlyph, ImtShaper, ImtTextWrap, ImtVertAlign,
+    ImtGlyphInfo, ImtGlyphPosition, ImtHoriAlign, ImtShapeOpts, ImtShapeOptsBuilder,
+    ImtShapedGlyph, ImtShapedLines, ImtShaper, ImtTextWrap, ImtVertAlign,
 };
+#[cfg(feature = "gpu")]
 use vulkano::device::Features as VkFeatures;
 
+#[cfg(feature = "gpu")]
 pub fn ilmenite_required_vk_features() -> VkFeatures {
     VkFeatures {
         shader_storage_image_write_without_format: true,
@@ -60,12 +93,24 @@ pub struct ImtGlyph {
     pub h: u32,
     pub crop_x: f32,
     pub crop_y: f32,
+    /// Visible region of the bitmap as `[min_x, min_y, max_x, max_y]` in bitmap pixels. `None`
+    /// if the glyph is not clipped by the body on any edge.
+    pub clip: Option<[f32; 4]>,
     pub family: String,
     pub weight: ImtWeight,
     pub index: u16,
     pub bitmap: Option<ImtBitmapData>,
 }
 
+/// A span of text with its own font & size, see `Ilmenite::glyphs_for_runs`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImtTextRun {
+    pub text: String,
+    pub text_height: f32,
+    pub family: String,
+    pub weight: ImtWeight,
+}
+
 pub struct Ilmenite {
     fonts: ShardedLock<HashMap<ImtFontKey, ImtFont>>,
 }
@@ -82,16 +127,50 @@ impl Ilmenite {
         self.fonts.write().unwrap().insert(key, font);
     }
 
-    pub fn has_font<F: Into<String>>(&self, family: F, weight: ImtWeight) -> bool {
-        self.fonts.read().unwrap().contains_key(&ImtFontKey {
-            family: family.into(),
-            weight,
-        })
+    /// Change the raster quality of every added font that has a raster.
+    pub fn set_raster_quality(
+        &self,
+        fill_quality: ImtFillQuality,
+        sample_quality: ImtSampleQuality,
+    ) -> Result<(), ImtError> {
+        for font in self.fonts.write().unwrap().values_mut() {
+            if font.has_raster() {
+                font.set_raster_quality(fill_quality, sample_quality)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn has_font<F: AsRef<str>>(&self, family: F, weight: ImtWeight) -> bool {
+        self.fonts
+            .read()
+            .unwrap()
+            .contains_key(&(family.as_ref(), weight) as &dyn ImtFontKeyRef)
+    }
+
+    /// Call `f` with the font of `family` & `weight`, or return `MissingFont` if it hasn't
+    /// been added.
+    pub(crate) fn with_font<R, F: FnOnce(&ImtFont) -> Result<R, ImtError>>(
+        &self,
+        family: &str,
+        weight: ImtWeight,
+        f: F,
+    ) -> Result<R, ImtError> {
+        f(self
+            .fonts
+            .read()
+            .unwrap()
+            .get(&(family, weight) as &dyn ImtFontKeyRef)
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::MissingFont,
+            ))?)
     }
 
-    pub fn glyphs_for_text<T: AsRef<str>>(
+    pub fn glyphs_for_text<F: AsRef<str>, T: AsRef<str>>(
         &self,
-        family: String,
+        family: F,
         weight: ImtWeight,
         text_height: f32,
         shape_ops: Option<ImtShapeOpts>,
@@ -100,10 +179,7 @@ impl Ilmenite {
         self.fonts
             .read()
             .unwrap()
-            .get(&ImtFontKey {
-                family,
-                weight,
-            })
+            .get(&(family.as_ref(), weight) as &dyn ImtFontKeyRef)
             .ok_or(ImtError::src_and_ty(
                 ImtErrorSrc::Ilmenite,
                 ImtErrorTy::MissingFont,
@@ -114,4 +190,213 @@ impl Ilmenite {
                 text,
             )
     }
+
+    /// Lay out runs of text that may each differ in font & size one after another.
+    ///
+    /// Glyphs on a line share a baseline placed below the largest ascent of the runs on that
+    /// line. Lines are only broken at new lines and spaced by the largest line height on each.
+    /// A line without glyphs takes its height from the run containing the new line.
+    ///
+    /// Of `shape_ops` only the glyph related options, `align_whole_pixels` & `pixel_scale`
+    /// are used. Wrapping & alignment aren't supported, so options that would wrap or align
+    /// text within the body return an error naming the unsupported option.
+    ///
+    /// The returned glyphs are grouped by run in the order of `runs` rather than per line. A
+    /// run that spans several lines has all of its glyphs before those of the next run.
+    pub fn glyphs_for_runs(
+        &self,
+        runs: &[ImtTextRun],
+        shape_ops: Option<ImtShapeOpts>,
+    ) -> Result<Vec<ImtGlyph>, ImtError> {
+        let shape_ops = shape_ops.unwrap_or(ImtShapeOpts::default());
+
+        let bounded_width = shape_ops.body_width > 0.0;
+        let bounded_height = shape_ops.body_height > 0.0;
+
+        let unsupported = if bounded_width && shape_ops.text_wrap != ImtTextWrap::None {
+            Some("text_wrap")
+        } else if bounded_width && shape_ops.hori_align != ImtHoriAlign::Left {
+            Some("hori_align")
+        } else {
+            match shape_ops.vert_align {
+                ImtVertAlign::Top => None,
+                ImtVertAlign::Baseline(_) => Some("vert_align"),
+                ImtVertAlign::Bottom | ImtVertAlign::Center if bounded_height => Some("vert_align"),
+                ImtVertAlign::Bottom | ImtVertAlign::Center => None,
+            }
+        };
+
+        if let Some(option) = unsupported {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Shaper,
+                ImtErrorTy::Other(format!("Runs don't support the {} shape option.", option)),
+            ));
+        }
+
+        let fonts = self.fonts.read().unwrap();
+        let mut run_fonts = Vec::with_capacity(runs.len());
+
+        // Glyphs of each line as (run index, position, x in px, y offset in px from the line's
+        // top).
+        let mut lines: Vec<Vec<(usize, ImtGlyphPosition, f32, f32)>> = vec![Vec::new()];
+        // Largest (ascent, line height) of each line in px.
+        let mut line_metrics: Vec<(f32, f32)> = vec![(0.0, 0.0)];
+        let mut pen_x = 0.0;
+
+        for (run_i, run) in runs.iter().enumerate() {
+            let font = fonts
+                .get(&(run.family.as_str(), run.weight) as &dyn ImtFontKeyRef)
+                .ok_or(ImtError::src_and_ty(
+                    ImtErrorSrc::Ilmenite,
+                    ImtErrorTy::MissingFont,
+                ))?;
+
+            let font_props = font.parser().font_props()?;
+            let px_scaler = font_props.scaler * run.text_height;
+            let ascent = font_props.ascender * px_scaler;
+            let line_height = font_props.line_height() * px_scaler;
+
+            for position in font.glyph_positions(shape_ops.clone(), &run.text)? {
+                let metrics = line_metrics.last_mut().unwrap();
+                metrics.0 = metrics.0.max(ascent);
+                metrics.1 = metrics.1.max(line_height);
+
+                if position.unicodes.first() == Some(&'\n') {
+                    lines.push(Vec::new());
+                    line_metrics.push((ascent, line_height));
+                    pen_x = 0.0;
+                    continue;
+                }
+
+                let x = pen_x + (position.x_offset * px_scaler);
+                // Font units are y-up, lines are laid out y-down.
+                let y_offset = -position.y_offset * px_scaler;
+                pen_x += position.x_advance * px_scaler;
+                lines
+                    .last_mut()
+                    .unwrap()
+                    .push((run_i, position, x, y_offset));
+            }
+
+            run_fonts.push((font, font_props));
+        }
+
+        let mut run_shaped: Vec<Vec<ImtShapedGlyph>> = runs.iter().map(|_| Vec::new()).collect();
+        let mut line_top = 0.0;
+
+        for (line, (line_ascent, line_height)) in lines.into_iter().zip(line_metrics) {
+            let mut baseline = line_top + line_ascent;
+
+            if shape_ops.align_whole_pixels {
+                baseline = f32::ceil(baseline * shape_ops.pixel_scale) / shape_ops.pixel_scale;
+            }
+
+            for (run_i, position, x, y_offset) in line {
+                let (_, font_props) = &run_fonts[run_i];
+                let px_scaler = font_props.scaler * runs[run_i].text_height;
+                // Positions are of the top of the glyph's line in the run's font units.
+                let top = baseline - (font_props.ascender * px_scaler) + y_offset;
+
+                let x = if shape_ops.align_whole_pixels {
+                    snap_to_grid(x, shape_ops.pixel_scale)
+                } else {
+                    x
+                };
+
+                run_shaped[run_i].push(ImtShapedGlyph {
+                    parsed: position.parsed,
+                    position: ImtPosition {
+                        x: x / px_scaler,
+                        y: top / px_scaler,
+                    },
+                    x_overflow: 0.0,
+                    y_overflow: 0.0,
+                    x_underflow: 0.0,
+                    y_underflow: 0.0,
+                    unicodes: position.unicodes,
+                });
+            }
+
+            line_top += line_height;
+        }
+
+        let mut glyphs = Vec::new();
+
+        for (run_i, shaped_glyphs) in run_shaped.into_iter().enumerate() {
+            let (font, _) = &run_fonts[run_i];
+            glyphs.append(&mut font.glyphs_for_shaped(runs[run_i].text_height, shaped_glyphs)?);
+        }
+
+        Ok(glyphs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_font::TestFont;
+
+    #[test]
+    fn runs_unsupported_options() {
+        let ilmenite = Ilmenite::new();
+
+        ilmenite.add_font(
+            ImtFont::from_bytes_cpu(
+                "Test",
+                ImtWeight::Normal,
+                ImtRasterOpts::default(),
+                TestFont::new().build(),
+            )
+            .unwrap(),
+        );
+
+        let runs = [ImtTextRun {
+            text: String::from("abc"),
+            text_height: 10.0,
+            family: String::from("Test"),
+            weight: ImtWeight::Normal,
+        }];
+
+        let unsupported = |shape_ops: ImtShapeOpts| {
+            match ilmenite.glyphs_for_runs(&runs, Some(shape_ops)) {
+                Err(ImtError {
+                    ty: ImtErrorTy::Other(msg),
+                    ..
+                }) => msg,
+                _ => panic!("expected the options to be unsupported"),
+            }
+        };
+
+        let bounded = ImtShapeOpts {
+            body_width: 100.0,
+            body_height: 100.0,
+            ..ImtShapeOpts::default()
+        };
+
+        assert!(unsupported(ImtShapeOpts {
+            text_wrap: ImtTextWrap::NewLine,
+            ..bounded.clone()
+        })
+        .contains("text_wrap"));
+
+        assert!(unsupported(ImtShapeOpts {
+            hori_align: ImtHoriAlign::Center,
+            ..bounded.clone()
+        })
+        .contains("hori_align"));
+
+        assert!(unsupported(ImtShapeOpts {
+            vert_align: ImtVertAlign::Center,
+            ..bounded.clone()
+        })
+        .contains("vert_align"));
+
+        assert_eq!(
+            ilmenite
+                .glyphs_for_runs(&runs, Some(bounded))
+                .unwrap()
+                .len(),
+            3
+        );
+    }
 }
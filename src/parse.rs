@@ -1,24 +1,69 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 use std::sync::atomic::{self, AtomicBool};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use allsorts::binary::read::ReadScope;
-use allsorts::font::read_cmap_subtable;
+use allsorts::font::{read_cmap_subtable, Encoding};
 use allsorts::gpos::{self, Info};
 use allsorts::gsub::{self, GlyphOrigin, RawGlyph};
 use allsorts::layout::{new_layout_cache, GDEFTable, LayoutCache, LayoutTable, GPOS, GSUB};
+use allsorts::post::PostTable;
 use allsorts::tables::cmap::{Cmap, CmapSubtable};
-use allsorts::tables::glyf::{self, CompositeGlyphArgument, GlyfRecord, GlyfTable};
+use allsorts::tables::glyf::{
+    self, CompositeGlyphArgument, CompositeGlyphFlag, CompositeGlyphScale, GlyfRecord, GlyfTable,
+};
 use allsorts::tables::loca::LocaTable;
+use allsorts::tables::os2::Os2;
 use allsorts::tables::{HeadTable, HheaTable, HmtxTable, MaxpTable, OpenTypeData, OpenTypeFont};
 use allsorts::tag;
+use allsorts::unicode::VariationSelector;
 use crossbeam::queue::SegQueue;
 use crossbeam::sync::{Parker, Unparker};
 use parking_lot::{Condvar, Mutex};
+use unicode_bidi::{bidi_class, BidiClass};
+
+use crate::hinting::{Hinter, TAG_CVT, TAG_FPGM, TAG_PREP};
+use crate::{
+    ImtContour, ImtError, ImtErrorSrc, ImtErrorTy, ImtFeatureTag, ImtGeometry, ImtLang, ImtPathCmd,
+    ImtPoint, ImtScript,
+};
+
+/// Amount synthetic bold widens a glyph by as a fraction of the em.
+pub(crate) const SYNTHETIC_BOLD_EM: f32 = 1.0 / 24.0;
+/// Horizontal shear applied to synthetic italic glyphs, roughly 12 degrees.
+pub(crate) const SYNTHETIC_ITALIC_SHEAR: f32 = 0.2126;
+/// `fsSelection` bit of the `OS/2` table indicating the typographic metrics should be used.
+const OS2_USE_TYPO_METRICS: u16 = 1 << 7;
+/// Scale of synthetic small caps relative to capitals when the font lacks `OS/2` heights.
+pub(crate) const SYNTHETIC_SMALL_CAPS_SCALE: f32 = 0.7;
+/// Glyph class of marks in the `GDEF` glyph class definitions.
+const GDEF_MARK_CLASS: u16 = 3;
+const TAG_KERN: u32 = u32::from_be_bytes(*b"kern");
+
+type ParsedGlyphMap = BTreeMap<(u16, bool, bool, bool), Arc<ImtParsedGlyph>>;
+type FallbackFn = Box<dyn FnMut(char) -> Option<ImtGlyphFallback> + Send>;
+
+/// Parsed glyphs of parsers created with `ImtParser::new_shared` keyed by the hash & length of
+/// the font's bytes.
+static SHARED_PARSED_GLYPHS: Mutex<BTreeMap<(u64, usize), Weak<Mutex<ParsedGlyphMap>>>> =
+    parking_lot::const_mutex(BTreeMap::new());
+
+fn shared_parsed_glyphs(font_hash: u64, len: usize) -> Arc<Mutex<ParsedGlyphMap>> {
+    let mut shared = SHARED_PARSED_GLYPHS.lock();
+    shared.retain(|_, parsed_glyphs| parsed_glyphs.strong_count() > 0);
+
+    if let Some(parsed_glyphs) = shared.get(&(font_hash, len)).and_then(Weak::upgrade) {
+        return parsed_glyphs;
+    }
 
-use crate::{ImtError, ImtErrorSrc, ImtErrorTy, ImtGeometry, ImtLang, ImtPoint, ImtScript};
+    let parsed_glyphs = Arc::new(Mutex::new(BTreeMap::new()));
+    shared.insert((font_hash, len), Arc::downgrade(&parsed_glyphs));
+    parsed_glyphs
+}
 
 struct ParserReqRes<T> {
     cond: Condvar,
@@ -33,11 +78,22 @@ impl<T> ParserReqRes<T> {
         })
     }
 
-    fn get(&self) -> Result<T, ImtError> {
+    fn get(&self, timeout: Option<Duration>) -> Result<T, ImtError> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
         let mut result = self.result.lock();
 
         while result.is_none() {
-            self.cond.wait(&mut result);
+            match deadline {
+                Some(deadline) => {
+                    if self.cond.wait_until(&mut result, deadline).timed_out() && result.is_none() {
+                        return Err(ImtError::src_and_ty(
+                            ImtErrorSrc::Ilmenite,
+                            ImtErrorTy::ParserTimeout,
+                        ));
+                    }
+                },
+                None => self.cond.wait(&mut result),
+            }
         }
 
         result.take().unwrap()
@@ -49,6 +105,461 @@ impl<T> ParserReqRes<T> {
     }
 }
 
+/// Run `f` catching any panic and converting it into a `ParserPanicked` error.
+fn catch_panic<T, F: FnOnce() -> Result<T, ImtError>>(f: F) -> (Result<T, ImtError>, bool) {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(ok) => (ok, false),
+        Err(_) => {
+            (
+                Err(ImtError::src_and_ty(
+                    ImtErrorSrc::Ilmenite,
+                    ImtErrorTy::ParserPanicked,
+                )),
+                true,
+            )
+        },
+    }
+}
+
+/// Affine transform of a composite glyph component as `[xx, xy, yx, yy, dx, dy]` where
+/// `x' = xx * x + yx * y + dx` and `y' = xy * x + yy * y + dy`.
+type ComponentTransform = [f32; 6];
+
+const IDENTITY_TRANSFORM: ComponentTransform = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+fn transform_point(t: &ComponentTransform, x: f32, y: f32) -> ImtPoint {
+    ImtPoint {
+        x: (t[0] * x) + (t[2] * y) + t[4],
+        y: (t[1] * x) + (t[3] * y) + t[5],
+    }
+}
+
+/// Transform of a composite glyph component from its scale & offset. When `scaled_offset` is set
+/// the offset is scaled along with the component, otherwise it is applied as is.
+fn component_transform(scale: [f32; 4], x: f32, y: f32, scaled_offset: bool) -> ComponentTransform {
+    let [xx, xy, yx, yy] = scale;
+
+    if scaled_offset {
+        [xx, xy, yx, yy, (xx * x) + (yx * y), (xy * x) + (yy * y)]
+    } else {
+        [xx, xy, yx, yy, x, y]
+    }
+}
+
+/// Transform that applies `child` and then `parent`.
+fn combine_transforms(
+    parent: &ComponentTransform,
+    child: &ComponentTransform,
+) -> ComponentTransform {
+    [
+        (parent[0] * child[0]) + (parent[2] * child[1]),
+        (parent[1] * child[0]) + (parent[3] * child[1]),
+        (parent[0] * child[2]) + (parent[2] * child[3]),
+        (parent[1] * child[2]) + (parent[3] * child[3]),
+        (parent[0] * child[4]) + (parent[2] * child[5]) + parent[4],
+        (parent[1] * child[4]) + (parent[3] * child[5]) + parent[5],
+    ]
+}
+
+/// Append the contours of a simple glyph to `contours`.
+fn simple_glyph_contours(
+    points: &[(f32, f32)],
+    on_curve: &[bool],
+    end_pts: &[u16],
+    transform: &ComponentTransform,
+    contours: &mut Vec<ImtContour>,
+) {
+    let mut contour = Vec::new();
+    let mut geometry = Vec::new();
+
+    for (i, point) in points.iter().enumerate() {
+        contour.push((i, point.0, point.1));
+
+        if end_pts.contains(&(i as u16)) {
+            for j in 0..contour.len() {
+                if !on_curve[contour[j].0] {
+                    let p_i = if j == 0 { contour.len() - 1 } else { j - 1 };
+                    let n_i = if j == contour.len() - 1 { 0 } else { j + 1 };
+
+                    let a = if on_curve[contour[p_i].0] {
+                        (contour[p_i].1, contour[p_i].2)
+                    } else {
+                        (
+                            (contour[p_i].1 + contour[j].1) / 2.0,
+                            (contour[p_i].2 + contour[j].2) / 2.0,
+                        )
+                    };
+
+                    let c = if on_curve[contour[n_i].0] {
+                        (contour[n_i].1, contour[n_i].2)
+                    } else {
+                        (
+                            (contour[n_i].1 + contour[j].1) / 2.0,
+                            (contour[n_i].2 + contour[j].2) / 2.0,
+                        )
+                    };
+
+                    let b = (contour[j].1, contour[j].2);
+
+                    geometry.push(ImtGeometry::Curve([
+                        transform_point(transform, a.0, a.1),
+                        transform_point(transform, b.0, b.1),
+                        transform_point(transform, c.0, c.1),
+                    ]));
+                } else {
+                    let n_i = if j == contour.len() - 1 { 0 } else { j + 1 };
+
+                    if on_curve[contour[n_i].0] {
+                        geometry.push(ImtGeometry::Line([
+                            transform_point(transform, contour[j].1, contour[j].2),
+                            transform_point(transform, contour[n_i].1, contour[n_i].2),
+                        ]));
+                    }
+                }
+            }
+
+            if !geometry.is_empty() {
+                contours.push(ImtContour::new(std::mem::take(&mut geometry)));
+            }
+
+            contour.clear();
+        }
+    }
+}
+
+/// Bounds of a glyph as `[min_x, min_y, max_x, max_y]`, using `bbox` when it is well-formed.
+///
+/// A bounding box that is inverted or not finite is replaced by the bounds of the outline, and
+/// glyphs without an outline are collapsed to a zero-area box at the origin so that they are
+/// rastered as empty bitmaps.
+fn glyph_bounds(bbox: Option<[f32; 4]>, contours: &[ImtContour]) -> [f32; 4] {
+    if contours.is_empty() {
+        return [0.0; 4];
+    }
+
+    if let Some(bbox) = bbox {
+        if bbox.iter().all(|v| v.is_finite()) && bbox[0] <= bbox[2] && bbox[1] <= bbox[3] {
+            return bbox;
+        }
+    }
+
+    let bounds = point_bounds(contour_points(contours));
+
+    if bounds.iter().all(|v| v.is_finite()) {
+        bounds
+    } else {
+        [0.0; 4]
+    }
+}
+
+/// Bounds of `points` as `[min_x, min_y, max_x, max_y]`. Infinite and inverted when there are no
+/// points.
+fn point_bounds<'a, I: IntoIterator<Item = &'a ImtPoint>>(points: I) -> [f32; 4] {
+    points.into_iter().fold(
+        [
+            f32::INFINITY,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+            f32::NEG_INFINITY,
+        ],
+        |bounds, point| {
+            [
+                bounds[0].min(point.x),
+                bounds[1].min(point.y),
+                bounds[2].max(point.x),
+                bounds[3].max(point.y),
+            ]
+        },
+    )
+}
+
+/// Points of every line & curve of `contours`.
+fn contour_points(contours: &[ImtContour]) -> impl Iterator<Item = &ImtPoint> {
+    contours
+        .iter()
+        .flat_map(|contour| contour.geometry.iter())
+        .flat_map(|geo| {
+            let points: &[ImtPoint] = match geo {
+                ImtGeometry::Line(points) => points,
+                ImtGeometry::Curve(points) => points,
+            };
+
+            points.iter()
+        })
+}
+
+/// Scale of one font unit relative to `text_height`. `text_height` is the height of the em box
+/// in pixels, so a font unit is `1 / units_per_em` of it.
+fn em_scaler(units_per_em: u16) -> f32 {
+    1.0 / units_per_em as f32
+}
+
+/// A glyph referenced directly by its index rather than mapped from a character.
+pub(crate) fn direct_glyph(index: u16) -> RawGlyph<()> {
+    RawGlyph {
+        unicodes: Default::default(),
+        glyph_index: index,
+        liga_component_pos: 0,
+        glyph_origin: GlyphOrigin::Direct,
+        small_caps: false,
+        multi_subst_dup: false,
+        is_vert_alt: false,
+        fake_bold: false,
+        fake_italic: false,
+        extra_data: (),
+        variation: None,
+    }
+}
+
+/// Horizontal kerning pairs of a version 0 `kern` table. Pairs of multiple subtables are summed
+/// unless a subtable overrides them. Other versions, formats & malformed tables are ignored.
+fn parse_kern_pairs(data: &[u8]) -> HashMap<(u16, u16), i16> {
+    let read_u16 = |offset: usize| -> Option<u16> {
+        Some(u16::from_be_bytes(
+            data.get(offset..(offset + 2))?.try_into().ok()?,
+        ))
+    };
+
+    let mut pairs = HashMap::new();
+
+    if read_u16(0) != Some(0) {
+        return pairs;
+    }
+
+    let num_tables = read_u16(2).unwrap_or(0);
+    let mut offset = 4;
+
+    for _ in 0..num_tables {
+        let (length, coverage) = match (read_u16(offset + 2), read_u16(offset + 4)) {
+            (Some(length), Some(coverage)) => (length as usize, coverage),
+            _ => break,
+        };
+
+        let horizontal = coverage & 0x1 != 0;
+        let minimum = coverage & 0x2 != 0;
+        let cross_stream = coverage & 0x4 != 0;
+        let overrides = coverage & 0x8 != 0;
+
+        if coverage >> 8 == 0 && horizontal && !minimum && !cross_stream {
+            let num_pairs = read_u16(offset + 6).unwrap_or(0) as usize;
+
+            for pair_i in 0..num_pairs {
+                let pair_offset = offset + 14 + (pair_i * 6);
+
+                let (left, right, value) = match (
+                    read_u16(pair_offset),
+                    read_u16(pair_offset + 2),
+                    read_u16(pair_offset + 4),
+                ) {
+                    (Some(left), Some(right), Some(value)) => (left, right, value as i16),
+                    _ => break,
+                };
+
+                let kerning = pairs.entry((left, right)).or_insert(0_i16);
+
+                if overrides {
+                    *kerning = value;
+                } else {
+                    *kerning = kerning.saturating_add(value);
+                }
+            }
+        }
+
+        if length < 6 {
+            break;
+        }
+
+        offset += length;
+    }
+
+    pairs
+}
+
+/// Unicode variation sequences of a format 14 `cmap` subtable.
+#[derive(Default)]
+struct VariationSequences {
+    /// Ranges of `(selector, first, last)` base characters using their default glyph.
+    default: Vec<(u32, u32, u32)>,
+    /// Glyphs of `(base, selector)` sequences differing from the base's default glyph.
+    non_default: HashMap<(u32, u32), u16>,
+}
+
+/// Variation sequences of the format 14 subtable of a `cmap` table, if it has one. Malformed
+/// records are ignored.
+fn parse_variation_sequences(data: &[u8]) -> VariationSequences {
+    let read = |offset: usize, len: usize| -> Option<u32> {
+        Some(
+            data.get(offset..(offset + len))?
+                .iter()
+                .fold(0, |value, byte| (value << 8) | *byte as u32),
+        )
+    };
+
+    let mut sequences = VariationSequences::default();
+    let num_tables = read(2, 2).unwrap_or(0) as usize;
+
+    // Format 14 is only valid with the Unicode platform & Variation Sequences encoding.
+    let subtable = (0..num_tables)
+        .map(|table_i| 4 + (table_i * 8))
+        .find(|record| read(*record, 2) == Some(0) && read(record + 2, 2) == Some(5))
+        .and_then(|record| read(record + 4, 4))
+        .map(|offset| offset as usize);
+
+    let subtable = match subtable {
+        Some(subtable) if read(subtable, 2) == Some(14) => subtable,
+        _ => return sequences,
+    };
+
+    let num_records = read(subtable + 6, 4).unwrap_or(0) as usize;
+
+    for record_i in 0..num_records {
+        let record = subtable + 10 + (record_i * 11);
+
+        let (selector, default_offset, non_default_offset) =
+            match (read(record, 3), read(record + 3, 4), read(record + 7, 4)) {
+                (Some(selector), Some(default), Some(non_default)) => {
+                    (selector, default as usize, non_default as usize)
+                },
+                _ => break,
+            };
+
+        if default_offset != 0 {
+            let ranges = subtable + default_offset;
+
+            for range_i in 0..read(ranges, 4).unwrap_or(0) as usize {
+                match (
+                    read(ranges + 4 + (range_i * 4), 3),
+                    read(ranges + 7 + (range_i * 4), 1),
+                ) {
+                    (Some(first), Some(additional)) => {
+                        sequences
+                            .default
+                            .push((selector, first, first + additional))
+                    },
+                    _ => break,
+                }
+            }
+        }
+
+        if non_default_offset != 0 {
+            let mappings = subtable + non_default_offset;
+
+            for mapping_i in 0..read(mappings, 4).unwrap_or(0) as usize {
+                match (
+                    read(mappings + 4 + (mapping_i * 5), 3),
+                    read(mappings + 7 + (mapping_i * 5), 2),
+                ) {
+                    (Some(base), Some(index)) => {
+                        sequences.non_default.insert((base, selector), index as u16);
+                    },
+                    _ => break,
+                }
+            }
+        }
+    }
+
+    sequences
+}
+
+/// Whether `c` is a variation selector, which selects a variant of the preceding character
+/// rather than being displayed itself.
+pub(crate) fn is_variation_selector(c: char) -> bool {
+    matches!(c, '\u{FE00}'..='\u{FE0F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
+/// 64-bit FNV-1a hash. Used over `DefaultHasher` as it is stable between builds.
+pub(crate) fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+
+    hash
+}
+
+/// Enable or disable `features` on top of `mask`. Tags the shaping engine doesn't support are
+/// ignored.
+fn apply_features(
+    mut mask: gsub::FeatureMask,
+    features: &[(ImtFeatureTag, bool)],
+) -> gsub::FeatureMask {
+    for &(tag, enable) in features {
+        let tag_mask = gsub::FeatureMask::from_tag(tag.0);
+
+        if enable {
+            mask.insert(tag_mask);
+        } else {
+            mask.remove(tag_mask);
+        }
+    }
+
+    mask
+}
+
+/// Tags of the features of `gsub::FeatureMask::default()` & small caps, in the order applied
+/// when shaping with a list of features.
+const MASK_FEATURE_TAGS: [&[u8; 4]; 7] = [
+    b"ccmp", b"locl", b"rlig", b"liga", b"clig", b"calt", b"smcp",
+];
+
+/// Substitution features of `mask` with `features` applied on top.
+///
+/// The mask has no bits for some features such as the stylistic sets. When one of those is
+/// enabled, the features are instead given to the shaping engine as a list of tags.
+fn substitution_features(
+    mask: gsub::FeatureMask,
+    features: &[(ImtFeatureTag, bool)],
+) -> gsub::Features {
+    let mask = apply_features(mask, features);
+
+    let unmasked =
+        |tag: u32| tag != ImtFeatureTag::KERN.0 && gsub::FeatureMask::from_tag(tag).is_empty();
+
+    if !features
+        .iter()
+        .any(|(tag, enable)| *enable && unmasked(tag.0))
+    {
+        return gsub::Features::Mask(mask);
+    }
+
+    let mut tags: Vec<u32> = Vec::new();
+
+    for tag in MASK_FEATURE_TAGS
+        .iter()
+        .map(|tag| u32::from_be_bytes(**tag))
+        .chain(features.iter().map(|(tag, _)| tag.0))
+    {
+        let enabled = if unmasked(tag) {
+            features
+                .iter()
+                .rev()
+                .find(|(feature, _)| feature.0 == tag)
+                .map(|&(_, enable)| enable)
+                .unwrap_or(false)
+        } else {
+            let tag_mask = gsub::FeatureMask::from_tag(tag);
+            !tag_mask.is_empty() && mask.contains(tag_mask)
+        };
+
+        if enabled && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    gsub::Features::Custom(
+        tags.into_iter()
+            .map(|feature_tag| {
+                gsub::FeatureInfo {
+                    feature_tag,
+                    alternate: None,
+                }
+            })
+            .collect(),
+    )
+}
+
 enum ParserReq {
     FontProps(Arc<ParserReqRes<ImtFontProps>>),
     RetrieveText(
@@ -56,24 +567,210 @@ enum ParserReq {
         String,
         ImtScript,
         ImtLang,
+        ImtTextOpts,
     ),
     RetrieveInfo(
         Arc<ParserReqRes<Vec<Info>>>,
         Vec<RawGlyph<()>>,
         ImtScript,
         ImtLang,
+        Vec<(ImtFeatureTag, bool)>,
     ),
+    GlyphName(Arc<ParserReqRes<Option<String>>>, u16),
+    GlyphIndexByName(Arc<ParserReqRes<Option<u16>>>, String),
+    MissingChars(Arc<ParserReqRes<Vec<char>>>, String),
+    RetrieveGlyph(Arc<ParserReqRes<Arc<ImtParsedGlyph>>>, u16),
+    HintGlyph(
+        Arc<ParserReqRes<Option<Arc<ImtParsedGlyph>>>>,
+        Arc<ImtParsedGlyph>,
+        f32,
+    ),
+    Kerning(Arc<ParserReqRes<f32>>, u16, u16, ImtScript, ImtLang),
+    VariationGlyph(Arc<ParserReqRes<Option<u16>>>, char, char),
+    CmapSubtables(Arc<ParserReqRes<Vec<ImtCmapSubtable>>>),
+    FontHash(Arc<ParserReqRes<u64>>),
+    SetFallback(Arc<ParserReqRes<()>>, Option<FallbackFn>),
+}
+
+impl ParserReq {
+    fn set_panicked(self) {
+        let err = ImtError::src_and_ty(ImtErrorSrc::Ilmenite, ImtErrorTy::ParserPanicked);
+
+        match self {
+            ParserReq::FontProps(res) => res.set(Err(err)),
+            ParserReq::RetrieveText(res, ..) => res.set(Err(err)),
+            ParserReq::RetrieveInfo(res, ..) => res.set(Err(err)),
+            ParserReq::GlyphName(res, ..) => res.set(Err(err)),
+            ParserReq::GlyphIndexByName(res, ..) => res.set(Err(err)),
+            ParserReq::MissingChars(res, ..) => res.set(Err(err)),
+            ParserReq::RetrieveGlyph(res, ..) => res.set(Err(err)),
+            ParserReq::HintGlyph(res, ..) => res.set(Err(err)),
+            ParserReq::Kerning(res, ..) => res.set(Err(err)),
+            ParserReq::VariationGlyph(res, ..) => res.set(Err(err)),
+            ParserReq::CmapSubtables(res) => res.set(Err(err)),
+            ParserReq::FontHash(res) => res.set(Err(err)),
+            ParserReq::SetFallback(res, ..) => res.set(Err(err)),
+        }
+    }
 }
 
+/// Answer a request, returning true if the parser panicked while doing so.
+fn handle_request(parser: &mut ImtParserNonSend, req: ParserReq) -> bool {
+    match req {
+        ParserReq::FontProps(res) => {
+            let (result, panicked) = catch_panic(|| Ok(parser.font_props()));
+            res.set(result);
+            panicked
+        },
+        ParserReq::RetrieveText(res, text, script, lang, opts) => {
+            let (result, panicked) = catch_panic(|| parser.retreive_text(text, script, lang, opts));
+            res.set(result);
+            panicked
+        },
+        ParserReq::RetrieveInfo(res, glyphs, script, lang, features) => {
+            let (result, panicked) =
+                catch_panic(|| parser.retreive_info(glyphs, script, lang, &features));
+            res.set(result);
+            panicked
+        },
+        ParserReq::GlyphName(res, index) => {
+            let (result, panicked) = catch_panic(|| Ok(parser.glyph_name(index)));
+            res.set(result);
+            panicked
+        },
+        ParserReq::GlyphIndexByName(res, name) => {
+            let (result, panicked) = catch_panic(|| Ok(parser.glyph_index_by_name(&name)));
+            res.set(result);
+            panicked
+        },
+        ParserReq::MissingChars(res, text) => {
+            let (result, panicked) = catch_panic(|| parser.missing_chars(&text));
+            res.set(result);
+            panicked
+        },
+        ParserReq::RetrieveGlyph(res, index) => {
+            let (result, panicked) = catch_panic(|| parser.retrieve_glyph(index));
+            res.set(result);
+            panicked
+        },
+        ParserReq::HintGlyph(res, parsed, text_height) => {
+            let (result, panicked) = catch_panic(|| parser.hint_glyph(&parsed, text_height));
+            res.set(result);
+            panicked
+        },
+        ParserReq::Kerning(res, left, right, script, lang) => {
+            let (result, panicked) = catch_panic(|| parser.kerning(left, right, script, lang));
+            res.set(result);
+            panicked
+        },
+        ParserReq::VariationGlyph(res, base, selector) => {
+            let (result, panicked) = catch_panic(|| parser.variation_glyph(base, selector));
+            res.set(result);
+            panicked
+        },
+        ParserReq::CmapSubtables(res) => {
+            let (result, panicked) = catch_panic(|| Ok(parser.cmap_subtables()));
+            res.set(result);
+            panicked
+        },
+        ParserReq::FontHash(res) => {
+            let (result, panicked) = catch_panic(|| Ok(parser.font_hash()));
+            res.set(result);
+            panicked
+        },
+        ParserReq::SetFallback(res, fallback) => {
+            parser.fallback = fallback;
+            res.set(Ok(()));
+            false
+        },
+    }
+}
+
+/// A parser answering requests on the calling thread, used where threads are unavailable.
+///
+/// The mutex is held for the whole of a request, including while a fallback set with
+/// `ImtParser::set_fallback` runs.
+struct InlineParser {
+    parser: Mutex<ImtParserNonSend>,
+}
+
+// SAFETY: `ImtParserNonSend` is `!Send` as its layout caches are `Rc`'s and its tables borrow the
+// bytes it owns. The `Rc`'s are never cloned out of the parser, so their counts are only changed
+// by the thread holding the mutex. The borrowed bytes are boxed, so moving the parser to another
+// thread doesn't move them. Requests only return owned or `Arc` data and the fallback callback
+// is `Send`.
+unsafe impl Send for InlineParser {}
+
+// SAFETY: The parser is only accessed through the mutex, so no two threads use it at once. Shared
+// references to `InlineParser` give no other access.
+unsafe impl Sync for InlineParser {}
+
 pub struct ImtParser {
     worker: Option<JoinHandle<()>>,
+    inline: Option<InlineParser>,
     requests: Arc<SegQueue<ParserReq>>,
     unparker: Unparker,
     dropped: Arc<AtomicBool>,
+    poisoned: Arc<AtomicBool>,
+    /// Hash of the font's bytes, computed when first needed unless the parser is shared.
+    font_hash: Mutex<Option<u64>>,
 }
 
 impl ImtParser {
-    pub fn new(bytes: Vec<u8>) -> Result<Self, ImtError> {
+    /// Create a parser backed by `bytes`.
+    ///
+    /// Any owned byte source may be used such as `Vec<u8>`, `Arc<[u8]>` or a memory map. The
+    /// bytes are not copied.
+    pub fn new<B: AsRef<[u8]> + Send + 'static>(bytes: B) -> Result<Self, ImtError> {
+        Self::new_with_opts(bytes, ImtParserOpts::default())
+    }
+
+    /// Same as `new`, but parsed glyphs are shared with other parsers created by this method
+    /// from identical bytes. This avoids duplicating glyph geometry when the same font is
+    /// loaded more than once, such as under multiple family names.
+    pub fn new_shared<B: AsRef<[u8]> + Send + 'static>(bytes: B) -> Result<Self, ImtError> {
+        Self::new_with_opts(
+            bytes,
+            ImtParserOpts {
+                shared: true,
+                ..ImtParserOpts::default()
+            },
+        )
+    }
+
+    /// Same as `new`, but without a worker thread. Requests are answered on the calling thread,
+    /// so panics while parsing occur on the caller's stack, though they are still returned as
+    /// `ParserPanicked` errors, and dropping the parser doesn't need to join a thread. Requests
+    /// from multiple threads are answered one at a time.
+    pub fn new_sync<B: AsRef<[u8]> + Send + 'static>(bytes: B) -> Result<Self, ImtError> {
+        Self::new_with_opts(
+            bytes,
+            ImtParserOpts {
+                synchronous: true,
+                ..ImtParserOpts::default()
+            },
+        )
+    }
+
+    /// Same as `new`, but with the provided options.
+    pub fn new_with_opts<B: AsRef<[u8]> + Send + 'static>(
+        bytes: B,
+        opts: ImtParserOpts,
+    ) -> Result<Self, ImtError> {
+        // Shared parsers are keyed by the hash, otherwise hashing is deferred until needed.
+        let font_hash = match opts.shared {
+            true => Some(fnv1a_64(bytes.as_ref())),
+            false => None,
+        };
+
+        let parsed_glyphs_op =
+            font_hash.map(|font_hash| shared_parsed_glyphs(font_hash, bytes.as_ref().len()));
+
+        // Threads can't be spawned on wasm.
+        if opts.synchronous || cfg!(target_arch = "wasm32") {
+            return Self::new_inline(bytes, opts, font_hash, parsed_glyphs_op);
+        }
+
         let requests_orig = Arc::new(SegQueue::new());
         let requests = requests_orig.clone();
         let result_orig: Arc<ParserReqRes<()>> = ParserReqRes::new();
@@ -82,10 +779,16 @@ impl ImtParser {
         let unparker = parker.unparker().clone();
         let dropped_orig = Arc::new(AtomicBool::new(false));
         let dropped = dropped_orig.clone();
+        let poisoned_orig = Arc::new(AtomicBool::new(false));
+        let poisoned = poisoned_orig.clone();
 
         let worker = Some(thread::spawn(move || {
-            let mut parser = match ImtParserNonSend::new(bytes) {
-                Ok(ok) => {
+            let mut parser = match catch_panic(|| ImtParserNonSend::new_with_opts(bytes, &opts)).0 {
+                Ok(mut ok) => {
+                    if let Some(parsed_glyphs) = parsed_glyphs_op {
+                        ok.parsed_glyphs = parsed_glyphs;
+                    }
+
                     result.set(Ok(()));
                     ok
                 },
@@ -101,14 +804,18 @@ impl ImtParser {
                 }
 
                 while let Some(req) = requests.pop() {
-                    match req {
-                        ParserReq::FontProps(res) => res.set(Ok(parser.font_props())),
-                        ParserReq::RetrieveText(res, text, script, lang) => {
-                            res.set(parser.retreive_text(text, script, lang));
-                        },
-                        ParserReq::RetrieveInfo(res, glyphs, script, lang) => {
-                            res.set(parser.retreive_info(glyphs, script, lang));
-                        },
+                    let panicked = handle_request(&mut parser, req);
+
+                    // The parser's state can't be trusted after a panic. Fail any requests
+                    // already queued so their callers don't wait on a worker that has exited.
+                    if panicked {
+                        poisoned.store(true, atomic::Ordering::SeqCst);
+
+                        while let Some(req) = requests.pop() {
+                            req.set_panicked();
+                        }
+
+                        return;
                     }
                 }
 
@@ -116,21 +823,104 @@ impl ImtParser {
             }
         }));
 
-        result_orig.get()?;
+        result_orig.get(None)?;
 
         Ok(ImtParser {
             worker,
+            inline: None,
             requests: requests_orig,
             unparker,
             dropped: dropped_orig,
+            poisoned: poisoned_orig,
+            font_hash: Mutex::new(font_hash),
+        })
+    }
+
+    /// Create a parser that answers requests on the calling thread instead of a worker.
+    fn new_inline<B: AsRef<[u8]> + Send + 'static>(
+        bytes: B,
+        opts: ImtParserOpts,
+        font_hash: Option<u64>,
+        parsed_glyphs_op: Option<Arc<Mutex<ParsedGlyphMap>>>,
+    ) -> Result<Self, ImtError> {
+        let mut parser = catch_panic(|| ImtParserNonSend::new_with_opts(bytes, &opts)).0?;
+
+        if let Some(parsed_glyphs) = parsed_glyphs_op {
+            parser.parsed_glyphs = parsed_glyphs;
+        }
+
+        Ok(ImtParser {
+            worker: None,
+            inline: Some(InlineParser {
+                parser: Mutex::new(parser),
+            }),
+            requests: Arc::new(SegQueue::new()),
+            unparker: Parker::new().unparker().clone(),
+            dropped: Arc::new(AtomicBool::new(false)),
+            poisoned: Arc::new(AtomicBool::new(false)),
+            font_hash: Mutex::new(font_hash),
         })
     }
 
-    pub fn font_props(&self) -> ImtFontProps {
+    /// Hash of the font's bytes. Computed by the parser the first time it is requested.
+    pub fn font_hash(&self) -> Result<u64, ImtError> {
+        if let Some(font_hash) = *self.font_hash.lock() {
+            return Ok(font_hash);
+        }
+
         let res = ParserReqRes::new();
-        self.requests.push(ParserReq::FontProps(res.clone()));
+        self.submit(ParserReq::FontHash(res.clone()))?;
+        let font_hash = res.get(None)?;
+        *self.font_hash.lock() = Some(font_hash);
+        Ok(font_hash)
+    }
+
+    /// Returns true if the parser has panicked, for parsers with a worker this means it has
+    /// exited. Once poisoned all requests return a `ParserPanicked` error.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(atomic::Ordering::SeqCst)
+    }
+
+    fn submit(&self, req: ParserReq) -> Result<(), ImtError> {
+        if self.is_poisoned() {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::ParserPanicked,
+            ));
+        }
+
+        if let Some(inline) = self.inline.as_ref() {
+            if handle_request(&mut inline.parser.lock(), req) {
+                self.poisoned.store(true, atomic::Ordering::SeqCst);
+            }
+
+            return Ok(());
+        }
+
+        self.requests.push(req);
+
+        // The worker may have been poisoned after the check above and already drained the
+        // queue. In that case nothing else will respond to this request.
+        if self.is_poisoned() {
+            while let Some(req) = self.requests.pop() {
+                req.set_panicked();
+            }
+        }
+
         self.unparker.unpark();
-        res.get().unwrap()
+        Ok(())
+    }
+
+    pub fn font_props(&self) -> Result<ImtFontProps, ImtError> {
+        self.font_props_timeout(None)
+    }
+
+    /// Same as `font_props`, but returns a `ParserTimeout` error if the parser has not responded
+    /// within `timeout`. A timeout of `None` waits indefinitely.
+    pub fn font_props_timeout(&self, timeout: Option<Duration>) -> Result<ImtFontProps, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::FontProps(res.clone()))?;
+        res.get(timeout)
     }
 
     pub fn retrieve_text<T: AsRef<str>>(
@@ -138,16 +928,74 @@ impl ImtParser {
         text: T,
         script: ImtScript,
         lang: ImtLang,
+    ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
+        self.retrieve_text_synthetic(text, script, lang, false, false)
+    }
+
+    /// Same as `retrieve_text`, but returns a `ParserTimeout` error if the parser has not
+    /// responded within `timeout`. A timeout of `None` waits indefinitely.
+    pub fn retrieve_text_timeout<T: AsRef<str>>(
+        &self,
+        text: T,
+        script: ImtScript,
+        lang: ImtLang,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
+        self.request_text(text, script, lang, ImtTextOpts::default(), timeout)
+    }
+
+    /// Same as `retrieve_text`, but marks the glyphs as `fake_bold` and/or `fake_italic`.
+    ///
+    /// Synthetic italic glyphs have their geometry sheared. Synthetic bold glyphs are widened
+    /// and have their coverage dilated when rasterized.
+    pub fn retrieve_text_synthetic<T: AsRef<str>>(
+        &self,
+        text: T,
+        script: ImtScript,
+        lang: ImtLang,
+        fake_bold: bool,
+        fake_italic: bool,
+    ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
+        self.retrieve_text_with_opts(
+            text,
+            script,
+            lang,
+            ImtTextOpts {
+                fake_bold,
+                fake_italic,
+                ..ImtTextOpts::default()
+            },
+        )
+    }
+
+    /// Same as `retrieve_text`, but with the substitution and synthesis options of `opts`.
+    pub fn retrieve_text_with_opts<T: AsRef<str>>(
+        &self,
+        text: T,
+        script: ImtScript,
+        lang: ImtLang,
+        opts: ImtTextOpts,
+    ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
+        self.request_text(text, script, lang, opts, None)
+    }
+
+    fn request_text<T: AsRef<str>>(
+        &self,
+        text: T,
+        script: ImtScript,
+        lang: ImtLang,
+        opts: ImtTextOpts,
+        timeout: Option<Duration>,
     ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
         let res = ParserReqRes::new();
-        self.requests.push(ParserReq::RetrieveText(
+        self.submit(ParserReq::RetrieveText(
             res.clone(),
             String::from(text.as_ref()),
             script,
             lang,
-        ));
-        self.unparker.unpark();
-        res.get()
+            opts,
+        ))?;
+        res.get(timeout)
     }
 
     pub fn retreive_info(
@@ -155,16 +1003,177 @@ impl ImtParser {
         raw_glyphs: Vec<RawGlyph<()>>,
         script: ImtScript,
         lang: ImtLang,
+    ) -> Result<Vec<Info>, ImtError> {
+        self.retreive_info_timeout(raw_glyphs, script, lang, None)
+    }
+
+    /// Same as `retreive_info`, but enabling or disabling `features`.
+    pub fn retreive_info_with_features(
+        &self,
+        raw_glyphs: Vec<RawGlyph<()>>,
+        script: ImtScript,
+        lang: ImtLang,
+        features: Vec<(ImtFeatureTag, bool)>,
+    ) -> Result<Vec<Info>, ImtError> {
+        self.request_info(raw_glyphs, script, lang, features, None)
+    }
+
+    /// Same as `retreive_info`, but returns a `ParserTimeout` error if the parser has not
+    /// responded within `timeout`. A timeout of `None` waits indefinitely.
+    pub fn retreive_info_timeout(
+        &self,
+        raw_glyphs: Vec<RawGlyph<()>>,
+        script: ImtScript,
+        lang: ImtLang,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Info>, ImtError> {
+        self.request_info(raw_glyphs, script, lang, Vec::new(), timeout)
+    }
+
+    fn request_info(
+        &self,
+        raw_glyphs: Vec<RawGlyph<()>>,
+        script: ImtScript,
+        lang: ImtLang,
+        features: Vec<(ImtFeatureTag, bool)>,
+        timeout: Option<Duration>,
     ) -> Result<Vec<Info>, ImtError> {
         let res = ParserReqRes::new();
-        self.requests.push(ParserReq::RetrieveInfo(
+        self.submit(ParserReq::RetrieveInfo(
             res.clone(),
             raw_glyphs,
             script,
             lang,
-        ));
-        self.unparker.unpark();
-        res.get()
+            features,
+        ))?;
+        res.get(timeout)
+    }
+
+    /// Name of the glyph at `index` from the `post` table. Returns `None` if the font has no
+    /// glyph names, such as with a format 3 `post` table, or the parser has failed.
+    pub fn glyph_name(&self, index: u16) -> Option<String> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::GlyphName(res.clone(), index)).ok()?;
+        res.get(None).ok().flatten()
+    }
+
+    /// Index of the glyph named `name` in the `post` table. See `glyph_name`.
+    pub fn glyph_index_by_name<N: AsRef<str>>(&self, name: N) -> Option<u16> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::GlyphIndexByName(
+            res.clone(),
+            name.as_ref().to_string(),
+        ))
+        .ok()?;
+        res.get(None).ok().flatten()
+    }
+
+    /// Characters of `text` that the font's cmap does not map to a glyph, in order of first
+    /// occurrence. Control characters are skipped as they aren't rendered. Unlike parsing text
+    /// this does not populate the parsed glyph cache.
+    pub fn missing_chars<T: AsRef<str>>(&self, text: T) -> Result<Vec<char>, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::MissingChars(
+            res.clone(),
+            text.as_ref().to_string(),
+        ))?;
+        res.get(None)
+    }
+
+    /// Parse the glyph at `index` directly, bypassing the cmap and substitution. This allows
+    /// glyphs that aren't mapped to any character to be retrieved.
+    pub fn retrieve_glyph(&self, index: u16) -> Result<Arc<ImtParsedGlyph>, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::RetrieveGlyph(res.clone(), index))?;
+        res.get(None)
+    }
+
+    /// The outline of the glyph at `index` as closed paths in font units with y pointing up. See
+    /// `retrieve_glyph`.
+    pub fn glyph_path(&self, index: u16) -> Result<Vec<ImtPathCmd>, ImtError> {
+        Ok(self.retrieve_glyph(index)?.path())
+    }
+
+    /// The outline of the glyph at `index` as the `d` attribute of an SVG path, see
+    /// `ImtParsedGlyph::to_svg_path`. Composite glyphs are flattened into a single path.
+    pub fn glyph_to_svg_path(&self, index: u16, precision: usize) -> Result<String, ImtError> {
+        Ok(self.retrieve_glyph(index)?.to_svg_path(precision))
+    }
+
+    /// Grid-fit a parsed glyph at `text_height` by executing the font's TrueType instructions.
+    ///
+    /// Returns `None` when the glyph isn't a simple glyph with instructions, is synthetic italic or
+    /// small caps, or its instructions couldn't be executed. The glyph should then be used as is.
+    pub fn hint_glyph(
+        &self,
+        parsed: Arc<ImtParsedGlyph>,
+        text_height: f32,
+    ) -> Result<Option<Arc<ImtParsedGlyph>>, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::HintGlyph(res.clone(), parsed, text_height))?;
+        res.get(None)
+    }
+
+    /// Set a callback deciding the glyph of characters the font's cmap doesn't map, replacing
+    /// any previously set callback. When it returns `None` the glyph of `?` is used as before.
+    ///
+    /// The callback is called on the parser's thread while text is being retrieved. Characters it
+    /// provides a glyph for are still reported by `missing_chars`.
+    ///
+    /// The parser is busy with the request while the callback runs, so the callback must not
+    /// make requests to this parser. They would never be answered, deadlocking the parser.
+    pub fn set_fallback<F: FnMut(char) -> Option<ImtGlyphFallback> + Send + 'static>(
+        &self,
+        fallback: F,
+    ) -> Result<(), ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::SetFallback(
+            res.clone(),
+            Some(Box::new(fallback)),
+        ))?;
+        res.get(None)
+    }
+
+    /// Remove the callback set with `set_fallback`.
+    pub fn clear_fallback(&self) -> Result<(), ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::SetFallback(res.clone(), None))?;
+        res.get(None)
+    }
+
+    /// Kerning adjustment in font units between the glyphs at `left` & `right` when `right`
+    /// directly follows `left`.
+    ///
+    /// Pair positioning of the `GPOS` table is used, falling back to the `kern` table for fonts
+    /// without a `GPOS` table.
+    pub fn kerning(
+        &self,
+        left: u16,
+        right: u16,
+        script: ImtScript,
+        lang: ImtLang,
+    ) -> Result<f32, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::Kerning(res.clone(), left, right, script, lang))?;
+        res.get(None)
+    }
+
+    /// Glyph of the variation sequence of `base` followed by the variation `selector`, such as
+    /// `U+FE0E` for text presentation. `None` if the font doesn't define the sequence.
+    ///
+    /// Sequences are those of the `cmap` table's format 14 subtable.
+    pub fn variation_glyph(&self, base: char, selector: char) -> Result<Option<u16>, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::VariationGlyph(res.clone(), base, selector))?;
+        res.get(None)
+    }
+
+    /// Subtables of the font's cmap. One may be chosen with `ImtParserOpts::cmap_subtable`,
+    /// see `ImtFontProps::cmap_encoding` for the encoding of the one in use.
+    pub fn cmap_subtables(&self) -> Result<Vec<ImtCmapSubtable>, ImtError> {
+        let res = ParserReqRes::new();
+        self.submit(ParserReq::CmapSubtables(res.clone()))?;
+        res.get(None)
     }
 }
 
@@ -181,7 +1190,7 @@ impl Drop for ImtParser {
 
 #[allow(dead_code)]
 pub struct ImtParserNonSend {
-    bytes: Vec<u8>,
+    bytes: Box<dyn AsRef<[u8]> + Send>,
     scope: ReadScope<'static>,
     head: HeadTable,
     maxp: MaxpTable,
@@ -191,23 +1200,156 @@ pub struct ImtParserNonSend {
     hmtx: HmtxTable<'static>,
     loca: LocaTable<'static>,
     glyf: GlyfTable<'static>,
+    post_op: Option<PostTable<'static>>,
+    glyph_names: Option<HashMap<String, u16>>,
     gdef_op: Option<GDEFTable>,
     gpos_op: Option<LayoutCache<GPOS>>,
     gsub_op: Option<LayoutCache<GSUB>>,
     font_props: ImtFontProps,
-    parsed_glyphs: BTreeMap<u16, Arc<ImtParsedGlyph>>,
+    parsed_glyphs: Arc<Mutex<ParsedGlyphMap>>,
+    hinter: Hinter,
+    /// Pairs of the `kern` table, used for kerning queries when there is no `GPOS` table.
+    kern_pairs: HashMap<(u16, u16), i16>,
+    variation_sequences: VariationSequences,
+    fallback: Option<FallbackFn>,
+}
+
+/// Options used when creating an `ImtParser`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImtParserOpts {
+    /// Share parsed glyphs with other parsers of identical fonts, see `ImtParser::new_shared`.
+    pub shared: bool,
+    /// Use the `OS/2` typographic ascender, descender & line gap even when the font doesn't set
+    /// `USE_TYPO_METRICS`. Fonts without an `OS/2` table use the `hhea` metrics.
+    pub use_typo_metrics: bool,
+    /// Answer requests on the calling thread instead of a worker thread, see
+    /// `ImtParser::new_sync`. Always the case on wasm.
+    pub synchronous: bool,
+    /// Platform & encoding ids of the cmap subtable to use, see `ImtParser::cmap_subtables`.
+    /// One is chosen automatically when `None` or the font doesn't have the subtable.
+    pub cmap_subtable: Option<(u16, u16)>,
+}
+
+/// Options used when retrieving the glyphs of text.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImtTextOpts {
+    /// Mark glyphs as `fake_bold`, widening them and dilating their coverage.
+    pub fake_bold: bool,
+    /// Mark glyphs as `fake_italic`, shearing their geometry.
+    pub fake_italic: bool,
+    /// Enable the `smcp` feature. Lowercase characters the font doesn't substitute are
+    /// synthesized from scaled down capitals.
+    pub small_caps: bool,
+    /// Features to enable or disable in addition to the defaults. Later entries take
+    /// precedence.
+    pub features: Vec<(ImtFeatureTag, bool)>,
+}
+
+/// Glyph to use for a character the cmap doesn't map, see `ImtParser::set_fallback`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImtGlyphFallback {
+    /// Use the glyph the cmap maps this character to, such as `-` for a missing hyphen.
+    Char(char),
+    /// Use the glyph at this index, such as a private glyph of the font.
+    Index(u16),
+}
+
+/// The encoding of the cmap subtable used to map characters to glyphs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImtCmapEncoding {
+    Unicode,
+    /// Characters are also looked up at U+F000 plus their code.
+    Symbol,
+    /// Only ASCII characters can be mapped.
+    AppleRoman,
+}
+
+impl ImtCmapEncoding {
+    /// Encoding of the cmap subtable with the given platform & encoding ids, if supported.
+    pub fn from_ids(platform_id: u16, encoding_id: u16) -> Option<Self> {
+        match (platform_id, encoding_id) {
+            // Encoding 5 is variation sequences, which doesn't map characters on its own.
+            (0, 5) => None,
+            (0, _) | (3, 1) | (3, 10) => Some(ImtCmapEncoding::Unicode),
+            (3, 0) => Some(ImtCmapEncoding::Symbol),
+            (1, 0) => Some(ImtCmapEncoding::AppleRoman),
+            _ => None,
+        }
+    }
+}
+
+/// A subtable of a font's cmap, see `ImtParser::cmap_subtables`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImtCmapSubtable {
+    pub platform_id: u16,
+    pub encoding_id: u16,
+    /// `None` if the subtable's encoding isn't supported.
+    pub encoding: Option<ImtCmapEncoding>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ImtFontProps {
     pub scaler: f32,
+    /// Distance from the baseline to the top of a line, positive is up.
     pub ascender: f32,
+    /// Distance from the baseline to the bottom of a line, typically negative.
     pub descender: f32,
+    /// Additional space between lines.
     pub line_gap: f32,
     pub min_x: f32,
     pub max_x: f32,
     pub min_y: f32,
     pub max_y: f32,
+    /// Font-wide bounding box from the `head` table as `[min_x, min_y, max_x, max_y]`.
+    pub bbox: [f32; 4],
+    /// Cap height from the `OS/2` table if present.
+    pub cap_height: Option<f32>,
+    /// x-height from the `OS/2` table if present.
+    pub x_height: Option<f32>,
+    /// Whether the `post` table marks this font as fixed-pitch.
+    pub is_monospace: bool,
+    /// Underline position relative to the baseline from the `post` table if present.
+    pub underline_position: Option<f32>,
+    /// Underline thickness from the `post` table if present.
+    pub underline_thickness: Option<f32>,
+    /// Strikeout position relative to the baseline from the `OS/2` table if present.
+    pub strikeout_position: Option<f32>,
+    /// Strikeout thickness from the `OS/2` table if present.
+    pub strikeout_thickness: Option<f32>,
+    /// Encoding of the cmap subtable used.
+    pub cmap_encoding: ImtCmapEncoding,
+}
+
+impl ImtFontProps {
+    /// Distance between baselines in font units, `ascender - descender + line_gap`.
+    pub fn line_height(&self) -> f32 {
+        self.ascender - self.descender + self.line_gap
+    }
+
+    /// Convert a value in font units to pixels for the given `text_height`.
+    pub fn font_units_to_px(&self, value: f32, text_height: f32) -> f32 {
+        value * self.scaler * text_height
+    }
+
+    /// Underline `(position, thickness)` in pixels for the given `text_height`.
+    ///
+    /// The position is relative to the baseline where positive is up.
+    pub fn underline_px(&self, text_height: f32) -> Option<(f32, f32)> {
+        Some((
+            self.font_units_to_px(self.underline_position?, text_height),
+            self.font_units_to_px(self.underline_thickness?, text_height),
+        ))
+    }
+
+    /// Strikeout `(position, thickness)` in pixels for the given `text_height`.
+    ///
+    /// The position is relative to the baseline where positive is up.
+    pub fn strikeout_px(&self, text_height: f32) -> Option<(f32, f32)> {
+        Some((
+            self.font_units_to_px(self.strikeout_position?, text_height),
+            self.font_units_to_px(self.strikeout_thickness?, text_height),
+        ))
+    }
 }
 
 pub struct ImtParsedGlyph {
@@ -217,15 +1359,153 @@ pub struct ImtParsedGlyph {
     pub max_x: f32,
     pub max_y: f32,
     pub hori_adv: f32,
-    pub geometry: Vec<ImtGeometry>,
+    /// Whether this is a combining mark, which is placed over the preceding glyph instead of
+    /// advancing. Given by the `GDEF` glyph classes, or for fonts without them whether the
+    /// character is a non-spacing mark.
+    pub is_mark: bool,
+    pub contours: Vec<ImtContour>,
+}
+
+impl ImtParsedGlyph {
+    /// The segments of all contours in order.
+    pub fn geometry(&self) -> impl Iterator<Item = &ImtGeometry> + '_ {
+        self.contours
+            .iter()
+            .flat_map(|contour| contour.geometry.iter())
+    }
+
+    /// The contours as closed paths in font units with y pointing up.
+    pub fn path(&self) -> Vec<ImtPathCmd> {
+        self.contours
+            .iter()
+            .flat_map(|contour| contour.path())
+            .collect()
+    }
+
+    /// The outline as the `d` attribute of an SVG path with coordinates rounded to `precision`
+    /// decimal places. Coordinates are in font units with y flipped to point down, so the
+    /// baseline is at zero and the glyph extends above it into negative y.
+    pub fn to_svg_path(&self, precision: usize) -> String {
+        // Adding zero turns negative zero positive, avoiding `-0` in the output.
+        let fmt = |point: &ImtPoint| {
+            format!(
+                "{:.*} {:.*}",
+                precision,
+                point.x + 0.0,
+                precision,
+                -point.y + 0.0
+            )
+        };
+
+        self.path()
+            .iter()
+            .map(|cmd| {
+                match cmd {
+                    ImtPathCmd::MoveTo(p) => format!("M{}", fmt(p)),
+                    ImtPathCmd::LineTo(p) => format!("L{}", fmt(p)),
+                    ImtPathCmd::QuadTo(c, p) => format!("Q{} {}", fmt(c), fmt(p)),
+                    ImtPathCmd::CubicTo(c1, c2, p) => {
+                        format!("C{} {} {}", fmt(c1), fmt(c2), fmt(p))
+                    },
+                    ImtPathCmd::Close => String::from("Z"),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Copy of this glyph with its outline transformed by `transform`.
+    ///
+    /// The transform is `[xx, xy, yx, yy, dx, dy]` in font units with y pointing up, where
+    /// `x' = xx * x + yx * y + dx` and `y' = xy * x + yy * y + dy`. For example `yx` of `0.2`
+    /// slants the glyph like synthetic italic. The bounds are recomputed from the transformed
+    /// outline while the advance is kept.
+    pub fn transformed(&self, transform: &[f32; 6]) -> ImtParsedGlyph {
+        let transform_points = |points: &[ImtPoint]| -> Vec<ImtPoint> {
+            points
+                .iter()
+                .map(|point| transform_point(transform, point.x, point.y))
+                .collect()
+        };
+
+        let contours: Vec<ImtContour> = self
+            .contours
+            .iter()
+            .map(|contour| {
+                ImtContour::new(
+                    contour
+                        .geometry
+                        .iter()
+                        .map(|geo| {
+                            match geo {
+                                ImtGeometry::Line(points) => {
+                                    let p = transform_points(points);
+                                    ImtGeometry::Line([p[0].clone(), p[1].clone()])
+                                },
+                                ImtGeometry::Curve(points) => {
+                                    let p = transform_points(points);
+                                    ImtGeometry::Curve([p[0].clone(), p[1].clone(), p[2].clone()])
+                                },
+                            }
+                        })
+                        .collect(),
+                )
+            })
+            .collect();
+
+        // Glyphs without an outline keep their transformed bounds. Otherwise anything the bounds
+        // extend past the outline, such as the widening of synthetic bold, is kept on the right.
+        let (points, bold_amt) = if contours.is_empty() {
+            (
+                transform_points(&[
+                    ImtPoint {
+                        x: self.min_x,
+                        y: self.min_y,
+                    },
+                    ImtPoint {
+                        x: self.max_x,
+                        y: self.max_y,
+                    },
+                ]),
+                0.0,
+            )
+        } else {
+            let outline_max_x = point_bounds(contour_points(&self.contours))[2];
+            let points = contour_points(&contours).cloned().collect();
+            (points, (self.max_x - outline_max_x).max(0.0))
+        };
+
+        let [min_x, min_y, max_x, max_y] = point_bounds(&points);
+
+        ImtParsedGlyph {
+            inner: self.inner.clone(),
+            min_x,
+            min_y,
+            max_x: max_x + bold_amt,
+            max_y,
+            hori_adv: self.hori_adv,
+            is_mark: self.is_mark,
+            contours,
+        }
+    }
 }
 
 impl ImtParserNonSend {
-    pub fn new(bytes: Vec<u8>) -> Result<Self, ImtError> {
+    pub fn new<B: AsRef<[u8]> + Send + 'static>(bytes: B) -> Result<Self, ImtError> {
+        Self::new_with_opts(bytes, &ImtParserOpts::default())
+    }
+
+    pub fn new_with_opts<B: AsRef<[u8]> + Send + 'static>(
+        bytes: B,
+        opts: &ImtParserOpts,
+    ) -> Result<Self, ImtError> {
+        // Boxed so that the data referenced by the scope doesn't move with the parser.
+        let bytes: Box<dyn AsRef<[u8]> + Send> = Box::new(bytes);
+
         let OpenTypeFont {
             scope,
             data,
-        } = ReadScope::new(unsafe { &*(bytes.as_ref() as *const _) })
+        } = ReadScope::new(unsafe { &*((*bytes).as_ref() as *const [u8]) })
             .read::<OpenTypeFont>()
             .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::File, e))?;
 
@@ -250,12 +1530,40 @@ impl ImtParserNonSend {
             .read::<Cmap>()
             .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?;
 
-        let cmap_sub = read_cmap_subtable(&cmap)
-            .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?
-            .ok_or(ImtError::src_and_ty(
-                ImtErrorSrc::Cmap,
-                ImtErrorTy::FileMissingSubTable,
-            ))?;
+        let preferred_sub = match opts.cmap_subtable {
+            Some((platform_id, encoding_id)) => {
+                cmap.find_subtable(platform_id, encoding_id)
+                    .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?
+                    .map(|sub| (ImtCmapEncoding::from_ids(platform_id, encoding_id), sub))
+            },
+            None => None,
+        };
+
+        let (cmap_encoding, cmap_sub) = match preferred_sub {
+            Some((encoding, sub)) => (encoding, sub),
+            None => {
+                let (encoding, sub) = read_cmap_subtable(&cmap)
+                    .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?
+                    .ok_or(ImtError::src_and_ty(
+                        ImtErrorSrc::Cmap,
+                        ImtErrorTy::FileMissingSubTable,
+                    ))?;
+
+                let encoding = match encoding {
+                    Encoding::Unicode => Some(ImtCmapEncoding::Unicode),
+                    Encoding::Symbol => Some(ImtCmapEncoding::Symbol),
+                    Encoding::AppleRoman => Some(ImtCmapEncoding::AppleRoman),
+                    _ => None,
+                };
+
+                (encoding, sub)
+            },
+        };
+
+        let cmap_encoding = cmap_encoding.ok_or(ImtError::src_and_ty(
+            ImtErrorSrc::Cmap,
+            ImtErrorTy::FileUnsupportedEncoding,
+        ))?;
 
         let maxp = otf
             .find_table_record(tag::MAXP)
@@ -348,6 +1656,26 @@ impl ImtParserNonSend {
             .read_dep::<GlyfTable>(unsafe { &*(&loca as *const _) })
             .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Glyf, e))?;
 
+        // The OS/2 & post tables only provide optional metrics & names, so a malformed table is
+        // treated as missing and the defaults are used.
+        let os2_op = otf.find_table_record(tag::OS_2).and_then(|v| {
+            v.read_table(&scope)
+                .and_then(|table| table.read_dep::<Os2>(v.length as usize))
+                .map_err(|e| {
+                    log::warn!("Ignoring malformed OS/2 table: {:?}", e);
+                })
+                .ok()
+        });
+
+        let post_op = otf.find_table_record(tag::POST).and_then(|v| {
+            v.read_table(&scope)
+                .and_then(|table| table.read::<PostTable>())
+                .map_err(|e| {
+                    log::warn!("Ignoring malformed post table: {:?}", e);
+                })
+                .ok()
+        });
+
         let gsub_op = match otf.find_table_record(tag::GSUB) {
             None => None,
             Some(v) => {
@@ -361,41 +1689,99 @@ impl ImtParserNonSend {
             },
         };
 
-        let default_dpi = 72.0;
-        let default_pixel_height = 1.0;
-        // TODO 1.00 should be 1.33 but why?
-        let scaler = ((default_pixel_height * 1.00) * default_dpi)
-            / (default_dpi * head.units_per_em as f32);
-        let line_gap = hhea.line_gap as f32 + hhea.ascender as f32
-            - (hhea.ascender as f32 + head.y_min as f32);
+        let scaler = em_scaler(head.units_per_em);
+
+        // Fonts setting USE_TYPO_METRICS intend the OS/2 typographic metrics to be used.
+        let (ascender, descender, line_gap) = os2_op
+            .as_ref()
+            .filter(|os2| opts.use_typo_metrics || os2.fs_selection & OS2_USE_TYPO_METRICS != 0)
+            .and_then(|os2| os2.version0.as_ref())
+            .map(|v| {
+                (
+                    v.s_typo_ascender as f32,
+                    v.s_typo_descender as f32,
+                    v.s_typo_line_gap as f32,
+                )
+            })
+            .unwrap_or((
+                hhea.ascender as f32,
+                hhea.descender as f32,
+                hhea.line_gap as f32,
+            ));
 
         let font_props = ImtFontProps {
             scaler,
-            // TODO: (head.units_per_em as f32 / 22.0).floor()
-            // 			This is needed to adjust the y_min for some reason
-            ascender: hhea.ascender as f32
-                + head.y_min as f32
-                + (head.units_per_em as f32 / 22.0).floor(),
-            descender: hhea.descender as f32,
+            ascender,
+            descender,
             line_gap,
             min_x: head.x_min as f32,
             max_x: head.x_max as f32,
             min_y: head.y_min as f32,
             max_y: head.y_max as f32,
+            bbox: [
+                head.x_min as f32,
+                head.y_min as f32,
+                head.x_max as f32,
+                head.y_max as f32,
+            ],
+            cap_height: os2_op
+                .as_ref()
+                .and_then(|os2| os2.version2to4.as_ref())
+                .map(|v| v.s_cap_height as f32),
+            x_height: os2_op
+                .as_ref()
+                .and_then(|os2| os2.version2to4.as_ref())
+                .map(|v| v.sx_height as f32),
+            is_monospace: post_op
+                .as_ref()
+                .map(|post| post.header.is_fixed_pitch != 0)
+                .unwrap_or(false),
+            underline_position: post_op
+                .as_ref()
+                .map(|post| post.header.underline_position as f32),
+            underline_thickness: post_op
+                .as_ref()
+                .map(|post| post.header.underline_thickness as f32),
+            strikeout_position: os2_op.as_ref().map(|os2| os2.y_strikeout_position as f32),
+            strikeout_thickness: os2_op.as_ref().map(|os2| os2.y_strikeout_size as f32),
+            cmap_encoding,
+        };
+
+        let table_data = |tag: u32| {
+            otf.find_table_record(tag)
+                .and_then(|record| record.read_table(&scope).ok())
+                .map(|table| table.data().to_vec())
+                .unwrap_or_default()
         };
 
+        let hinter = Hinter::new(
+            head.units_per_em,
+            table_data(TAG_FPGM),
+            table_data(TAG_PREP),
+            &table_data(TAG_CVT),
+        );
+
+        let kern_pairs = parse_kern_pairs(&table_data(TAG_KERN));
+        let variation_sequences = parse_variation_sequences(&table_data(tag::CMAP));
+
         Ok(ImtParserNonSend {
-            parsed_glyphs: BTreeMap::new(),
+            parsed_glyphs: Arc::new(Mutex::new(BTreeMap::new())),
+            hinter,
+            kern_pairs,
+            variation_sequences,
+            fallback: None,
             bytes,
             scope,
             head,
             maxp,
             cmap,
-            cmap_sub: cmap_sub.1,
+            cmap_sub,
             hhea,
             hmtx,
             loca,
             glyf,
+            post_op,
+            glyph_names: None,
             gdef_op,
             gpos_op,
             gsub_op,
@@ -407,22 +1793,75 @@ impl ImtParserNonSend {
         self.font_props.clone()
     }
 
+    pub fn font_hash(&self) -> u64 {
+        fnv1a_64((*self.bytes).as_ref())
+    }
+
+    pub fn glyph_name(&self, index: u16) -> Option<String> {
+        self.post_op
+            .as_ref()?
+            .glyph_name(index)
+            .ok()
+            .flatten()
+            .map(|name| name.to_string())
+    }
+
+    pub fn glyph_index_by_name(&mut self, name: &str) -> Option<u16> {
+        // Names are looked up by index in the post table, so build the reverse map once.
+        if self.glyph_names.is_none() {
+            let mut glyph_names = HashMap::new();
+
+            for index in 0..self.maxp.num_glyphs {
+                if let Some(glyph_name) = self.glyph_name(index) {
+                    glyph_names.entry(glyph_name).or_insert(index);
+                }
+            }
+
+            self.glyph_names = Some(glyph_names);
+        }
+
+        self.glyph_names.as_ref().unwrap().get(name).copied()
+    }
+
+    pub fn missing_chars(&self, text: &str) -> Result<Vec<char>, ImtError> {
+        let mut missing = Vec::new();
+
+        for c in text.chars() {
+            if c.is_control() || is_variation_selector(c) || missing.contains(&c) {
+                continue;
+            }
+
+            if self.map_char(c)?.is_none() {
+                missing.push(c);
+            }
+        }
+
+        Ok(missing)
+    }
+
     pub fn retreive_info(
         &mut self,
         raw_glyphs: Vec<RawGlyph<()>>,
         script: ImtScript,
         lang: ImtLang,
+        features: &[(ImtFeatureTag, bool)],
     ) -> Result<Vec<Info>, ImtError> {
         let mut infos = Info::init_from_glyphs(self.gdef_op.as_ref(), raw_glyphs);
 
         if let Some(gpos) = self.gpos_op.take() {
             let gpos_rc = Rc::new(gpos);
+            let kerning = features
+                .iter()
+                .rev()
+                .find(|(tag, _)| *tag == ImtFeatureTag::KERN)
+                .map(|&(_, enable)| enable)
+                .unwrap_or(true);
 
             gpos::apply(
                 &gpos_rc,
                 self.gdef_op.as_ref(),
-                true,
-                &gsub::Features::Mask(gsub::FeatureMask::default()),
+                kerning,
+                &gsub::Features::Mask(apply_features(gsub::FeatureMask::default(), features)),
                 script.tag(),
                 Some(lang.tag()),
                 &mut infos,
@@ -435,20 +1874,100 @@ impl ImtParserNonSend {
         Ok(infos)
     }
 
+    fn map_char(&self, c: char) -> Result<Option<u16>, ImtError> {
+        let map = |code: u32| {
+            self.cmap_sub
+                .map_glyph(code)
+                .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))
+        };
+
+        let code = c as u32;
+
+        match self.font_props.cmap_encoding {
+            ImtCmapEncoding::Unicode => map(code),
+            // Symbol fonts conventionally place their glyphs at U+F000 plus the character code.
+            ImtCmapEncoding::Symbol => {
+                match map(code)? {
+                    Some(index) => Ok(Some(index)),
+                    None if code <= 0xFF => map(0xF000 + code),
+                    None => Ok(None),
+                }
+            },
+            // Only the ASCII range of Mac Roman matches unicode.
+            ImtCmapEncoding::AppleRoman => {
+                if code < 0x80 {
+                    map(code)
+                } else {
+                    Ok(None)
+                }
+            },
+        }
+    }
+
+    pub fn cmap_subtables(&self) -> Vec<ImtCmapSubtable> {
+        self.cmap
+            .encoding_records
+            .iter()
+            .map(|record| {
+                ImtCmapSubtable {
+                    platform_id: record.platform_id,
+                    encoding_id: record.encoding_id,
+                    encoding: ImtCmapEncoding::from_ids(record.platform_id, record.encoding_id),
+                }
+            })
+            .collect()
+    }
+
+    pub fn variation_glyph(&self, base: char, selector: char) -> Result<Option<u16>, ImtError> {
+        let key = (base as u32, selector as u32);
+
+        if let Some(index) = self.variation_sequences.non_default.get(&key) {
+            return Ok(Some(*index));
+        }
+
+        let default =
+            self.variation_sequences
+                .default
+                .iter()
+                .any(|&(range_selector, first, last)| {
+                    range_selector == key.1 && key.0 >= first && key.0 <= last
+                });
+
+        match default {
+            true => self.map_char(base),
+            false => Ok(None),
+        }
+    }
+
+    /// Glyph index the fallback callback provides for `c`, if any.
+    fn fallback_index(&mut self, c: char) -> Result<Option<u16>, ImtError> {
+        let fallback = match self.fallback.as_mut() {
+            Some(fallback) => fallback(c),
+            None => return Ok(None),
+        };
+
+        match fallback {
+            Some(ImtGlyphFallback::Char(fallback_c)) => self.map_char(fallback_c),
+            Some(ImtGlyphFallback::Index(index)) if index < self.maxp.num_glyphs => Ok(Some(index)),
+            _ => Ok(None),
+        }
+    }
+
     fn glyph_for_char(&mut self, c: char) -> Result<RawGlyph<()>, ImtError> {
-        let index = self
-            .cmap_sub
-            .map_glyph(c as u32)
-            .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?
-            .unwrap_or(
-                self.cmap_sub
-                    .map_glyph('?' as u32)
-                    .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Cmap, e))?
-                    .ok_or(ImtError::src_and_ty(
-                        ImtErrorSrc::Cmap,
-                        ImtErrorTy::MissingGlyph,
-                    ))?,
-            );
+        let index = match self.map_char(c)? {
+            Some(index) => index,
+            None => {
+                match self.fallback_index(c)? {
+                    Some(index) => index,
+                    None => {
+                        self.map_char('?')?.ok_or(ImtError::src_and_ty(
+                            ImtErrorSrc::Cmap,
+                            ImtErrorTy::MissingGlyph,
+                        ))?
+                    },
+                }
+            },
+        };
 
         Ok(RawGlyph {
             unicodes: [c].into(),
@@ -465,19 +1984,83 @@ impl ImtParserNonSend {
         })
     }
 
+    /// Substitute a lowercase glyph the font didn't replace with its capital, marking it as
+    /// `small_caps` so that it is scaled down when parsed.
+    fn synthesize_small_caps(&self, glyph: &mut RawGlyph<()>) -> Result<(), ImtError> {
+        let c = match glyph.glyph_origin {
+            GlyphOrigin::Char(c) if c.is_lowercase() => c,
+            _ => return Ok(()),
+        };
+
+        if self.map_char(c)? != Some(glyph.glyph_index) {
+            return Ok(());
+        }
+
+        let mut upper = c.to_uppercase();
+
+        if let (Some(upper_c), None) = (upper.next(), upper.next()) {
+            if let Some(index) = self.map_char(upper_c)? {
+                glyph.glyph_index = index;
+                glyph.small_caps = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Scale of synthetic small caps, the ratio of the x-height to the cap height.
+    fn small_caps_scale(&self) -> f32 {
+        match (self.font_props.x_height, self.font_props.cap_height) {
+            (Some(x_height), Some(cap_height)) if x_height > 0.0 && cap_height > 0.0 => {
+                x_height / cap_height
+            },
+            _ => SYNTHETIC_SMALL_CAPS_SCALE,
+        }
+    }
+
     pub fn retreive_text<T: AsRef<str>>(
         &mut self,
         text: T,
         script: ImtScript,
         lang: ImtLang,
+        opts: ImtTextOpts,
     ) -> Result<Vec<Arc<ImtParsedGlyph>>, ImtError> {
+        let ImtTextOpts {
+            fake_bold,
+            fake_italic,
+            small_caps,
+            features: opt_features,
+        } = opts;
+
         let mut glyphs = Vec::new();
 
         for c in text.as_ref().replace("\r", "").chars() {
+            // Selectors aren't displayed, but are kept on the glyph of the character they follow.
+            if is_variation_selector(c) {
+                if let Some(glyph) = glyphs.last_mut() {
+                    glyph.variation = VariationSelector::try_from(c).ok();
+
+                    if let GlyphOrigin::Char(base) = glyph.glyph_origin {
+                        if let Some(index) = self.variation_glyph(base, c)? {
+                            glyph.glyph_index = index;
+                        }
+                    }
+                }
+
+                continue;
+            }
+
             glyphs.push(self.glyph_for_char(c)?);
         }
 
         let sub_glyph = self.glyph_for_char('?')?;
+        let mut features = gsub::FeatureMask::default();
+
+        if small_caps {
+            features |= gsub::FeatureMask::SMCP;
+        }
+
+        let features = substitution_features(features, &opt_features);
 
         if let &Some(ref gsub) = &self.gsub_op {
             gsub::apply(
@@ -486,7 +2069,7 @@ impl ImtParserNonSend {
                 self.gdef_op.as_ref(),
                 script.tag(),
                 Some(lang.tag()),
-                &gsub::Features::Mask(gsub::FeatureMask::default()),
+                &features,
                 self.maxp.num_glyphs,
                 &mut glyphs,
             )
@@ -495,202 +2078,675 @@ impl ImtParserNonSend {
 
         let mut imt_raw_glyphs = Vec::new();
 
-        for glyph in glyphs {
-            let index = glyph.glyph_index;
-
-            if self.parsed_glyphs.get(&index).is_none() {
-                let mut geometry_indexes: Vec<(u16, f32, f32)> = vec![(index, 0.0, 0.0)];
-                let mut geometry = Vec::new();
-                let mut min_x = None;
-                let mut min_y = None;
-                let mut max_x = None;
-                let mut max_y = None;
-
-                while let Some((geometry_index, gox, goy)) = geometry_indexes.pop() {
-                    let glyf_record = self.glyf.records.get_mut(geometry_index as usize).ok_or(
-                        ImtError::src_and_ty(ImtErrorSrc::Glyf, ImtErrorTy::MissingGlyph),
-                    )?;
-
-                    if let Some(parsed_record) = match &glyf_record {
-                        &GlyfRecord::Present {
-                            ref scope, ..
-                        } => {
-                            Some(GlyfRecord::Parsed(scope.read::<glyf::Glyph>().map_err(
-                                |e| ImtError::allsorts_parse(ImtErrorSrc::Glyf, e),
-                            )?))
-                        },
-                        _ => None,
-                    } {
-                        *glyf_record = parsed_record;
-                    }
+        for mut glyph in glyphs {
+            glyph.fake_bold = fake_bold;
+            glyph.fake_italic = fake_italic;
 
-                    match &glyf_record {
-                        &GlyfRecord::Parsed(ref glfy_glyph) => {
-                            let g_min_x = glfy_glyph.bounding_box.x_min as f32 - gox as f32;
-                            let g_min_y = glfy_glyph.bounding_box.y_min as f32 - goy as f32;
-                            let g_max_x = glfy_glyph.bounding_box.x_max as f32 - gox as f32;
-                            let g_max_y = glfy_glyph.bounding_box.y_max as f32 - goy as f32;
+            if small_caps {
+                self.synthesize_small_caps(&mut glyph)?;
+            }
 
-                            if min_x.is_none() || g_min_x < *min_x.as_ref().unwrap() {
-                                min_x = Some(g_min_x);
-                            }
+            imt_raw_glyphs.push(self.parse_glyph(glyph)?);
+        }
 
-                            if min_y.is_none() || g_min_y < *min_y.as_ref().unwrap() {
-                                min_y = Some(g_min_y);
-                            }
+        Ok(imt_raw_glyphs)
+    }
 
-                            if max_x.is_none() || g_max_x > *max_x.as_ref().unwrap() {
-                                max_x = Some(g_max_x);
-                            }
+    pub fn retrieve_glyph(&mut self, index: u16) -> Result<Arc<ImtParsedGlyph>, ImtError> {
+        if index >= self.maxp.num_glyphs {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Glyf,
+                ImtErrorTy::MissingGlyph,
+            ));
+        }
 
-                            if max_y.is_none() || g_max_y > *max_y.as_ref().unwrap() {
-                                max_y = Some(g_max_y);
-                            }
+        self.parse_glyph(direct_glyph(index))
+    }
 
-                            match &glfy_glyph.data {
-                                &glyf::GlyphData::Simple(ref simple) => {
-                                    let mut contour = Vec::new();
-
-                                    for i in 0..simple.coordinates.len() {
-                                        contour.push((
-                                            i,
-                                            simple.coordinates[i].0 as f32,
-                                            simple.coordinates[i].1 as f32,
-                                        ));
-
-                                        if simple.end_pts_of_contours.contains(&(i as u16)) {
-                                            for j in 0..contour.len() {
-                                                if !simple.flags[contour[j].0].is_on_curve() {
-                                                    let p_i = if j == 0 {
-                                                        contour.len() - 1
-                                                    } else {
-                                                        j - 1
-                                                    };
-                                                    let n_i = if j == contour.len() - 1 {
-                                                        0
-                                                    } else {
-                                                        j + 1
-                                                    };
-
-                                                    let a = if simple.flags[contour[p_i].0]
-                                                        .is_on_curve()
-                                                    {
-                                                        (contour[p_i].1, contour[p_i].2)
-                                                    } else {
-                                                        (
-                                                            (contour[p_i].1 + contour[j].1) / 2.0,
-                                                            (contour[p_i].2 + contour[j].2) / 2.0,
-                                                        )
-                                                    };
-
-                                                    let c = if simple.flags[contour[n_i].0]
-                                                        .is_on_curve()
-                                                    {
-                                                        (contour[n_i].1, contour[n_i].2)
-                                                    } else {
-                                                        (
-                                                            (contour[n_i].1 + contour[j].1) / 2.0,
-                                                            (contour[n_i].2 + contour[j].2) / 2.0,
-                                                        )
-                                                    };
-
-                                                    let b = (contour[j].1, contour[j].2);
-
-                                                    geometry.push(ImtGeometry::Curve([
-                                                        ImtPoint {
-                                                            x: a.0 as f32 + gox as f32,
-                                                            y: a.1 as f32 + goy as f32,
-                                                        },
-                                                        ImtPoint {
-                                                            x: b.0 as f32 + gox as f32,
-                                                            y: b.1 as f32 + goy as f32,
-                                                        },
-                                                        ImtPoint {
-                                                            x: c.0 as f32 + gox as f32,
-                                                            y: c.1 as f32 + goy as f32,
-                                                        },
-                                                    ]));
-                                                } else {
-                                                    let n_i = if j == contour.len() - 1 {
-                                                        0
-                                                    } else {
-                                                        j + 1
-                                                    };
-
-                                                    if simple.flags[contour[n_i].0].is_on_curve() {
-                                                        geometry.push(ImtGeometry::Line([
-                                                            ImtPoint {
-                                                                x: contour[j].1 as f32 + gox as f32,
-                                                                y: contour[j].2 as f32 + goy as f32,
-                                                            },
-                                                            ImtPoint {
-                                                                x: contour[n_i].1 as f32
-                                                                    + gox as f32,
-                                                                y: contour[n_i].2 as f32
-                                                                    + goy as f32,
-                                                            },
-                                                        ]));
-                                                    }
-                                                }
-                                            }
-
-                                            contour.clear();
-                                        }
-                                    }
-                                },
-                                glyf::GlyphData::Composite {
-                                    glyphs, ..
-                                } => {
-                                    for glyph in glyphs {
-                                        let x: f32 = match glyph.argument1 {
-                                            CompositeGlyphArgument::U8(v) => v as f32,
-                                            CompositeGlyphArgument::I8(v) => v as f32,
-                                            CompositeGlyphArgument::U16(v) => v as f32,
-                                            CompositeGlyphArgument::I16(v) => v as f32,
-                                        };
-
-                                        let y: f32 = match glyph.argument2 {
-                                            CompositeGlyphArgument::U8(v) => v as f32,
-                                            CompositeGlyphArgument::I8(v) => v as f32,
-                                            CompositeGlyphArgument::U16(v) => v as f32,
-                                            CompositeGlyphArgument::I16(v) => v as f32,
-                                        };
-
-                                        geometry_indexes.push((glyph.glyph_index, x, y));
-                                    }
-                                },
-                            };
+    pub fn kerning(
+        &mut self,
+        left: u16,
+        right: u16,
+        script: ImtScript,
+        lang: ImtLang,
+    ) -> Result<f32, ImtError> {
+        if self.gpos_op.is_none() {
+            return Ok(self.kern_pairs.get(&(left, right)).copied().unwrap_or(0) as f32);
+        }
+
+        let infos = self.retreive_info(
+            vec![direct_glyph(left), direct_glyph(right)],
+            script,
+            lang,
+            &[],
+        )?;
+
+        Ok(infos[0].kerning as f32)
+    }
+
+    pub fn hint_glyph(
+        &mut self,
+        parsed: &ImtParsedGlyph,
+        text_height: f32,
+    ) -> Result<Option<Arc<ImtParsedGlyph>>, ImtError> {
+        if parsed.inner.fake_italic || parsed.inner.small_caps {
+            return Ok(None);
+        }
+
+        let index = parsed.inner.glyph_index;
+        self.glyf_record(index)?;
+
+        let simple = match self.glyf.records.get(index as usize) {
+            Some(GlyfRecord::Parsed(glyph)) => {
+                match &glyph.data {
+                    glyf::GlyphData::Simple(simple) => simple,
+                    _ => return Ok(None),
+                }
+            },
+            _ => return Ok(None),
+        };
+
+        let bold_amt = if parsed.inner.fake_bold {
+            self.head.units_per_em as f32 * SYNTHETIC_BOLD_EM
+        } else {
+            0.0
+        };
+
+        let points: Vec<(f32, f32)> = simple
+            .coordinates
+            .iter()
+            .map(|point| (point.0 as f32, point.1 as f32))
+            .collect();
+        let on_curve: Vec<bool> = simple.flags.iter().map(|flag| flag.is_on_curve()).collect();
+
+        let hinted = match self.hinter.hint(
+            text_height,
+            &points,
+            &on_curve,
+            &simple.end_pts_of_contours,
+            simple.instructions,
+            parsed.hori_adv - bold_amt,
+        ) {
+            Some(hinted) => hinted,
+            None => return Ok(None),
+        };
+
+        let points: Vec<(f32, f32)> = hinted.iter().map(|point| (point.x, point.y)).collect();
+        let on_curve: Vec<bool> = hinted.iter().map(|point| point.on_curve).collect();
+        let mut contours = Vec::new();
+
+        simple_glyph_contours(
+            &points,
+            &on_curve,
+            &simple.end_pts_of_contours,
+            &IDENTITY_TRANSFORM,
+            &mut contours,
+        );
+
+        let [min_x, min_y, max_x, max_y] = glyph_bounds(None, &contours);
+
+        Ok(Some(Arc::new(ImtParsedGlyph {
+            inner: parsed.inner.clone(),
+            min_x,
+            min_y,
+            max_x: max_x + bold_amt,
+            max_y,
+            hori_adv: parsed.hori_adv,
+            is_mark: parsed.is_mark,
+            contours,
+        })))
+    }
+
+    /// The `glyf` record of a glyph, parsing it if it hasn't been yet.
+    fn glyf_record(&mut self, index: u16) -> Result<&GlyfRecord<'static>, ImtError> {
+        let glyf_record = self
+            .glyf
+            .records
+            .get_mut(index as usize)
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Glyf,
+                ImtErrorTy::MissingGlyph,
+            ))?;
+
+        if let Some(parsed_record) = match &glyf_record {
+            &GlyfRecord::Present {
+                ref scope, ..
+            } => {
+                Some(GlyfRecord::Parsed(scope.read::<glyf::Glyph>().map_err(
+                    |e| ImtError::allsorts_parse(ImtErrorSrc::Glyf, e),
+                )?))
+            },
+            _ => None,
+        } {
+            *glyf_record = parsed_record;
+        }
+
+        Ok(&*glyf_record)
+    }
+
+    /// Whether the glyph is a combining mark. Without `GDEF` glyph classes this falls back to
+    /// the bidi class of its character, so glyphs retrieved by index are never marks.
+    fn is_mark(&self, glyph: &RawGlyph<()>) -> bool {
+        let classdef = self
+            .gdef_op
+            .as_ref()
+            .and_then(|gdef| gdef.opt_glyph_classdef.as_ref());
+
+        match classdef {
+            Some(classdef) => classdef.glyph_class_value(glyph.glyph_index) == GDEF_MARK_CLASS,
+            None => {
+                glyph
+                    .unicodes
+                    .first()
+                    .map(|c| bidi_class(*c) == BidiClass::NSM)
+                    .unwrap_or(false)
+            },
+        }
+    }
+
+    /// Parse the geometry of a glyph, reusing a previously parsed glyph if present.
+    fn parse_glyph(&mut self, glyph: RawGlyph<()>) -> Result<Arc<ImtParsedGlyph>, ImtError> {
+        let fake_bold = glyph.fake_bold;
+        let fake_italic = glyph.fake_italic;
+        let index = glyph.glyph_index;
+        let key = (index, fake_bold, fake_italic, glyph.small_caps);
+
+        if let Some(parsed) = self.parsed_glyphs.lock().get(&key).cloned() {
+            return Ok(self.with_occurrence(parsed, glyph));
+        }
+
+        let mut geometry_indexes: Vec<(u16, ComponentTransform)> =
+            vec![(index, IDENTITY_TRANSFORM)];
+        let mut contours = Vec::new();
+        // Corners of each component's bounding box, transformed into the glyph's space.
+        let mut bbox_corners = Vec::new();
+
+        while let Some((geometry_index, transform)) = geometry_indexes.pop() {
+            match self.glyf_record(geometry_index)? {
+                &GlyfRecord::Parsed(ref glfy_glyph) => {
+                    let bbox = &glfy_glyph.bounding_box;
+
+                    bbox_corners.extend(
+                        [
+                            (bbox.x_min, bbox.y_min),
+                            (bbox.x_max, bbox.y_min),
+                            (bbox.x_min, bbox.y_max),
+                            (bbox.x_max, bbox.y_max),
+                        ]
+                        .map(|(x, y)| transform_point(&transform, x as f32, y as f32)),
+                    );
+
+                    match &glfy_glyph.data {
+                        &glyf::GlyphData::Simple(ref simple) => {
+                            let points: Vec<(f32, f32)> = simple
+                                .coordinates
+                                .iter()
+                                .map(|point| (point.0 as f32, point.1 as f32))
+                                .collect();
+                            let on_curve: Vec<bool> =
+                                simple.flags.iter().map(|flag| flag.is_on_curve()).collect();
+
+                            simple_glyph_contours(
+                                &points,
+                                &on_curve,
+                                &simple.end_pts_of_contours,
+                                &transform,
+                                &mut contours,
+                            );
+                        },
+                        glyf::GlyphData::Composite {
+                            glyphs, ..
+                        } => {
+                            for glyph in glyphs {
+                                // The arguments are otherwise indexes of points to align.
+                                if !glyph.flags.contains(CompositeGlyphFlag::ARGS_ARE_XY_VALUES) {
+                                    return Err(ImtError::src_and_ty(
+                                        ImtErrorSrc::Glyf,
+                                        ImtErrorTy::Unimplemented,
+                                    ));
+                                }
+
+                                let x: f32 = match glyph.argument1 {
+                                    CompositeGlyphArgument::U8(v) => v as f32,
+                                    CompositeGlyphArgument::I8(v) => v as f32,
+                                    CompositeGlyphArgument::U16(v) => v as f32,
+                                    CompositeGlyphArgument::I16(v) => v as f32,
+                                };
+
+                                let y: f32 = match glyph.argument2 {
+                                    CompositeGlyphArgument::U8(v) => v as f32,
+                                    CompositeGlyphArgument::I8(v) => v as f32,
+                                    CompositeGlyphArgument::U16(v) => v as f32,
+                                    CompositeGlyphArgument::I16(v) => v as f32,
+                                };
+
+                                let (xx, xy, yx, yy) = match glyph.scale {
+                                    None => (1.0, 0.0, 0.0, 1.0),
+                                    Some(CompositeGlyphScale::Scale(s)) => {
+                                        let s = f32::from(s);
+                                        (s, 0.0, 0.0, s)
+                                    },
+                                    Some(CompositeGlyphScale::XY {
+                                        x_scale,
+                                        y_scale,
+                                    }) => (f32::from(x_scale), 0.0, 0.0, f32::from(y_scale)),
+                                    Some(CompositeGlyphScale::Matrix(m)) => {
+                                        (
+                                            f32::from(m[0][0]),
+                                            f32::from(m[0][1]),
+                                            f32::from(m[1][0]),
+                                            f32::from(m[1][1]),
+                                        )
+                                    },
+                                };
+
+                                let scaled_offset = glyph
+                                    .flags
+                                    .contains(CompositeGlyphFlag::SCALED_COMPONENT_OFFSET)
+                                    && !glyph
+                                        .flags
+                                        .contains(CompositeGlyphFlag::UNSCALED_COMPONENT_OFFSET);
+
+                                geometry_indexes.push((
+                                    glyph.glyph_index,
+                                    combine_transforms(
+                                        &transform,
+                                        &component_transform([xx, xy, yx, yy], x, y, scaled_offset),
+                                    ),
+                                ));
+                            }
                         },
-                        &GlyfRecord::Empty => continue,
-                        &GlyfRecord::Present {
-                            ..
-                        } => panic!("Glyph should already be parsed!"),
                     };
+                },
+                &GlyfRecord::Empty => continue,
+                &GlyfRecord::Present {
+                    ..
+                } => panic!("Glyph should already be parsed!"),
+            };
+        }
+
+        let mut hori_adv =
+            self.hmtx
+                .horizontal_advance(index, self.hhea.num_h_metrics)
+                .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Glyph, e))? as f32;
+
+        let bbox = (!bbox_corners.is_empty()).then(|| point_bounds(&bbox_corners));
+
+        let [mut min_x, mut min_y, mut max_x, mut max_y] = glyph_bounds(bbox, &contours);
+
+        if glyph.small_caps {
+            let scale = self.small_caps_scale();
+
+            for contour in contours.iter_mut() {
+                for geo in contour.geometry.iter_mut() {
+                    let points: &mut [ImtPoint] = match geo {
+                        ImtGeometry::Line(points) => points,
+                        ImtGeometry::Curve(points) => points,
+                    };
+
+                    for point in points.iter_mut() {
+                        point.x *= scale;
+                        point.y *= scale;
+                    }
                 }
 
-                let hori_adv = self
-                    .hmtx
-                    .horizontal_advance(index, self.hhea.num_h_metrics)
-                    .map_err(|e| ImtError::allsorts_parse(ImtErrorSrc::Glyph, e))?
-                    as f32;
-
-                self.parsed_glyphs.insert(
-                    index,
-                    Arc::new(ImtParsedGlyph {
-                        inner: glyph,
-                        min_x: min_x.unwrap_or(0.0),
-                        min_y: min_y.unwrap_or(0.0),
-                        max_x: max_x.unwrap_or(0.0),
-                        max_y: max_y.unwrap_or(0.0),
-                        hori_adv,
-                        geometry,
-                    }),
-                );
+                contour.signed_area *= scale * scale;
             }
 
-            imt_raw_glyphs.push(self.parsed_glyphs.get(&index).unwrap().clone());
+            min_x *= scale;
+            min_y *= scale;
+            max_x *= scale;
+            max_y *= scale;
+            hori_adv *= scale;
         }
 
-        Ok(imt_raw_glyphs)
+        if fake_italic {
+            // Shearing doesn't change the area of the contours.
+            for geo in contours
+                .iter_mut()
+                .flat_map(|contour| contour.geometry.iter_mut())
+            {
+                let points: &mut [ImtPoint] = match geo {
+                    ImtGeometry::Line(points) => points,
+                    ImtGeometry::Curve(points) => points,
+                };
+
+                for point in points.iter_mut() {
+                    point.x += point.y * SYNTHETIC_ITALIC_SHEAR;
+                }
+            }
+
+            min_x += min_y * SYNTHETIC_ITALIC_SHEAR;
+            max_x += max_y * SYNTHETIC_ITALIC_SHEAR;
+        }
+
+        if fake_bold {
+            // The coverage is dilated to the right when rastered, so only the right
+            // side of the bounds and the advance need to grow.
+            let bold_amt = self.head.units_per_em as f32 * SYNTHETIC_BOLD_EM;
+            max_x += bold_amt;
+            hori_adv += bold_amt;
+        }
+
+        let is_mark = self.is_mark(&glyph);
+
+        // Another parser sharing these glyphs may have parsed it in the mean time, keep
+        // theirs so that all users share the same allocation.
+        let parsed = self
+            .parsed_glyphs
+            .lock()
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(ImtParsedGlyph {
+                    inner: glyph.clone(),
+                    min_x,
+                    min_y,
+                    max_x,
+                    max_y,
+                    hori_adv,
+                    is_mark,
+                    contours,
+                })
+            })
+            .clone();
+
+        Ok(self.with_occurrence(parsed, glyph))
+    }
+
+    /// The cache only identifies a glyph's geometry, while the `RawGlyph` also carries the text
+    /// it was mapped from. Reuse a cached glyph only if that text matches, otherwise copy its
+    /// geometry around `glyph`.
+    fn with_occurrence(
+        &self,
+        parsed: Arc<ImtParsedGlyph>,
+        glyph: RawGlyph<()>,
+    ) -> Arc<ImtParsedGlyph> {
+        let cached = &parsed.inner;
+
+        if cached.unicodes[..] == glyph.unicodes[..]
+            && cached.glyph_origin == glyph.glyph_origin
+            && cached.liga_component_pos == glyph.liga_component_pos
+            && cached.multi_subst_dup == glyph.multi_subst_dup
+            && cached.is_vert_alt == glyph.is_vert_alt
+            && cached.variation == glyph.variation
+        {
+            return parsed;
+        }
+
+        Arc::new(ImtParsedGlyph {
+            is_mark: self.is_mark(&glyph),
+            inner: glyph,
+            min_x: parsed.min_x,
+            min_y: parsed.min_y,
+            max_x: parsed.max_x,
+            max_y: parsed.max_y,
+            hori_adv: parsed.hori_adv,
+            contours: parsed.contours.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_font::TestFont;
+
+    #[test]
+    fn component_offsets() {
+        let scale = [2.0, 0.0, 0.0, 3.0];
+
+        // Unscaled offsets are applied after scaling the component.
+        let unscaled = component_transform(scale, 10.0, 20.0, false);
+        let point = transform_point(&unscaled, 1.0, 1.0);
+        assert_eq!((point.x, point.y), (12.0, 23.0));
+
+        // Scaled offsets are scaled along with the component.
+        let scaled = component_transform(scale, 10.0, 20.0, true);
+        let point = transform_point(&scaled, 1.0, 1.0);
+        assert_eq!((point.x, point.y), (22.0, 63.0));
+
+        // A rotation also rotates a scaled offset.
+        let rotate = [0.0, 1.0, -1.0, 0.0];
+        assert_eq!(
+            component_transform(rotate, 10.0, 0.0, true),
+            [0.0, 1.0, -1.0, 0.0, 0.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn nested_components() {
+        let parent = component_transform([2.0, 0.0, 0.0, 2.0], 100.0, 0.0, false);
+        let child = component_transform([1.0, 0.0, 0.0, 1.0], 5.0, 5.0, false);
+        let combined = combine_transforms(&parent, &child);
+
+        // The child's offset is in the parent's space, so it's scaled by the parent.
+        let point = transform_point(&combined, 1.0, 1.0);
+        assert_eq!((point.x, point.y), (112.0, 12.0));
+
+        let direct = transform_point(&parent, 6.0, 6.0);
+        assert_eq!((point.x, point.y), (direct.x, direct.y));
+    }
+
+    fn point(x: f32, y: f32) -> ImtPoint {
+        ImtPoint {
+            x,
+            y,
+        }
+    }
+
+    /// A closed triangle.
+    fn triangle(a: ImtPoint, b: ImtPoint, c: ImtPoint) -> ImtContour {
+        ImtContour {
+            geometry: vec![
+                ImtGeometry::Line([a.clone(), b.clone()]),
+                ImtGeometry::Line([b, c.clone()]),
+                ImtGeometry::Line([c, a]),
+            ],
+            signed_area: 0.0,
+        }
+    }
+
+    /// Corners of `bbox` moved by `transform`, as collected for composite glyphs.
+    fn bbox_corners(bbox: [f32; 4], transform: &ComponentTransform) -> Vec<ImtPoint> {
+        [
+            (bbox[0], bbox[1]),
+            (bbox[2], bbox[1]),
+            (bbox[0], bbox[3]),
+            (bbox[2], bbox[3]),
+        ]
+        .into_iter()
+        .map(|(x, y)| transform_point(transform, x, y))
+        .collect()
+    }
+
+    #[test]
+    fn space_bounds() {
+        // Glyphs without an outline are empty at the origin, whatever their bounding box.
+        assert_eq!(glyph_bounds(None, &[]), [0.0; 4]);
+        assert_eq!(glyph_bounds(Some([0.0; 4]), &[]), [0.0; 4]);
+        assert_eq!(glyph_bounds(Some([50.0, 60.0, 10.0, 20.0]), &[]), [0.0; 4]);
+    }
+
+    #[test]
+    fn zero_contour_composite_bounds() {
+        // A composite of two spaces has component boxes, but no outline.
+        let offset = component_transform([1.0, 0.0, 0.0, 1.0], 500.0, 0.0, false);
+        let mut corners = bbox_corners([0.0, 0.0, 0.0, 0.0], &IDENTITY_TRANSFORM);
+        corners.extend(bbox_corners([0.0, 0.0, 0.0, 0.0], &offset));
+
+        let bbox = point_bounds(&corners);
+        assert_eq!(bbox, [0.0, 0.0, 500.0, 0.0]);
+        assert_eq!(glyph_bounds(Some(bbox), &[]), [0.0; 4]);
+    }
+
+    #[test]
+    fn composite_bounds() {
+        let contours = [
+            triangle(point(0.0, 0.0), point(100.0, 0.0), point(50.0, 200.0)),
+            triangle(point(600.0, 0.0), point(700.0, 0.0), point(650.0, 200.0)),
+        ];
+
+        let offset = component_transform([1.0, 0.0, 0.0, 1.0], 600.0, 0.0, false);
+        let mut corners = bbox_corners([0.0, 0.0, 100.0, 200.0], &IDENTITY_TRANSFORM);
+        corners.extend(bbox_corners([0.0, 0.0, 100.0, 200.0], &offset));
+
+        let bbox = point_bounds(&corners);
+        assert_eq!(bbox, [0.0, 0.0, 700.0, 200.0]);
+        assert_eq!(glyph_bounds(Some(bbox), &contours), bbox);
+    }
+
+    #[test]
+    fn degenerate_bbox_bounds() {
+        let contours = [triangle(
+            point(10.0, -20.0),
+            point(300.0, 0.0),
+            point(150.0, 700.0),
+        )];
+
+        let outline = [10.0, -20.0, 300.0, 700.0];
+
+        // Well-formed boxes are kept, even when they differ from the outline.
+        assert_eq!(
+            glyph_bounds(Some([0.0, -50.0, 320.0, 710.0]), &contours),
+            [0.0, -50.0, 320.0, 710.0]
+        );
+
+        // Missing, inverted or non-finite boxes are replaced by the outline's bounds.
+        assert_eq!(glyph_bounds(None, &contours), outline);
+        assert_eq!(
+            glyph_bounds(Some([300.0, -20.0, 10.0, 700.0]), &contours),
+            outline
+        );
+        assert_eq!(
+            glyph_bounds(Some([10.0, 700.0, 300.0, -20.0]), &contours),
+            outline
+        );
+        assert_eq!(
+            glyph_bounds(Some([f32::NAN, -20.0, 300.0, 700.0]), &contours),
+            outline
+        );
+        assert_eq!(
+            glyph_bounds(Some([10.0, -20.0, f32::INFINITY, 700.0]), &contours),
+            outline
+        );
+
+        // Outlines without any finite points are empty.
+        let nan = point(f32::NAN, f32::NAN);
+        let contours = [triangle(nan.clone(), nan.clone(), nan)];
+        assert_eq!(glyph_bounds(None, &contours), [0.0; 4]);
+    }
+
+    fn font_props(units_per_em: u16, ascender: f32, descender: f32) -> ImtFontProps {
+        ImtFontProps {
+            scaler: em_scaler(units_per_em),
+            ascender,
+            descender,
+            line_gap: 0.0,
+            min_x: 0.0,
+            max_x: 0.0,
+            min_y: 0.0,
+            max_y: 0.0,
+            bbox: [0.0; 4],
+            cap_height: None,
+            x_height: None,
+            is_monospace: false,
+            underline_position: None,
+            underline_thickness: None,
+            strikeout_position: None,
+            strikeout_thickness: None,
+            cmap_encoding: ImtCmapEncoding::Unicode,
+        }
+    }
+
+    #[test]
+    fn em_scale() {
+        // The em box is exactly the requested text height.
+        for units_per_em in [1000, 1024, 2048] {
+            let props = font_props(units_per_em, 0.0, 0.0);
+            let em_px = props.font_units_to_px(units_per_em as f32, 36.0);
+            assert!((em_px - 36.0).abs() < 1e-4);
+        }
+
+        // DejaVu Sans: 2048 units per em, hhea ascender 1901 & descender -483.
+        let props = font_props(2048, 1901.0, -483.0);
+        let ascent_px = props.font_units_to_px(props.ascender, 36.0);
+        let descent_px = props.font_units_to_px(props.descender, 36.0);
+        assert!((ascent_px - 33.416).abs() < 1e-3);
+        assert!((descent_px + 8.490).abs() < 1e-3);
+    }
+
+    #[test]
+    fn fnv1a_64_vectors() {
+        assert_eq!(fnv1a_64(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a_64(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a_64(b"foobar"), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn parser_opts_default() {
+        let opts = ImtParserOpts::default();
+        assert!(!opts.shared);
+        assert!(!opts.use_typo_metrics);
+        assert!(!opts.synchronous);
+        assert_eq!(opts.cmap_subtable, None);
+    }
+
+    #[test]
+    fn synchronous_parser_errors() {
+        let bytes = vec![0_u8; 64];
+
+        let synchronous = ImtParser::new_with_opts(
+            bytes.clone(),
+            ImtParserOpts {
+                synchronous: true,
+                ..ImtParserOpts::default()
+            },
+        )
+        .err();
+
+        // Invalid fonts fail the same way with or without a worker thread.
+        let worker = ImtParser::new_with_opts(bytes.clone(), ImtParserOpts::default()).err();
+        assert!(synchronous.is_some());
+        assert_eq!(synchronous, worker);
+        assert_eq!(ImtParser::new_sync(bytes).err(), worker);
+    }
+
+    fn line_metrics(font: &TestFont, use_typo_metrics: bool) -> (f32, f32, f32) {
+        let props = ImtParser::new_with_opts(
+            font.build(),
+            ImtParserOpts {
+                use_typo_metrics,
+                synchronous: true,
+                ..ImtParserOpts::default()
+            },
+        )
+        .unwrap()
+        .font_props()
+        .unwrap();
+
+        (props.ascender, props.descender, props.line_gap)
+    }
+
+    #[test]
+    fn typo_metrics() {
+        let mut font = TestFont::new();
+        font.hhea = (800, -200, 100);
+        let hhea = (800.0, -200.0, 100.0);
+        let typo = (900.0, -300.0, 50.0);
+
+        // Typographic metrics are only used when requested if the font doesn't set the bit.
+        font.typo = Some((900, -300, 50, false));
+        assert_eq!(line_metrics(&font, false), hhea);
+        assert_eq!(line_metrics(&font, true), typo);
+
+        // Fonts setting USE_TYPO_METRICS always use them.
+        font.typo = Some((900, -300, 50, true));
+        assert_eq!(line_metrics(&font, false), typo);
+        assert_eq!(line_metrics(&font, true), typo);
+
+        // Without an OS/2 table hhea is the fallback.
+        font.typo = None;
+        assert_eq!(line_metrics(&font, false), hhea);
+        assert_eq!(line_metrics(&font, true), hhea);
     }
 }
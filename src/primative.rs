@@ -4,6 +4,99 @@ pub enum ImtGeometry {
     Curve([ImtPoint; 3]),
 }
 
+/// A closed contour of a glyph's outline.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImtContour {
+    pub geometry: Vec<ImtGeometry>,
+    /// Area enclosed by the contour in font units. Positive when the contour runs
+    /// counter-clockwise with y pointing up.
+    pub signed_area: f32,
+}
+
+/// A command of a path, as used by SVG & PDF.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ImtPathCmd {
+    /// Begin a new contour at the point.
+    MoveTo(ImtPoint),
+    LineTo(ImtPoint),
+    /// Quadratic curve with a control point & end point.
+    QuadTo(ImtPoint, ImtPoint),
+    /// Cubic curve with two control points & end point. Not produced by TrueType outlines.
+    CubicTo(ImtPoint, ImtPoint, ImtPoint),
+    /// Close the contour back to where it began.
+    Close,
+}
+
+/// Direction a contour runs in with y pointing up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImtWinding {
+    Clockwise,
+    CounterClockwise,
+}
+
+impl ImtContour {
+    /// Create a contour from its segments, computing its signed area.
+    pub fn new(geometry: Vec<ImtGeometry>) -> Self {
+        let signed_area = geometry
+            .iter()
+            .map(|geo| {
+                match geo {
+                    ImtGeometry::Line([a, b]) => a.cross(b) / 2.0,
+                    // The area between a curve and its chord is two thirds of the triangle
+                    // formed by its control points.
+                    ImtGeometry::Curve([a, b, c]) => {
+                        (a.cross(c) / 2.0)
+                            + (((b.x - a.x) * (c.y - a.y)) - ((b.y - a.y) * (c.x - a.x))) / 3.0
+                    },
+                }
+            })
+            .sum();
+
+        ImtContour {
+            geometry,
+            signed_area,
+        }
+    }
+
+    /// The contour as a closed path.
+    pub fn path(&self) -> Vec<ImtPathCmd> {
+        let mut path = Vec::with_capacity(self.geometry.len() + 2);
+        let mut last: Option<&ImtPoint> = None;
+
+        for geo in self.geometry.iter() {
+            let (start, end) = match geo {
+                ImtGeometry::Line([a, b]) => (a, b),
+                ImtGeometry::Curve([a, _, c]) => (a, c),
+            };
+
+            if last != Some(start) {
+                path.push(ImtPathCmd::MoveTo(start.clone()));
+            }
+
+            path.push(match geo {
+                ImtGeometry::Line([_, b]) => ImtPathCmd::LineTo(b.clone()),
+                ImtGeometry::Curve([_, b, c]) => ImtPathCmd::QuadTo(b.clone(), c.clone()),
+            });
+
+            last = Some(end);
+        }
+
+        if !path.is_empty() {
+            path.push(ImtPathCmd::Close);
+        }
+
+        path
+    }
+
+    pub fn winding(&self) -> ImtWinding {
+        if self.signed_area < 0.0 {
+            ImtWinding::Clockwise
+        } else {
+            ImtWinding::CounterClockwise
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ImtPosition {
     pub x: f32,
@@ -24,6 +117,10 @@ impl ImtPoint {
         }
     }
 
+    fn cross(&self, other: &Self) -> f32 {
+        (self.x * other.y) - (other.x * self.y)
+    }
+
     pub fn dist(&self, other: &Self) -> f32 {
         ((self.x - other.x).powi(2) + (self.y - other.y).powi(2)).sqrt()
     }
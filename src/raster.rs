@@ -1,25 +1,62 @@
 use std::collections::BTreeMap;
+#[cfg(feature = "gpu")]
+use std::collections::VecDeque;
+use std::io::{Read, Write};
 use std::sync::Arc;
 
 use crossbeam::sync::{Parker, Unparker};
 use ordered_float::OrderedFloat;
 use parking_lot::Mutex;
+#[cfg(feature = "gpu")]
+use vulkano::buffer::allocator::{SubbufferAllocator, SubbufferAllocatorCreateInfo};
+#[cfg(feature = "gpu")]
 use vulkano::buffer::subbuffer::Subbuffer;
+#[cfg(feature = "gpu")]
 use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage};
-use vulkano::command_buffer::allocator::StandardCommandBufferAllocator;
+#[cfg(feature = "gpu")]
+use vulkano::command_buffer::allocator::{
+    StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
+};
+#[cfg(feature = "gpu")]
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, PrimaryCommandBufferAbstract,
+    AutoCommandBufferBuilder, CommandBufferUsage, CopyBufferInfo, CopyImageToBufferInfo,
+    PrimaryAutoCommandBuffer, PrimaryCommandBufferAbstract,
 };
+#[cfg(feature = "gpu")]
 use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+#[cfg(feature = "gpu")]
+use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+#[cfg(feature = "gpu")]
 use vulkano::device::{Device, Queue};
+#[cfg(feature = "gpu")]
 use vulkano::format::Format;
+#[cfg(feature = "gpu")]
+use vulkano::image::{ImageFormatInfo, ImageUsage};
+#[cfg(feature = "gpu")]
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryUsage, StandardMemoryAllocator};
-use vulkano::pipeline::ComputePipeline;
+#[cfg(feature = "gpu")]
+use vulkano::pipeline::{ComputePipeline, Pipeline};
+#[cfg(feature = "gpu")]
+use vulkano::query::{QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType};
+#[cfg(feature = "gpu")]
 use vulkano::shader::ShaderModule;
-use vulkano::sync::GpuFuture;
+#[cfg(feature = "gpu")]
+use vulkano::sync::future::FenceSignalFuture;
+#[cfg(feature = "gpu")]
+use vulkano::sync::{GpuFuture, PipelineStage};
+#[cfg(feature = "gpu")]
+use vulkano::DeviceSize;
 
+use crate::bitmap::ImtBitmapMetrics;
+use crate::parse::fnv1a_64;
+#[cfg(feature = "gpu")]
 use crate::shaders::glyph_cs;
-use crate::{ImtError, ImtGlyphBitmap, ImtParser, ImtShapedGlyph};
+use crate::shape::snap_to_grid;
+#[cfg(feature = "gpu")]
+use crate::ImtImageView;
+use crate::{
+    ImtBitmapData, ImtError, ImtErrorSrc, ImtErrorTy, ImtGlyphBitmap, ImtParser, ImtShapedGlyph,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ImtFillQuality {
@@ -45,6 +82,7 @@ pub enum ImtSampleQuality {
     Fast,
     Normal,
     Best,
+    VeryBest,
 }
 
 impl ImtSampleQuality {
@@ -55,6 +93,67 @@ impl ImtSampleQuality {
             Self::Fast => 9,
             Self::Normal => 16,
             Self::Best => 25,
+            Self::VeryBest => 64,
+        }
+    }
+}
+
+/// Sample offsets & ray directions used instead of those generated from the qualities, see
+/// `ImtRasterOpts::pattern`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImtRasterPattern {
+    /// Offsets of the samples within a pixel from its center, in the range `-1.0..=1.0`.
+    pub samples: Vec<[f32; 2]>,
+    /// Directions of the rays casted from each sample. These should be normalized.
+    pub rays: Vec<[f32; 2]>,
+}
+
+/// Rule deciding whether a point is inside an outline from the edges crossed by a ray.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImtFillRule {
+    /// Inside if an odd number of edges are crossed. Suited to `glyf` outlines.
+    EvenOdd,
+    /// Inside if the edges crossed don't cancel out by direction. Suited to CFF outlines and
+    /// outlines with overlapping contours.
+    NonZero,
+}
+
+/// How color is stored relative to alpha in rastered bitmaps.
+///
+/// Alpha is the largest of a pixel's subpixel coverages for both cpu and gpu rasterization.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImtAlphaMode {
+    /// Color is not multiplied by alpha. Fully transparent pixels have a color of zero.
+    Straight,
+    /// Color is multiplied by alpha.
+    Premultiplied,
+}
+
+/// Format of the images output by gpu rasterization, see `ImtRasterOpts::raster_image_format`.
+///
+/// Available without the `gpu` feature so `ImtRasterOpts` has the same fields either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImtImageFormat {
+    /// `R8G8B8A8_UNORM`, the default.
+    R8G8B8A8Unorm,
+    /// `R16G16B16A16_SFLOAT`.
+    R16G16B16A16Sfloat,
+    /// `R32G32B32A32_SFLOAT`.
+    R32G32B32A32Sfloat,
+    /// `R8_UNORM`, single channel coverage. Used regardless of the chosen format with
+    /// `ImtRasterOpts::coverage_only`.
+    R8Unorm,
+}
+
+impl ImtImageFormat {
+    /// The vulkano format of images.
+    #[cfg(feature = "gpu")]
+    pub fn vulkano_format(self) -> Format {
+        match self {
+            Self::R8G8B8A8Unorm => Format::R8G8B8A8_UNORM,
+            Self::R16G16B16A16Sfloat => Format::R16G16B16A16_SFLOAT,
+            Self::R32G32B32A32Sfloat => Format::R32G32B32A32_SFLOAT,
+            Self::R8Unorm => Format::R8_UNORM,
         }
     }
 }
@@ -66,23 +165,143 @@ pub struct ImtRasterOpts {
     /// This effects how many samples will be used per subpixel
     pub sample_quality: ImtSampleQuality,
     /// Whether to align bitmaps to whole pixels. This will adjust bearings to whole
-    /// pixels and offset the resulting bitmap.
+    /// pixels and offset the resulting bitmap. Glyphs from `ImtFont` are then also placed on
+    /// whole pixels by rounding the pen position, which `ImtShapeOpts::align_whole_pixels`
+    /// already does during layout.
     pub align_whole_pixels: bool,
+    /// Device pixels per pixel, see `ImtShapeOpts::pixel_scale`. Glyphs from `ImtFont` are
+    /// placed on the device's pixels when `align_whole_pixels` is set. Bitmaps are unaffected.
+    pub pixel_scale: f32,
     /// This option will be ignored and set by _cpu or _gpu constructors
     pub cpu_rasterization: bool,
     /// Whether or to output a image instead of raw data. Only effects gpu rasterization
+    ///
+    /// Images are storage images in `ImageLayout::General`. Bitmaps returned by
+    /// `raster_shaped_glyphs` or `ImtRasterJob::wait` & `poll` have finished being written and
+    /// their submissions have been cleaned up, so they can be sampled in any later submission
+    /// without further synchronization. See `ImtRasterJob::into_future` for sampling images
+    /// before the raster has completed.
     pub raster_to_image: bool,
-    /// Format used for the bitmap image.
-    pub raster_image_format: Format,
+    /// Format used for the bitmap image. It must support storage images, which is checked when
+    /// the raster is created.
+    pub raster_image_format: ImtImageFormat,
+    /// Number of horizontal sub-pixel positions a glyph may be rastered at. Each phase is
+    /// cached separately. A value of `1` disables sub-pixel positioning.
+    pub subpixel_phases: u32,
+    /// How color is stored relative to alpha. Applies to both cpu and gpu rasterization.
+    /// Defaults to `ImtAlphaMode::Premultiplied`.
+    pub alpha_mode: ImtAlphaMode,
+    /// Output single channel coverage instead of subpixel RGBA. Data will be
+    /// `ImtBitmapData::R8` and gpu images will use `R8_UNORM` regardless of
+    /// `raster_image_format`.
+    pub coverage_only: bool,
+    /// Number of line segments each curve is subdivided into. When `None` the count is
+    /// based on the curve's length at the rastered size. A fixed count makes outlines
+    /// independent of length calculations which is useful for reproducible output.
+    pub curve_steps: Option<u32>,
+    /// Produce bit-reproducible output.
+    ///
+    /// Gpu rasterization is replaced by cpu rasterization as shader float precision varies
+    /// between drivers, and `curve_steps` defaults to a fixed count. Output is then identical
+    /// between runs and machines given the same opts, font and target platform. Output may
+    /// still differ between platforms whose `std` trigonometric functions differ.
+    pub deterministic: bool,
+    /// Collect statistics about rasterization, see `ImtRaster::stats`.
+    pub collect_stats: bool,
+    /// Rule used to decide what is inside of outlines.
+    pub fill_rule: ImtFillRule,
+    /// Snap outlines to the pixel grid before rasterizing, improving the legibility of small
+    /// text. The baseline, x-height & cap height are snapped vertically and, when `subpixel_phases`
+    /// is `1`, vertical stems horizontally. Requires `align_whole_pixels`.
+    pub hinting: bool,
+    /// Grid-fit glyphs by executing the font's TrueType instructions, see
+    /// `ImtParser::hint_glyph`. Glyphs without instructions are rastered as if this was disabled,
+    /// while glyphs with them skip the grid snapping of `hinting`.
+    pub truetype_hinting: bool,
+    /// Threshold coverage to fully covered or uncovered for a hard-edged look. Pixels are
+    /// sampled as a whole, so subpixel RGB output is gray and `subpixel_phases` is set to `1`
+    /// by the constructors.
+    pub aliased: bool,
+    /// Largest width or height in pixels of a glyph bitmap. Rastering a larger glyph returns
+    /// `ImtErrorTy::BitmapTooLarge` instead of allocating it. Gpu rasterization is further
+    /// limited by the device's maximum image dimension.
+    pub max_bitmap_dimension: u32,
+    /// Use these samples & rays instead of generating them from `fill_quality` &
+    /// `sample_quality`. There must be at least one of each.
+    pub pattern: Option<ImtRasterPattern>,
+    /// Transparent pixels added to each edge of bitmaps, such as to keep glyphs packed in an
+    /// atlas from bleeding into each other when filtered. Bearings are adjusted to match.
+    pub padding: u32,
 }
 
+/// Curve subdivisions used by deterministic rasterization when `curve_steps` isn't set.
+const DETERMINISTIC_CURVE_STEPS: u32 = 16;
+
 impl ImtRasterOpts {
+    pub fn builder() -> ImtRasterOptsBuilder {
+        ImtRasterOptsBuilder::default()
+    }
+
     pub fn sample_count(&self) -> usize {
-        self.sample_quality.sample_count()
+        match self.pattern.as_ref() {
+            Some(pattern) => pattern.samples.len(),
+            None => self.sample_quality.sample_count(),
+        }
     }
 
     pub fn ray_count(&self) -> usize {
-        self.fill_quality.ray_count()
+        match self.pattern.as_ref() {
+            Some(pattern) => pattern.rays.len(),
+            None => self.fill_quality.ray_count(),
+        }
+    }
+
+    /// Check a custom pattern has samples & rays.
+    fn validate_pattern(&self) -> Result<(), ImtError> {
+        if self.sample_count() == 0 || self.ray_count() == 0 {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::Other(format!("Pattern must have at least one sample & ray.")),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Split a horizontal position in pixels into a whole pixel position and a sub-pixel phase.
+    ///
+    /// When sub-pixel positioning is disabled the position is returned as is with phase `0`.
+    pub fn subpixel_phase(&self, x: f32) -> (f32, u32) {
+        if self.subpixel_phases <= 1 {
+            return (x, 0);
+        }
+
+        let whole = x.floor();
+        let phase = ((x - whole) * self.subpixel_phases as f32).round() as u32;
+
+        if phase >= self.subpixel_phases {
+            (whole + 1.0, 0)
+        } else {
+            (whole, phase)
+        }
+    }
+
+    /// Pen position in pixels of a glyph positioned at `x`, `y` in pixels, along with the
+    /// sub-pixel phase baked into its bitmap.
+    ///
+    /// With `align_whole_pixels` the pen is rounded to the grid of `pixel_scale`. Positions
+    /// aligned when shaping are already whole, so this only removes the error of converting
+    /// from font units.
+    pub(crate) fn pen_position(&self, x: f32, y: f32) -> (f32, f32, u32) {
+        let (mut pen_x, phase) = self.subpixel_phase(x);
+        let mut pen_y = y;
+
+        if self.align_whole_pixels {
+            pen_x = snap_to_grid(pen_x, self.pixel_scale);
+            pen_y = snap_to_grid(pen_y, self.pixel_scale);
+        }
+
+        (pen_x, pen_y, phase)
     }
 }
 
@@ -92,18 +311,535 @@ impl Default for ImtRasterOpts {
             fill_quality: ImtFillQuality::Normal,
             sample_quality: ImtSampleQuality::Normal,
             align_whole_pixels: true,
+            pixel_scale: 1.0,
             cpu_rasterization: false,
             raster_to_image: true,
-            raster_image_format: Format::R8G8B8A8_UNORM,
+            raster_image_format: ImtImageFormat::R8G8B8A8Unorm,
+            subpixel_phases: 1,
+            alpha_mode: ImtAlphaMode::Premultiplied,
+            coverage_only: false,
+            curve_steps: None,
+            deterministic: false,
+            collect_stats: false,
+            fill_rule: ImtFillRule::EvenOdd,
+            hinting: false,
+            truetype_hinting: false,
+            aliased: false,
+            max_bitmap_dimension: 4096,
+            pattern: None,
+            padding: 0,
         }
     }
 }
 
+/// Builder for `ImtRasterOpts`. Fields not set are their default.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImtRasterOptsBuilder {
+    opts: ImtRasterOpts,
+}
+
+impl ImtRasterOptsBuilder {
+    /// Set `ImtRasterOpts::fill_quality`.
+    pub fn fill_quality(mut self, fill_quality: ImtFillQuality) -> Self {
+        self.opts.fill_quality = fill_quality;
+        self
+    }
+
+    /// Set `ImtRasterOpts::sample_quality`.
+    pub fn sample_quality(mut self, sample_quality: ImtSampleQuality) -> Self {
+        self.opts.sample_quality = sample_quality;
+        self
+    }
+
+    /// Set `ImtRasterOpts::align_whole_pixels`.
+    pub fn align_whole_pixels(mut self, align_whole_pixels: bool) -> Self {
+        self.opts.align_whole_pixels = align_whole_pixels;
+        self
+    }
+
+    /// Set `ImtRasterOpts::pixel_scale`.
+    pub fn pixel_scale(mut self, pixel_scale: f32) -> Self {
+        self.opts.pixel_scale = pixel_scale;
+        self
+    }
+
+    /// Set `ImtRasterOpts::raster_to_image`.
+    pub fn raster_to_image(mut self, raster_to_image: bool) -> Self {
+        self.opts.raster_to_image = raster_to_image;
+        self
+    }
+
+    /// Set `ImtRasterOpts::raster_image_format`.
+    pub fn raster_image_format(mut self, raster_image_format: ImtImageFormat) -> Self {
+        self.opts.raster_image_format = raster_image_format;
+        self
+    }
+
+    /// Set `ImtRasterOpts::subpixel_phases`.
+    pub fn subpixel_phases(mut self, subpixel_phases: u32) -> Self {
+        self.opts.subpixel_phases = subpixel_phases;
+        self
+    }
+
+    /// Set `ImtRasterOpts::coverage_only`.
+    pub fn coverage_only(mut self, coverage_only: bool) -> Self {
+        self.opts.coverage_only = coverage_only;
+        self
+    }
+
+    /// Set `ImtRasterOpts::curve_steps`.
+    pub fn curve_steps(mut self, curve_steps: Option<u32>) -> Self {
+        self.opts.curve_steps = curve_steps;
+        self
+    }
+
+    /// Set `ImtRasterOpts::deterministic`.
+    pub fn deterministic(mut self, deterministic: bool) -> Self {
+        self.opts.deterministic = deterministic;
+        self
+    }
+
+    /// Set `ImtRasterOpts::alpha_mode`.
+    pub fn alpha_mode(mut self, alpha_mode: ImtAlphaMode) -> Self {
+        self.opts.alpha_mode = alpha_mode;
+        self
+    }
+
+    /// Set `ImtRasterOpts::collect_stats`.
+    pub fn collect_stats(mut self, collect_stats: bool) -> Self {
+        self.opts.collect_stats = collect_stats;
+        self
+    }
+
+    /// Set `ImtRasterOpts::fill_rule`.
+    pub fn fill_rule(mut self, fill_rule: ImtFillRule) -> Self {
+        self.opts.fill_rule = fill_rule;
+        self
+    }
+
+    /// Set `ImtRasterOpts::hinting`.
+    pub fn hinting(mut self, hinting: bool) -> Self {
+        self.opts.hinting = hinting;
+        self
+    }
+
+    /// Set `ImtRasterOpts::truetype_hinting`.
+    pub fn truetype_hinting(mut self, truetype_hinting: bool) -> Self {
+        self.opts.truetype_hinting = truetype_hinting;
+        self
+    }
+
+    /// Set `ImtRasterOpts::aliased`.
+    pub fn aliased(mut self, aliased: bool) -> Self {
+        self.opts.aliased = aliased;
+        self
+    }
+
+    /// Set `ImtRasterOpts::max_bitmap_dimension`.
+    pub fn max_bitmap_dimension(mut self, max_bitmap_dimension: u32) -> Self {
+        self.opts.max_bitmap_dimension = max_bitmap_dimension;
+        self
+    }
+
+    /// Set `ImtRasterOpts::pattern`.
+    pub fn pattern(mut self, pattern: Option<ImtRasterPattern>) -> Self {
+        self.opts.pattern = pattern;
+        self
+    }
+
+    /// Set `ImtRasterOpts::padding`.
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.opts.padding = padding;
+        self
+    }
+
+    pub fn build(self) -> ImtRasterOpts {
+        self.opts
+    }
+}
+
 pub struct ImtRasteredGlyph {
     pub shaped: ImtShapedGlyph,
     pub bitmap: Arc<ImtGlyphBitmap>,
 }
 
+/// Statistics collected when `ImtRasterOpts::collect_stats` is set.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImtRasterStats {
+    /// Glyphs that were already present in the cache.
+    pub cache_hits: u64,
+    /// Glyphs that were missing from the cache.
+    pub cache_misses: u64,
+    /// Glyphs that have been rastered.
+    pub glyphs_rastered: u64,
+    /// Time the gpu has spent rastering as measured by timestamp queries. Remains zero for cpu
+    /// rasterization or if the queue doesn't support timestamps.
+    pub gpu_time_ms: f64,
+}
+
+/// A rasterization job returned by `ImtRaster::raster_shaped_glyphs_deferred`.
+///
+/// Dropping the job before it completes will block until the gpu has finished.
+pub struct ImtRasterJob<'a> {
+    raster: &'a ImtRaster,
+    #[cfg(feature = "gpu")]
+    future: Option<FenceSignalFuture<Box<dyn GpuFuture>>>,
+    glyphs: Vec<(ImtShapedGlyph, RasterJobBitmap)>,
+    pending: Vec<(RasterCacheKey, ImtGlyphBitmap)>,
+    // Dropped after `future` so the queries aren't reused before the gpu has written them.
+    #[cfg(feature = "gpu")]
+    timing: Option<GpuTiming<'a>>,
+    finished: bool,
+}
+
+enum RasterJobBitmap {
+    Ready(Arc<ImtGlyphBitmap>),
+    Pending(usize),
+}
+
+impl<'a> ImtRasterJob<'a> {
+    /// Check if the job has completed without blocking.
+    ///
+    /// Returns `None` until the gpu has finished. Once the glyphs have been returned further
+    /// calls will also return `None`.
+    pub fn poll(&mut self) -> Result<Option<Vec<ImtRasteredGlyph>>, ImtError> {
+        #[cfg(feature = "gpu")]
+        if let Some(future) = self.future.as_ref() {
+            if !future.is_signaled().map_err(ImtError::gpu)? {
+                return Ok(None);
+            }
+        }
+
+        Ok(self.finish())
+    }
+
+    /// Block until the job has completed.
+    pub fn wait(mut self) -> Result<Vec<ImtRasteredGlyph>, ImtError> {
+        #[cfg(feature = "gpu")]
+        if let Some(future) = self.future.as_ref() {
+            future.wait(None).map_err(ImtError::gpu)?;
+        }
+
+        Ok(self.finish().unwrap_or_default())
+    }
+
+    /// Return the glyphs without waiting along with the future of the raster's submission.
+    ///
+    /// The images of the glyphs may still be written by the gpu, so the returned future must be
+    /// joined with any future that accesses them, e.g. before executing a command buffer that
+    /// samples them. Images are left in `ImageLayout::General`. Glyphs rastered by this job are
+    /// added to the cache, so until the future has completed glyphs returned from the cache to
+    /// other callers must also be synchronized with it.
+    ///
+    /// The future is `None` if nothing needed to be rastered. If `raster_to_image` is disabled the
+    /// data has to be read back, so this will block like `wait` and return no future.
+    #[cfg(feature = "gpu")]
+    pub fn into_future(
+        mut self,
+    ) -> Result<
+        (
+            Vec<ImtRasteredGlyph>,
+            Option<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+        ),
+        ImtError,
+    > {
+        if self.raster.opts.cpu_rasterization || !self.raster.opts.raster_to_image {
+            return Ok((self.wait()?, None));
+        }
+
+        self.finished = true;
+        // The queries can't be read until the gpu has finished, so the time isn't recorded.
+        // Queries are reused in the order they're returned, so these won't be reset while
+        // still being written unless many more submissions are made before this one completes.
+        self.timing = None;
+        let future = self.future.take().map(Arc::new);
+        let glyphs_rastered = self.pending.len() as u64;
+        self.raster
+            .record_stats(|stats| stats.glyphs_rastered += glyphs_rastered);
+
+        let bitmaps = self.cache_pending();
+        Ok((self.take_glyphs(&bitmaps), future))
+    }
+
+    /// Add the glyphs rastered by this job to the cache.
+    fn cache_pending(&mut self) -> Vec<Arc<ImtGlyphBitmap>> {
+        let mut bitmaps = Vec::with_capacity(self.pending.len());
+        let mut cache = self.raster.cache.lock();
+
+        for (cache_key, bitmap) in self.pending.drain(..) {
+            // Reads back the data if the bitmap isn't rastered to an image.
+            #[cfg(feature = "gpu")]
+            let bitmap = {
+                let mut bitmap = bitmap;
+                bitmap.finish_gpu();
+                bitmap
+            };
+
+            let bitmap = Arc::new(bitmap);
+            ImtRaster::cache_completed(&mut cache, cache_key, bitmap.clone());
+            bitmaps.push(bitmap);
+        }
+
+        bitmaps
+    }
+
+    fn take_glyphs(&mut self, bitmaps: &[Arc<ImtGlyphBitmap>]) -> Vec<ImtRasteredGlyph> {
+        self.glyphs
+            .drain(..)
+            .map(|(shaped, bitmap)| {
+                ImtRasteredGlyph {
+                    shaped,
+                    bitmap: match bitmap {
+                        RasterJobBitmap::Ready(bitmap) => bitmap,
+                        RasterJobBitmap::Pending(i) => bitmaps[i].clone(),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    fn finish(&mut self) -> Option<Vec<ImtRasteredGlyph>> {
+        if self.finished {
+            return None;
+        }
+
+        self.finished = true;
+
+        #[cfg(feature = "gpu")]
+        let gpu_time_ms = {
+            self.future = None;
+
+            // Timing only contributes to the statistics, so a failure to read it isn't fatal.
+            self.timing
+                .take()
+                .and_then(|timing| timing.elapsed_ms().ok())
+        };
+
+        #[cfg(not(feature = "gpu"))]
+        let gpu_time_ms: Option<f64> = None;
+
+        let glyphs_rastered = self.pending.len() as u64;
+
+        self.raster.record_stats(|stats| {
+            stats.glyphs_rastered += glyphs_rastered;
+            stats.gpu_time_ms += gpu_time_ms.unwrap_or(0.0);
+        });
+
+        let bitmaps = self.cache_pending();
+        Some(self.take_glyphs(&bitmaps))
+    }
+}
+
+/// Text height, glyph index, fake bold, fake italic, small caps & sub-pixel phase.
+type RasterCacheKey = (OrderedFloat<f32>, u16, bool, bool, bool, u32);
+
+const CACHE_MAGIC: &[u8; 4] = b"IMTC";
+const CACHE_VERSION: u32 = 2;
+
+/// Reads little endian values from an exported cache.
+struct CacheCursor<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> CacheCursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ImtError> {
+        if self.bytes.len() < len {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Cache,
+                ImtErrorTy::FileBadEof,
+            ));
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ImtError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ImtError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ImtError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, ImtError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, ImtError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Length of an array of `size` byte values, checked against the remaining bytes before
+    /// anything is allocated for it.
+    fn array_len(&mut self, size: usize) -> Result<usize, ImtError> {
+        let len = usize::try_from(self.u64()?).ok();
+
+        match len.and_then(|len| len.checked_mul(size)) {
+            Some(bytes) if bytes <= self.bytes.len() => Ok(len.unwrap()),
+            _ => {
+                Err(ImtError::src_and_ty(
+                    ImtErrorSrc::Cache,
+                    ImtErrorTy::FileBadEof,
+                ))
+            },
+        }
+    }
+}
+
+/// A completed cache entry with its bitmap's data, as stored by `ImtRaster::export_cache`.
+type CacheEntry = (RasterCacheKey, ImtBitmapMetrics, ImtBitmapData);
+
+/// Serialize `entries` for the font & options of the given hashes. Image data must be read
+/// back beforehand, otherwise it is stored as empty.
+fn encode_cache(font_hash: u64, opts_hash: u64, entries: &[CacheEntry]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = Vec::new();
+    bytes.extend_from_slice(CACHE_MAGIC);
+    bytes.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&font_hash.to_le_bytes());
+    bytes.extend_from_slice(&opts_hash.to_le_bytes());
+    bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+
+    for ((height, index, fake_bold, fake_italic, small_caps, phase), metrics, data) in entries {
+        bytes.extend_from_slice(&height.into_inner().to_le_bytes());
+        bytes.extend_from_slice(&index.to_le_bytes());
+        bytes.push(*fake_bold as u8);
+        bytes.push(*fake_italic as u8);
+        bytes.push(*small_caps as u8);
+        bytes.extend_from_slice(&phase.to_le_bytes());
+        bytes.extend_from_slice(&metrics.width.to_le_bytes());
+        bytes.extend_from_slice(&metrics.height.to_le_bytes());
+        bytes.extend_from_slice(&metrics.bearing_x.to_le_bytes());
+        bytes.extend_from_slice(&metrics.bearing_y.to_le_bytes());
+
+        match data {
+            ImtBitmapData::Empty => bytes.push(0),
+            #[cfg(feature = "gpu")]
+            ImtBitmapData::Image(_) => bytes.push(0),
+            ImtBitmapData::LRGBA(data) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+
+                for v in data.iter() {
+                    bytes.extend_from_slice(&v.to_le_bytes());
+                }
+            },
+            ImtBitmapData::R8(data) => {
+                bytes.push(2);
+                bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(data);
+            },
+        }
+    }
+
+    bytes
+}
+
+/// Deserialize entries written by `encode_cache`. Returns `CacheStale` if they were written for
+/// a different font or options.
+fn decode_cache(bytes: &[u8], font_hash: u64, opts_hash: u64) -> Result<Vec<CacheEntry>, ImtError> {
+    let mut cursor = CacheCursor {
+        bytes,
+    };
+
+    if cursor.take(4)? != CACHE_MAGIC {
+        return Err(ImtError::src_and_ty(
+            ImtErrorSrc::Cache,
+            ImtErrorTy::FileUnsupportedFormat,
+        ));
+    }
+
+    if cursor.u32()? != CACHE_VERSION {
+        return Err(ImtError::src_and_ty(
+            ImtErrorSrc::Cache,
+            ImtErrorTy::FileBadVersion,
+        ));
+    }
+
+    if cursor.u64()? != font_hash || cursor.u64()? != opts_hash {
+        return Err(ImtError::src_and_ty(
+            ImtErrorSrc::Cache,
+            ImtErrorTy::CacheStale,
+        ));
+    }
+
+    let count = cursor.u64()?;
+    let mut entries = Vec::new();
+
+    for _ in 0..count {
+        let height = cursor.f32()?;
+        let index = cursor.u16()?;
+        let fake_bold = cursor.u8()? != 0;
+        let fake_italic = cursor.u8()? != 0;
+        let small_caps = cursor.u8()? != 0;
+        let phase = cursor.u32()?;
+
+        let metrics = ImtBitmapMetrics {
+            width: cursor.u32()?,
+            height: cursor.u32()?,
+            bearing_x: cursor.f32()?,
+            bearing_y: cursor.f32()?,
+        };
+
+        // Data along with the number of values & values per pixel.
+        let (data, len, channels) = match cursor.u8()? {
+            0 => (ImtBitmapData::Empty, 0, 0),
+            1 => {
+                let len = cursor.array_len(4)?;
+                let mut data = Vec::with_capacity(len);
+
+                for _ in 0..len {
+                    data.push(cursor.f32()?);
+                }
+
+                (ImtBitmapData::LRGBA(Arc::new(data)), len, 4)
+            },
+            2 => {
+                let len = cursor.array_len(1)?;
+                let data = cursor.take(len)?.to_vec();
+                (ImtBitmapData::R8(Arc::new(data)), len, 1)
+            },
+            _ => {
+                return Err(ImtError::src_and_ty(
+                    ImtErrorSrc::Cache,
+                    ImtErrorTy::FileBadValue,
+                ))
+            },
+        };
+
+        if channels != 0 {
+            let expected = (metrics.width as usize)
+                .checked_mul(metrics.height as usize)
+                .and_then(|pixels| pixels.checked_mul(channels));
+
+            if Some(len) != expected {
+                return Err(ImtError::src_and_ty(
+                    ImtErrorSrc::Cache,
+                    ImtErrorTy::FileBadValue,
+                ));
+            }
+        }
+
+        let key = (
+            OrderedFloat::from(height),
+            index,
+            fake_bold,
+            fake_italic,
+            small_caps,
+            phase,
+        );
+
+        entries.push((key, metrics, data));
+    }
+
+    Ok(entries)
+}
+
 #[derive(Clone)]
 enum RasterCacheState {
     Completed(Arc<ImtGlyphBitmap>),
@@ -114,23 +850,149 @@ enum RasterCacheState {
 #[allow(dead_code)]
 pub struct ImtRaster {
     opts: ImtRasterOpts,
-    cache: Mutex<BTreeMap<(OrderedFloat<f32>, u16), RasterCacheState>>,
+    cache: Mutex<BTreeMap<RasterCacheKey, RasterCacheState>>,
+    #[cfg(feature = "gpu")]
     gpu_raster_context: Option<GpuRasterContext>,
     cpu_raster_context: Option<CpuRasterContext>,
+    stats: Option<Mutex<ImtRasterStats>>,
 }
 
+#[cfg(feature = "gpu")]
 #[allow(dead_code)]
 pub(crate) struct GpuRasterContext {
     pub device: Arc<Device>,
     pub queue: Arc<Queue>,
-    pub mem_alloc: StandardMemoryAllocator,
-    pub cmd_alloc: StandardCommandBufferAllocator,
+    pub mem_alloc: Arc<StandardMemoryAllocator>,
+    pub cmd_alloc: Arc<StandardCommandBufferAllocator>,
     pub glyph_cs: Arc<ShaderModule>,
-    pub common_buf: Subbuffer<glyph_cs::Common>,
     pub pipeline: Arc<ComputePipeline>,
-    pub set_alloc: StandardDescriptorSetAllocator,
+    pub set_alloc: Arc<StandardDescriptorSetAllocator>,
+    /// Set of the data shared by all glyphs. It's created along with the data and bound for
+    /// every glyph, so only the per glyph set is allocated for each dispatch.
+    pub common_set: Arc<PersistentDescriptorSet>,
+    /// Per glyph uniform & line buffers are sub-allocated from arenas that are reused once
+    /// the glyphs using them have been rastered.
+    pub buf_alloc: Mutex<SubbufferAllocator>,
     pub raster_to_image: bool,
     pub raster_image_format: Format,
+    pub coverage_only: bool,
+    /// Pairs of timestamp queries used to time rasterization, `None` if timing is disabled.
+    pub timing_pool: Option<Arc<QueryPool>>,
+    /// Pairs of `timing_pool` not in use by a submission, by index of their first query.
+    pub timing_free: Mutex<VecDeque<u32>>,
+    pub timestamp_period: f32,
+}
+
+/// Number of submissions that may be timed at once. Further submissions aren't timed.
+#[cfg(feature = "gpu")]
+const TIMING_PAIRS: u32 = 64;
+
+/// A pair of timestamp queries of `GpuRasterContext::timing_pool` timing a submission. The
+/// queries are returned to the pool when this is dropped.
+#[cfg(feature = "gpu")]
+pub(crate) struct GpuTiming<'a> {
+    context: &'a GpuRasterContext,
+    pool: Arc<QueryPool>,
+    first_query: u32,
+}
+
+#[cfg(feature = "gpu")]
+impl GpuTiming<'_> {
+    /// Time between `GpuRasterContext::begin_timing` & `end` in milliseconds. The commands must
+    /// have completed.
+    pub fn elapsed_ms(self) -> Result<f64, ImtError> {
+        let mut timestamps = [0_u64; 2];
+
+        self.pool
+            .queries_range(self.first_query..(self.first_query + 2))
+            .ok_or(ImtError::src_and_ty(
+                ImtErrorSrc::Gpu,
+                ImtErrorTy::MissingIndex,
+            ))?
+            .get_results(&mut timestamps, QueryResultFlags::WAIT)
+            .map_err(ImtError::gpu)?;
+
+        Ok((timestamps[1].saturating_sub(timestamps[0]) as f64
+            * self.context.timestamp_period as f64)
+            / 1_000_000.0)
+    }
+
+    /// Stop timing the commands recorded into `cmd_buf`.
+    pub fn end(
+        &self,
+        cmd_buf: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<(), ImtError> {
+        unsafe {
+            cmd_buf
+                .write_timestamp(
+                    self.pool.clone(),
+                    self.first_query + 1,
+                    PipelineStage::BottomOfPipe,
+                )
+                .map_err(ImtError::gpu)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "gpu")]
+impl Drop for GpuTiming<'_> {
+    fn drop(&mut self) {
+        self.context.timing_free.lock().push_back(self.first_query);
+    }
+}
+
+#[cfg(feature = "gpu")]
+impl GpuRasterContext {
+    /// Start timing the commands recorded after this into `cmd_buf`. `None` if timing is
+    /// disabled or all of the queries are in use.
+    pub fn begin_timing(
+        &self,
+        cmd_buf: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>,
+    ) -> Result<Option<GpuTiming<'_>>, ImtError> {
+        let timing_pool = match self.timing_pool.as_ref() {
+            Some(timing_pool) => timing_pool,
+            None => return Ok(None),
+        };
+
+        let timing = match self.timing_free.lock().pop_front() {
+            Some(first_query) => {
+                GpuTiming {
+                    context: self,
+                    pool: timing_pool.clone(),
+                    first_query,
+                }
+            },
+            None => return Ok(None),
+        };
+
+        unsafe {
+            cmd_buf
+                .reset_query_pool(
+                    timing_pool.clone(),
+                    timing.first_query..(timing.first_query + 2),
+                )
+                .map_err(ImtError::gpu)?
+                .write_timestamp(
+                    timing_pool.clone(),
+                    timing.first_query,
+                    PipelineStage::TopOfPipe,
+                )
+                .map_err(ImtError::gpu)?;
+        }
+
+        Ok(Some(timing))
+    }
+}
+
+/// Allocators used by gpu rasterization. Any allocator not provided will be created.
+#[cfg(feature = "gpu")]
+#[derive(Clone, Default)]
+pub struct ImtRasterAllocators {
+    pub mem_alloc: Option<Arc<StandardMemoryAllocator>>,
+    pub cmd_alloc: Option<Arc<StandardCommandBufferAllocator>>,
+    pub set_alloc: Option<Arc<StandardDescriptorSetAllocator>>,
 }
 
 pub(crate) struct CpuRasterContext {
@@ -138,165 +1000,958 @@ pub(crate) struct CpuRasterContext {
     pub rays: Vec<[f32; 2]>,
 }
 
+impl CpuRasterContext {
+    /// Generate the sample offsets & ray directions for the qualities in `opts`, or use its
+    /// pattern if set.
+    fn new(opts: &ImtRasterOpts) -> Self {
+        if let Some(pattern) = opts.pattern.as_ref() {
+            return Self {
+                samples: pattern.samples.clone(),
+                rays: pattern.rays.clone(),
+            };
+        }
+
+        let sample_count = opts.sample_count();
+        let ray_count = opts.ray_count();
+        let mut samples = Vec::with_capacity(sample_count);
+        let mut rays = Vec::with_capacity(ray_count);
+        let w = (sample_count as f32).sqrt() as usize;
+
+        for x in 1..=w {
+            for y in 1..=w {
+                samples.push([
+                    ((x as f32 / (w as f32 + 1.0)) * 2.0) - 1.0,
+                    ((y as f32 / (w as f32 + 1.0)) * 2.0) - 1.0,
+                ]);
+            }
+        }
+
+        for i in 0..ray_count {
+            let rad = (i as f32 * (360.0 / ray_count as f32)).to_radians();
+            rays.push([rad.cos(), rad.sin()]);
+        }
+
+        Self {
+            samples,
+            rays,
+        }
+    }
+}
+
+/// Upload the data shared by every glyph dispatch for the options in `opts`, returning the
+/// common & the samples and rays buffers.
+/// Upload the data shared by all glyphs and create the set binding it.
+#[cfg(feature = "gpu")]
+fn create_common_set(
+    opts: &ImtRasterOpts,
+    mem_alloc: &Arc<StandardMemoryAllocator>,
+    cmd_alloc: &Arc<StandardCommandBufferAllocator>,
+    set_alloc: &Arc<StandardDescriptorSetAllocator>,
+    pipeline: &Arc<ComputePipeline>,
+    queue: &Arc<Queue>,
+) -> Result<Arc<PersistentDescriptorSet>, ImtError> {
+    let (common_buf, samples_and_rays_buf) = create_common_buf(opts, mem_alloc, cmd_alloc, queue);
+
+    PersistentDescriptorSet::new(
+        &**set_alloc,
+        pipeline.layout().set_layouts()[0].clone(),
+        [
+            WriteDescriptorSet::buffer(0, common_buf),
+            WriteDescriptorSet::buffer(1, samples_and_rays_buf),
+        ],
+    )
+    .map_err(ImtError::gpu)
+}
+
+#[cfg(feature = "gpu")]
+fn create_common_buf(
+    opts: &ImtRasterOpts,
+    mem_alloc: &Arc<StandardMemoryAllocator>,
+    cmd_alloc: &Arc<StandardCommandBufferAllocator>,
+    queue: &Arc<Queue>,
+) -> (Subbuffer<glyph_cs::Common>, Subbuffer<[[f32; 4]]>) {
+    let CpuRasterContext {
+        samples,
+        rays,
+    } = CpuRasterContext::new(opts);
+
+    let mut samples_and_rays = vec![[0.0; 4]; samples.len().max(rays.len())];
+
+    for (i, sample) in samples.iter().enumerate() {
+        samples_and_rays[i][0] = sample[0];
+        samples_and_rays[i][1] = sample[1];
+    }
+
+    for (i, ray) in rays.iter().enumerate() {
+        samples_and_rays[i][2] = ray[0];
+        samples_and_rays[i][3] = ray[1];
+    }
+
+    let common_cpu_buf = Buffer::from_data(
+        &**mem_alloc,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Upload,
+            ..Default::default()
+        },
+        glyph_cs::Common {
+            sample_count: samples.len() as u32,
+            ray_count: rays.len() as u32,
+            premultiplied: (opts.alpha_mode == ImtAlphaMode::Premultiplied) as u32,
+            coverage_only: opts.coverage_only as u32,
+            nonzero: (opts.fill_rule == ImtFillRule::NonZero) as u32,
+            aliased: opts.aliased as u32,
+        },
+    )
+    .unwrap();
+
+    let common_dev_buf = Buffer::new_sized(
+        &**mem_alloc,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST | BufferUsage::UNIFORM_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::DeviceOnly,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let samples_and_rays_len = samples_and_rays.len() as DeviceSize;
+
+    let samples_and_rays_cpu_buf = Buffer::from_iter(
+        &**mem_alloc,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::Upload,
+            ..Default::default()
+        },
+        samples_and_rays,
+    )
+    .unwrap();
+
+    let samples_and_rays_dev_buf = Buffer::new_slice::<[f32; 4]>(
+        &**mem_alloc,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST | BufferUsage::STORAGE_BUFFER,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            usage: MemoryUsage::DeviceOnly,
+            ..Default::default()
+        },
+        samples_and_rays_len,
+    )
+    .unwrap();
+
+    let mut cmd_buf = AutoCommandBufferBuilder::primary(
+        &**cmd_alloc,
+        queue.queue_family_index(),
+        CommandBufferUsage::OneTimeSubmit,
+    )
+    .unwrap();
+
+    cmd_buf
+        .copy_buffer(CopyBufferInfo::buffers(
+            common_cpu_buf,
+            common_dev_buf.clone(),
+        ))
+        .unwrap();
+
+    cmd_buf
+        .copy_buffer(CopyBufferInfo::buffers(
+            samples_and_rays_cpu_buf,
+            samples_and_rays_dev_buf.clone(),
+        ))
+        .unwrap();
+
+    cmd_buf
+        .build()
+        .unwrap()
+        .execute(queue.clone())
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    (common_dev_buf, samples_and_rays_dev_buf)
+}
+
 impl ImtRaster {
+    /// Create a gpu raster. If `opts.deterministic` is set a cpu raster is created instead.
+    #[cfg(feature = "gpu")]
     pub fn new_gpu(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        opts: ImtRasterOpts,
+    ) -> Result<Self, ImtError> {
+        Self::new_gpu_with_allocators(device, queue, opts, ImtRasterAllocators::default())
+    }
+
+    /// Same as `new_gpu`, but allows the allocators to be shared with the application.
+    #[cfg(feature = "gpu")]
+    pub fn new_gpu_with_allocators(
         device: Arc<Device>,
         queue: Arc<Queue>,
         mut opts: ImtRasterOpts,
+        allocators: ImtRasterAllocators,
     ) -> Result<Self, ImtError> {
+        if opts.deterministic {
+            return Self::new_cpu(opts);
+        }
+
         opts.cpu_rasterization = false;
+
+        if opts.aliased {
+            opts.subpixel_phases = 1;
+        }
+
+        if opts.coverage_only {
+            opts.raster_image_format = ImtImageFormat::R8Unorm;
+        }
+
+        opts.validate_pattern()?;
+
+        // Bitmaps are written as storage images and copied from for read back.
+        let format_supported = device
+            .physical_device()
+            .image_format_properties(ImageFormatInfo {
+                format: Some(opts.raster_image_format.vulkano_format()),
+                usage: ImageUsage::TRANSFER_SRC | ImageUsage::STORAGE,
+                ..Default::default()
+            })
+            .ok()
+            .flatten()
+            .is_some();
+
+        if !format_supported {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Ilmenite,
+                ImtErrorTy::Other(format!(
+                    "Format {:?} can't be used for storage images.",
+                    opts.raster_image_format
+                )),
+            ));
+        }
+
         let glyph_cs = glyph_cs::load(device.clone()).unwrap();
-        let mem_alloc = StandardMemoryAllocator::new_default(device.clone());
-        let cmd_alloc = StandardCommandBufferAllocator::new(device.clone(), Default::default());
 
-        let mut samples_and_rays = [[0.0; 4]; 25];
-        let sample_count = opts.sample_count();
+        let mem_alloc = allocators
+            .mem_alloc
+            .unwrap_or_else(|| Arc::new(StandardMemoryAllocator::new_default(device.clone())));
 
-        let w = (sample_count as f32).sqrt() as usize;
-        let mut sar_i = 0;
+        // Command buffers are returned to the allocator for reuse once their submission is
+        // cleaned up. Only primary command buffers are recorded.
+        let cmd_alloc = allocators.cmd_alloc.unwrap_or_else(|| {
+            Arc::new(StandardCommandBufferAllocator::new(
+                device.clone(),
+                StandardCommandBufferAllocatorCreateInfo {
+                    secondary_buffer_count: 0,
+                    ..Default::default()
+                },
+            ))
+        });
 
-        for x in 1..=w {
-            for y in 1..=w {
-                samples_and_rays[sar_i][0] = ((x as f32 / (w as f32 + 1.0)) * 2.0) - 1.0;
-                samples_and_rays[sar_i][1] = ((y as f32 / (w as f32 + 1.0)) * 2.0) - 1.0;
-                sar_i += 1;
-            }
+        let pipeline = ComputePipeline::new(
+            device.clone(),
+            glyph_cs.entry_point("main").unwrap(),
+            &(),
+            None,
+            |_| {},
+        )
+        .unwrap();
+
+        let set_alloc = allocators
+            .set_alloc
+            .unwrap_or_else(|| Arc::new(StandardDescriptorSetAllocator::new(device.clone())));
+        let common_set =
+            create_common_set(&opts, &mem_alloc, &cmd_alloc, &set_alloc, &pipeline, &queue)?;
+        let buf_alloc = Mutex::new(SubbufferAllocator::new(
+            mem_alloc.clone(),
+            SubbufferAllocatorCreateInfo {
+                buffer_usage: BufferUsage::UNIFORM_BUFFER | BufferUsage::STORAGE_BUFFER,
+                memory_usage: MemoryUsage::Upload,
+                ..Default::default()
+            },
+        ));
+        let raster_to_image = opts.raster_to_image;
+        let raster_image_format = opts.raster_image_format.vulkano_format();
+        let coverage_only = opts.coverage_only;
+        let physical_device = device.physical_device();
+
+        let timestamps = opts.collect_stats
+            && physical_device.queue_family_properties()[queue.queue_family_index() as usize]
+                .timestamp_valid_bits
+                .is_some();
+
+        // A single pool is shared by all submissions, each using a pair of its queries.
+        let timing_pool = match timestamps {
+            true => {
+                Some(
+                    QueryPool::new(
+                        device.clone(),
+                        QueryPoolCreateInfo {
+                            query_count: TIMING_PAIRS * 2,
+                            ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+                        },
+                    )
+                    .map_err(ImtError::gpu)?,
+                )
+            },
+            false => None,
+        };
+
+        let timing_free = Mutex::new((0..TIMING_PAIRS).map(|pair| pair * 2).collect());
+
+        let timestamp_period = physical_device.properties().timestamp_period;
+        let stats = opts
+            .collect_stats
+            .then(|| Mutex::new(ImtRasterStats::default()));
+
+        Ok(ImtRaster {
+            opts,
+            cache: Mutex::new(BTreeMap::new()),
+            gpu_raster_context: Some(GpuRasterContext {
+                device,
+                queue,
+                mem_alloc,
+                cmd_alloc,
+                glyph_cs,
+                pipeline,
+                set_alloc,
+                common_set,
+                buf_alloc,
+                raster_to_image,
+                raster_image_format,
+                coverage_only,
+                timing_pool,
+                timing_free,
+                timestamp_period,
+            }),
+            cpu_raster_context: None,
+            stats,
+        })
+    }
+
+    pub fn new_cpu(mut opts: ImtRasterOpts) -> Result<Self, ImtError> {
+        opts.cpu_rasterization = true;
+
+        if opts.aliased {
+            opts.subpixel_phases = 1;
+        }
+
+        if opts.deterministic && opts.curve_steps.is_none() {
+            opts.curve_steps = Some(DETERMINISTIC_CURVE_STEPS);
+        }
+
+        opts.validate_pattern()?;
+        let cpu_raster_context = CpuRasterContext::new(&opts);
+        let stats = opts
+            .collect_stats
+            .then(|| Mutex::new(ImtRasterStats::default()));
+
+        Ok(ImtRaster {
+            opts,
+            cache: Mutex::new(BTreeMap::new()),
+            #[cfg(feature = "gpu")]
+            gpu_raster_context: None,
+            cpu_raster_context: Some(cpu_raster_context),
+            stats,
+        })
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.opts.sample_count()
+    }
+
+    pub fn ray_count(&self) -> usize {
+        self.opts.ray_count()
+    }
+
+    /// Change the fill & sample quality without recreating the raster.
+    ///
+    /// The sample & ray data is regenerated and the cache is cleared, so glyphs will be
+    /// rastered again at the new quality when next requested. Any custom pattern is removed.
+    pub fn set_quality(
+        &mut self,
+        fill_quality: ImtFillQuality,
+        sample_quality: ImtSampleQuality,
+    ) -> Result<(), ImtError> {
+        self.opts.fill_quality = fill_quality;
+        self.opts.sample_quality = sample_quality;
+        self.opts.pattern = None;
+
+        #[cfg(feature = "gpu")]
+        if let Some(gpu_raster_context) = self.gpu_raster_context.as_mut() {
+            gpu_raster_context.common_set = create_common_set(
+                &self.opts,
+                &gpu_raster_context.mem_alloc,
+                &gpu_raster_context.cmd_alloc,
+                &gpu_raster_context.set_alloc,
+                &gpu_raster_context.pipeline,
+                &gpu_raster_context.queue,
+            )?;
+        }
+
+        if let Some(cpu_raster_context) = self.cpu_raster_context.as_mut() {
+            *cpu_raster_context = CpuRasterContext::new(&self.opts);
+        }
+
+        self.cache.get_mut().clear();
+        Ok(())
+    }
+
+    /// Snapshot of all completed bitmaps currently in the cache as `(index, text_height, bitmap)`.
+    ///
+    /// The cache lock is only held while cloning the entries.
+    pub fn cached_bitmaps(&self) -> Vec<(u16, f32, Arc<ImtGlyphBitmap>)> {
+        self.cache
+            .lock()
+            .iter()
+            .filter_map(|(&(height, index, ..), state)| {
+                match state {
+                    RasterCacheState::Completed(bitmap) => {
+                        Some((index, height.into_inner(), bitmap.clone()))
+                    },
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// The options this raster was created with.
+    pub fn opts(&self) -> &ImtRasterOpts {
+        &self.opts
+    }
+
+    /// Statistics collected since creation or the last `reset_stats`. `None` unless
+    /// `ImtRasterOpts::collect_stats` is set.
+    pub fn stats(&self) -> Option<ImtRasterStats> {
+        self.stats.as_ref().map(|stats| stats.lock().clone())
+    }
+
+    /// Reset the collected statistics to zero.
+    pub fn reset_stats(&self) {
+        self.record_stats(|stats| *stats = ImtRasterStats::default());
+    }
+
+    fn record_stats<F: FnOnce(&mut ImtRasterStats)>(&self, f: F) {
+        if let Some(stats) = self.stats.as_ref() {
+            f(&mut *stats.lock());
         }
+    }
 
-        let ray_count = opts.ray_count();
+    /// Convert a bitmap into a sRGB encoded `image::RgbaImage` with straight alpha.
+    ///
+    /// Bitmaps output to an image are read back from the gpu first which requires the image
+    /// format to be `R8G8B8A8_UNORM` or `R8_UNORM`, otherwise `UnimplementedDataTy` is returned.
+    #[cfg(feature = "image")]
+    pub fn bitmap_to_rgba_image(
+        &self,
+        bitmap: &ImtGlyphBitmap,
+    ) -> Result<image::RgbaImage, ImtError> {
+        let unimplemented =
+            ImtError::src_and_ty(ImtErrorSrc::Bitmap, ImtErrorTy::UnimplementedDataTy);
 
-        for i in 0..ray_count {
-            let rad = (i as f32 * (360.0 / ray_count as f32)).to_radians();
-            samples_and_rays[i][2] = rad.cos();
-            samples_and_rays[i][3] = rad.sin();
+        #[cfg(feature = "gpu")]
+        if let Some(ImtBitmapData::Image(image)) = bitmap.data() {
+            use crate::bitmap::{lrgba_to_rgba_image, r8_to_rgba_image};
+
+            let metrics = bitmap.metrics();
+
+            return match self.read_back_image(image, &metrics)? {
+                ImtBitmapData::LRGBA(data) => {
+                    Ok(lrgba_to_rgba_image(
+                        metrics.width,
+                        metrics.height,
+                        bitmap.alpha_mode(),
+                        &data,
+                    ))
+                },
+                ImtBitmapData::R8(data) => {
+                    Ok(r8_to_rgba_image(metrics.width, metrics.height, &data))
+                },
+                _ => Err(unimplemented),
+            };
         }
 
-        let common_cpu_buf = Buffer::from_data(
-            &mem_alloc,
-            BufferCreateInfo {
-                usage: BufferUsage::TRANSFER_SRC,
-                ..Default::default()
-            },
-            AllocationCreateInfo {
-                usage: MemoryUsage::Upload,
-                ..Default::default()
-            },
-            glyph_cs::Common {
-                samples_and_rays,
-                sample_count: sample_count as u32,
-                ray_count: ray_count as u32,
+        bitmap.to_rgba_image().ok_or(unimplemented)
+    }
+
+    /// Read back a bitmap image from the gpu as `LRGBA` or `R8` data depending on the format.
+    #[cfg(feature = "gpu")]
+    fn read_back_image(
+        &self,
+        image: Arc<ImtImageView>,
+        metrics: &ImtBitmapMetrics,
+    ) -> Result<ImtBitmapData, ImtError> {
+        let (context, channels) = match self.gpu_raster_context.as_ref() {
+            Some(context) if context.raster_image_format == Format::R8G8B8A8_UNORM => (context, 4),
+            Some(context) if context.raster_image_format == Format::R8_UNORM => (context, 1),
+            _ => {
+                return Err(ImtError::src_and_ty(
+                    ImtErrorSrc::Bitmap,
+                    ImtErrorTy::UnimplementedDataTy,
+                ))
             },
-        )
-        .unwrap();
+        };
 
-        let common_dev_buf = Buffer::new_sized(
-            &mem_alloc,
+        let readback_buf: Subbuffer<[u8]> = Buffer::new_slice(
+            &*context.mem_alloc,
             BufferCreateInfo {
-                usage: BufferUsage::TRANSFER_DST | BufferUsage::UNIFORM_BUFFER,
+                usage: BufferUsage::TRANSFER_DST,
                 ..Default::default()
             },
             AllocationCreateInfo {
-                usage: MemoryUsage::DeviceOnly,
+                usage: MemoryUsage::Download,
                 ..Default::default()
             },
+            (metrics.width * metrics.height * channels) as u64,
         )
         .unwrap();
 
         let mut cmd_buf = AutoCommandBufferBuilder::primary(
-            &cmd_alloc,
-            queue.queue_family_index(),
+            &*context.cmd_alloc,
+            context.queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
         )
         .unwrap();
 
         cmd_buf
-            .copy_buffer(CopyBufferInfo::buffers(
-                common_cpu_buf,
-                common_dev_buf.clone(),
+            .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+                image,
+                readback_buf.clone(),
             ))
             .unwrap();
 
         cmd_buf
             .build()
             .unwrap()
-            .execute(queue.clone())
+            .execute(context.queue.clone())
             .unwrap()
             .then_signal_fence_and_flush()
             .unwrap()
             .wait(None)
             .unwrap();
 
-        let pipeline = ComputePipeline::new(
-            device.clone(),
-            glyph_cs.entry_point("main").unwrap(),
-            &(),
-            None,
-            |_| {},
+        let data = readback_buf.read().unwrap();
+
+        if channels == 1 {
+            Ok(ImtBitmapData::R8(Arc::new(data.to_vec())))
+        } else {
+            Ok(ImtBitmapData::LRGBA(Arc::new(
+                data.iter()
+                    .map(|v| *v as f32 / u8::max_value() as f32)
+                    .collect(),
+            )))
+        }
+    }
+
+    /// Hash of the options that effect rastered output. Used to reject stale caches.
+    fn opts_hash(&self) -> u64 {
+        let opts = &self.opts;
+        let mut bytes = Vec::new();
+        bytes.push(opts.fill_quality as u8);
+        bytes.push(opts.sample_quality as u8);
+        bytes.push(opts.align_whole_pixels as u8);
+        bytes.push(opts.cpu_rasterization as u8);
+        bytes.push(opts.alpha_mode as u8);
+        bytes.push(opts.coverage_only as u8);
+        bytes.push(opts.deterministic as u8);
+        bytes.push(opts.fill_rule as u8);
+        bytes.push(opts.hinting as u8);
+        bytes.push(opts.truetype_hinting as u8);
+        bytes.push(opts.aliased as u8);
+        bytes.extend_from_slice(&opts.subpixel_phases.to_le_bytes());
+        bytes.extend_from_slice(&opts.curve_steps.unwrap_or(0).to_le_bytes());
+        bytes.extend_from_slice(&opts.padding.to_le_bytes());
+
+        if let Some(pattern) = opts.pattern.as_ref() {
+            for [x, y] in pattern.samples.iter().chain(pattern.rays.iter()) {
+                bytes.extend_from_slice(&x.to_le_bytes());
+                bytes.extend_from_slice(&y.to_le_bytes());
+            }
+
+            bytes.extend_from_slice(&(pattern.samples.len() as u32).to_le_bytes());
+        }
+
+        fnv1a_64(&bytes)
+    }
+
+    /// Write the completed bitmaps of the cache to `writer`.
+    ///
+    /// Bitmaps output to an image are read back from the gpu first. The font's hash and the
+    /// options are stored so that `import_cache` can reject a stale cache.
+    pub fn export_cache<W: Write>(
+        &self,
+        parser: &ImtParser,
+        mut writer: W,
+    ) -> Result<(), ImtError> {
+        let entries: Vec<(RasterCacheKey, Arc<ImtGlyphBitmap>)> = self
+            .cache
+            .lock()
+            .iter()
+            .filter_map(|(key, state)| {
+                match state {
+                    RasterCacheState::Completed(bitmap) => Some((*key, bitmap.clone())),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        let mut encoded = Vec::with_capacity(entries.len());
+
+        for (key, bitmap) in entries {
+            let metrics = bitmap.metrics();
+
+            let data = match bitmap.data() {
+                #[cfg(feature = "gpu")]
+                Some(ImtBitmapData::Image(image)) => self.read_back_image(image, &metrics)?,
+                Some(data) => data,
+                None => ImtBitmapData::Empty,
+            };
+
+            encoded.push((key, metrics, data));
+        }
+
+        let bytes = encode_cache(parser.font_hash()?, self.opts_hash(), &encoded);
+
+        writer
+            .write_all(&bytes)
+            .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::Cache, ImtErrorTy::FileWrite))
+    }
+
+    /// Load bitmaps previously written with `export_cache` into the cache.
+    ///
+    /// Returns `CacheStale` if the cache was exported with a different font or options. Entries
+    /// already in the cache are kept. Imported bitmaps always contain raw data even if the
+    /// options specify `raster_to_image`.
+    pub fn import_cache<R: Read>(&self, parser: &ImtParser, mut reader: R) -> Result<(), ImtError> {
+        let mut bytes = Vec::new();
+
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|_| ImtError::src_and_ty(ImtErrorSrc::Cache, ImtErrorTy::FileRead))?;
+
+        let entries = decode_cache(&bytes, parser.font_hash()?, self.opts_hash())?;
+        let mut cache = self.cache.lock();
+
+        for (key, metrics, data) in entries {
+            let (_, index, fake_bold, fake_italic, small_caps, _) = key;
+
+            let bitmap = ImtGlyphBitmap::from_data(
+                index,
+                fake_bold,
+                fake_italic,
+                small_caps,
+                metrics,
+                data,
+                &self.opts,
+            );
+
+            cache
+                .entry(key)
+                .or_insert_with(|| RasterCacheState::Completed(Arc::new(bitmap)));
+        }
+
+        Ok(())
+    }
+
+    /// Cache key and the sub-pixel phase offset in pixels of a shaped glyph.
+    fn cache_key(
+        &self,
+        shaped: &ImtShapedGlyph,
+        height_key: OrderedFloat<f32>,
+        px_scaler: f32,
+    ) -> (RasterCacheKey, f32) {
+        let (_, phase) = self.opts.subpixel_phase(shaped.position.x * px_scaler);
+
+        (
+            (
+                height_key,
+                shaped.parsed.inner.glyph_index,
+                shaped.parsed.inner.fake_bold,
+                shaped.parsed.inner.fake_italic,
+                shaped.parsed.inner.small_caps,
+                phase,
+            ),
+            phase as f32 / self.opts.subpixel_phases.max(1) as f32,
         )
-        .unwrap();
+    }
 
-        let set_alloc = StandardDescriptorSetAllocator::new(device.clone());
-        let raster_to_image = opts.raster_to_image;
-        let raster_image_format = opts.raster_image_format;
+    fn new_bitmap(
+        &self,
+        parser: &ImtParser,
+        shaped: &ImtShapedGlyph,
+        text_height: f32,
+        phase_offset: f32,
+    ) -> Result<ImtGlyphBitmap, ImtError> {
+        let mut bitmap =
+            ImtGlyphBitmap::new(parser, shaped.parsed.clone(), text_height, &self.opts)?;
 
-        Ok(ImtRaster {
-            opts,
-            cache: Mutex::new(BTreeMap::new()),
-            gpu_raster_context: Some(GpuRasterContext {
-                device,
-                queue,
-                mem_alloc,
-                cmd_alloc,
-                glyph_cs,
-                common_buf: common_dev_buf,
-                pipeline,
-                set_alloc,
-                raster_to_image,
-                raster_image_format,
-            }),
-            cpu_raster_context: None,
+        // Hinting & creating the outline both scale with the size, so check it beforehand.
+        self.check_extent(&bitmap)?;
+
+        if self.opts.truetype_hinting {
+            // Glyphs that fail to hint are rastered unhinted rather than failing.
+            if let Ok(Some(hinted)) = parser.hint_glyph(shaped.parsed.clone(), text_height) {
+                let opts = ImtRasterOpts {
+                    hinting: false,
+                    ..self.opts.clone()
+                };
+
+                bitmap = ImtGlyphBitmap::new(parser, hinted, text_height, &opts)?;
+            }
+        }
+
+        bitmap.apply_x_phase(phase_offset);
+        self.check_extent(&bitmap)?;
+        bitmap.create_outline();
+        Ok(bitmap)
+    }
+
+    /// Largest width or height a glyph bitmap may have. This is `max_bitmap_dimension` of the
+    /// options, limited by the device's maximum image dimension when rasterizing on the gpu.
+    pub fn max_bitmap_dimension(&self) -> u32 {
+        #[cfg(feature = "gpu")]
+        if let Some(context) = self.gpu_raster_context.as_ref() {
+            return self.opts.max_bitmap_dimension.min(
+                context
+                    .device
+                    .physical_device()
+                    .properties()
+                    .max_image_dimension2_d,
+            );
+        }
+
+        self.opts.max_bitmap_dimension
+    }
+
+    /// Error if the bitmap is larger than `max_bitmap_dimension` allows.
+    fn check_extent(&self, bitmap: &ImtGlyphBitmap) -> Result<(), ImtError> {
+        let metrics = bitmap.metrics();
+        let max_dimension = self.max_bitmap_dimension();
+
+        if metrics.width > max_dimension || metrics.height > max_dimension {
+            return Err(ImtError::src_and_ty(
+                ImtErrorSrc::Bitmap,
+                ImtErrorTy::BitmapTooLarge {
+                    width: metrics.width,
+                    height: metrics.height,
+                    max_dimension,
+                },
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Same as `raster_shaped_glyphs`, but returns a job that can be polled instead of blocking.
+    ///
+    /// Glyphs missing from the cache are recorded into a single command buffer and added to the
+    /// cache once the job completes. When using cpu rasterization the job is already complete.
+    pub fn raster_shaped_glyphs_deferred(
+        &self,
+        parser: &ImtParser,
+        text_height: f32,
+        shaped_glyphs: Vec<ImtShapedGlyph>,
+    ) -> Result<ImtRasterJob<'_>, ImtError> {
+        #[cfg(feature = "gpu")]
+        if !self.opts.cpu_rasterization {
+            return self.record_shaped_glyphs(parser, text_height, shaped_glyphs);
+        }
+
+        Ok(ImtRasterJob {
+            raster: self,
+            #[cfg(feature = "gpu")]
+            future: None,
+            glyphs: self
+                .raster_shaped_glyphs(parser, text_height, shaped_glyphs)?
+                .into_iter()
+                .map(|rastered| (rastered.shaped, RasterJobBitmap::Ready(rastered.bitmap)))
+                .collect(),
+            pending: Vec::new(),
+            #[cfg(feature = "gpu")]
+            timing: None,
+            finished: false,
         })
     }
 
-    pub fn new_cpu(mut opts: ImtRasterOpts) -> Result<Self, ImtError> {
-        opts.cpu_rasterization = true;
-        let sample_count = opts.sample_count();
-        let ray_count = opts.ray_count();
-        let mut samples = Vec::with_capacity(sample_count);
-        let mut rays = Vec::with_capacity(ray_count);
-        let w = (sample_count as f32).sqrt() as usize;
+    /// Record the glyphs missing from the cache into a single command buffer and submit it.
+    #[cfg(feature = "gpu")]
+    fn record_shaped_glyphs(
+        &self,
+        parser: &ImtParser,
+        text_height: f32,
+        shaped_glyphs: Vec<ImtShapedGlyph>,
+    ) -> Result<ImtRasterJob<'_>, ImtError> {
+        let context = self.gpu_raster_context.as_ref().unwrap();
+        let height_key = OrderedFloat::from(text_height);
+        let px_scaler = parser.font_props()?.scaler * text_height;
+        let mut glyphs = Vec::with_capacity(shaped_glyphs.len());
+        let mut pending: Vec<(RasterCacheKey, ImtGlyphBitmap)> = Vec::new();
+        let mut cmd_buf_op = None;
+        let mut timing = None;
 
-        for x in 1..=w {
-            for y in 1..=w {
-                samples.push([
-                    ((x as f32 / (w as f32 + 1.0)) * 2.0) - 1.0,
-                    ((y as f32 / (w as f32 + 1.0)) * 2.0) - 1.0,
-                ]);
+        for shaped in shaped_glyphs {
+            let (cache_key, phase_offset) = self.cache_key(&shaped, height_key, px_scaler);
+
+            let cached = match self.cache.lock().get(&cache_key) {
+                Some(RasterCacheState::Completed(bitmap)) => Some(bitmap.clone()),
+                _ => None,
+            };
+
+            if let Some(bitmap) = cached {
+                self.record_stats(|stats| stats.cache_hits += 1);
+                glyphs.push((shaped, RasterJobBitmap::Ready(bitmap)));
+                continue;
+            }
+
+            if let Some(i) = pending.iter().position(|(key, _)| *key == cache_key) {
+                self.record_stats(|stats| stats.cache_hits += 1);
+                glyphs.push((shaped, RasterJobBitmap::Pending(i)));
+                continue;
             }
+
+            self.record_stats(|stats| stats.cache_misses += 1);
+
+            let mut bitmap = self.new_bitmap(parser, &shaped, text_height, phase_offset)?;
+
+            if !bitmap.raster_empty() {
+                if cmd_buf_op.is_none() {
+                    let mut cmd_buf = AutoCommandBufferBuilder::primary(
+                        &*context.cmd_alloc,
+                        context.queue.queue_family_index(),
+                        CommandBufferUsage::OneTimeSubmit,
+                    )
+                    .map_err(ImtError::gpu)?;
+
+                    timing = context.begin_timing(&mut cmd_buf)?;
+                    cmd_buf_op = Some(cmd_buf);
+                }
+
+                bitmap.record_gpu(context, cmd_buf_op.as_mut().unwrap())?;
+            }
+
+            glyphs.push((shaped, RasterJobBitmap::Pending(pending.len())));
+            pending.push((cache_key, bitmap));
         }
 
-        for i in 0..ray_count {
-            let rad = (i as f32 * (360.0 / ray_count as f32)).to_radians();
-            rays.push([rad.cos(), rad.sin()]);
+        if let (Some(cmd_buf), Some(timing)) = (cmd_buf_op.as_mut(), timing.as_ref()) {
+            timing.end(cmd_buf)?;
         }
 
-        Ok(ImtRaster {
-            opts,
-            cache: Mutex::new(BTreeMap::new()),
-            gpu_raster_context: None,
-            cpu_raster_context: Some(CpuRasterContext {
-                samples,
-                rays,
-            }),
+        let future = match cmd_buf_op {
+            Some(cmd_buf) => {
+                Some(
+                    cmd_buf
+                        .build()
+                        .map_err(ImtError::gpu)?
+                        .execute(context.queue.clone())
+                        .map_err(ImtError::gpu)?
+                        .boxed()
+                        .then_signal_fence_and_flush()
+                        .map_err(ImtError::gpu)?,
+                )
+            },
+            None => None,
+        };
+
+        Ok(ImtRasterJob {
+            raster: self,
+            future,
+            glyphs,
+            pending,
+            timing,
+            finished: false,
         })
     }
 
-    pub fn sample_count(&self) -> usize {
-        self.opts.sample_count()
+    /// Add a rastered bitmap to the cache, waking any threads waiting for it to be rastered. A
+    /// bitmap another thread has already completed is kept.
+    fn cache_completed(
+        cache: &mut BTreeMap<RasterCacheKey, RasterCacheState>,
+        cache_key: RasterCacheKey,
+        bitmap: Arc<ImtGlyphBitmap>,
+    ) {
+        if let Some(RasterCacheState::Completed(_)) = cache.get(&cache_key) {
+            return;
+        }
+
+        // Another thread rastering the same glyph will replace this with its own bitmap, which
+        // is equivalent. Its waiting threads are woken now as the glyph is available.
+        if let Some(RasterCacheState::Incomplete(unparkers)) =
+            cache.insert(cache_key, RasterCacheState::Completed(bitmap))
+        {
+            for unparker in unparkers {
+                unparker.unpark();
+            }
+        }
     }
 
-    pub fn ray_count(&self) -> usize {
-        self.opts.ray_count()
+    /// Rasterize a bitmap on the gpu or cpu, returning the gpu time if it was measured.
+    fn raster_bitmap(&self, bitmap: &mut ImtGlyphBitmap) -> Result<Option<f64>, ImtError> {
+        #[cfg(feature = "gpu")]
+        if !self.opts.cpu_rasterization {
+            return bitmap.raster_gpu(self.gpu_raster_context.as_ref().unwrap());
+        }
+
+        bitmap
+            .raster_cpu(self.cpu_raster_context.as_ref().unwrap())
+            .map(|_| None)
+    }
+
+    /// Rasterize a glyph with its outline transformed by `transform`, allowing for rotated,
+    /// slanted or mirrored glyphs. See `ImtParsedGlyph::transformed` for the layout of
+    /// `transform`.
+    ///
+    /// The metrics of the bitmap are those of the transformed outline. Transformed glyphs
+    /// bypass the cache and are never hinted.
+    pub fn raster_transformed_glyph(
+        &self,
+        parser: &ImtParser,
+        text_height: f32,
+        shaped: ImtShapedGlyph,
+        transform: &[f32; 6],
+    ) -> Result<ImtRasteredGlyph, ImtError> {
+        let px_scaler = parser.font_props()?.scaler * text_height;
+        let (_, phase_offset) = self.cache_key(&shaped, OrderedFloat::from(text_height), px_scaler);
+
+        let opts = ImtRasterOpts {
+            hinting: false,
+            truetype_hinting: false,
+            ..self.opts.clone()
+        };
+
+        let mut bitmap = ImtGlyphBitmap::new(
+            parser,
+            Arc::new(shaped.parsed.transformed(transform)),
+            text_height,
+            &opts,
+        )?;
+
+        bitmap.apply_x_phase(phase_offset);
+        self.check_extent(&bitmap)?;
+        bitmap.create_outline();
+        let gpu_time_ms = self.raster_bitmap(&mut bitmap)?;
+
+        self.record_stats(|stats| {
+            stats.glyphs_rastered += 1;
+            stats.gpu_time_ms += gpu_time_ms.unwrap_or(0.0);
+        });
+
+        Ok(ImtRasteredGlyph {
+            shaped,
+            bitmap: Arc::new(bitmap),
+        })
     }
 
     #[allow(unused_assignments)]
@@ -309,9 +1964,10 @@ impl ImtRaster {
         let mut rastered_glyphs_out = Vec::new();
         let mut cache_lk_op = None;
         let height_key = OrderedFloat::from(text_height);
+        let px_scaler = parser.font_props()?.scaler * text_height;
 
         'glyphs: for shaped in shaped_glyphs {
-            let index = shaped.parsed.inner.glyph_index;
+            let (cache_key, phase_offset) = self.cache_key(&shaped, height_key, px_scaler);
 
             // Acquire a lock to the cache if it isn't already present
             if cache_lk_op.is_none() {
@@ -321,10 +1977,12 @@ impl ImtRaster {
             let mut parker_op = None;
 
             // Obtain the current cache state
-            if let Some(cache_state) = cache_lk_op.as_mut().unwrap().get_mut(&(height_key, index)) {
+            if let Some(cache_state) = cache_lk_op.as_mut().unwrap().get_mut(&cache_key) {
                 match cache_state {
                     // This glyph has already be completed!
                     &mut RasterCacheState::Completed(ref bitmap) => {
+                        self.record_stats(|stats| stats.cache_hits += 1);
+
                         rastered_glyphs_out.push(ImtRasteredGlyph {
                             shaped,
                             bitmap: bitmap.clone(),
@@ -357,15 +2015,13 @@ impl ImtRaster {
 
                     // Should be safe to unwrap as the state should already be present given
                     // the previous logic.
-                    let cache_state = cache_lk_op
-                        .as_ref()
-                        .unwrap()
-                        .get(&(height_key, index))
-                        .unwrap();
+                    let cache_state = cache_lk_op.as_mut().unwrap().get_mut(&cache_key).unwrap();
 
                     match cache_state {
                         // As expected the glyph is completed.
-                        &RasterCacheState::Completed(ref bitmap) => {
+                        &mut RasterCacheState::Completed(ref bitmap) => {
+                            self.record_stats(|stats| stats.cache_hits += 1);
+
                             rastered_glyphs_out.push(ImtRasteredGlyph {
                                 shaped,
                                 bitmap: bitmap.clone(),
@@ -373,15 +2029,23 @@ impl ImtRaster {
 
                             continue 'glyphs;
                         },
-                        // Seems this thread has spuriously woken up, go back to sleep.
-                        &RasterCacheState::Incomplete(_) => continue,
+                        // Either this thread has spuriously woken up or a previous attempt
+                        // errored and another thread has since started rasterizing. In the
+                        // latter case the unparkers were replaced, so register again while
+                        // the lock is held before going back to sleep. Parking consumes any
+                        // unpark that happens after this, so the wakeup can't be missed.
+                        &mut RasterCacheState::Incomplete(ref mut unparkers) => {
+                            unparkers.push(parker.unparker().clone());
+                            continue;
+                        },
                         // The last attempted seem'd to have error, try again why not.
-                        &RasterCacheState::Errored(_) => break,
+                        &mut RasterCacheState::Errored(_) => break,
                     }
                 }
             }
 
             // Made it here, so assume that the glyph needs to be rasterized yet.
+            self.record_stats(|stats| stats.cache_misses += 1);
 
             // The cache lock should still be held, but check.
             if cache_lk_op.is_none() {
@@ -389,66 +2053,58 @@ impl ImtRaster {
             }
 
             // Update the cache to inform it that this thread is going to rasterize the glyph.
-            cache_lk_op.as_mut().unwrap().insert(
-                (height_key, index),
-                RasterCacheState::Incomplete(Vec::new()),
-            );
+            cache_lk_op
+                .as_mut()
+                .unwrap()
+                .insert(cache_key, RasterCacheState::Incomplete(Vec::new()));
 
             // Drop the lock so other threads can keep doing things.
             cache_lk_op = None;
 
-            let mut bitmap =
-                ImtGlyphBitmap::new(parser, shaped.parsed.clone(), text_height, &self.opts);
-            bitmap.create_outline();
+            let rastered = self
+                .new_bitmap(parser, &shaped, text_height, phase_offset)
+                .and_then(|mut bitmap| {
+                    let gpu_time_ms = self.raster_bitmap(&mut bitmap)?;
+                    Ok((bitmap, gpu_time_ms))
+                });
 
-            let raster_result = if self.opts.cpu_rasterization {
-                bitmap.raster_cpu(self.cpu_raster_context.as_ref().unwrap())
-            } else {
-                bitmap.raster_gpu(self.gpu_raster_context.as_ref().unwrap())
-            };
-
-            if let Err(e) = raster_result {
-                // Seems we have errored, up the cache and inform other threads.
-                // Reobtain the lock
-                cache_lk_op = Some(self.cache.lock());
+            let (bitmap, gpu_time_ms) = match rastered {
+                Ok(rastered) => rastered,
+                Err(e) => {
+                    // Seems we have errored, up the cache and inform other threads.
+                    // Reobtain the lock
+                    cache_lk_op = Some(self.cache.lock());
 
-                // Update the state to errored and retrieve the old one.
-                let old_state = cache_lk_op
-                    .as_mut()
-                    .unwrap()
-                    .insert((height_key, index), RasterCacheState::Errored(e.clone()));
+                    // Update the state to errored and retrieve the old one.
+                    let old_state = cache_lk_op
+                        .as_mut()
+                        .unwrap()
+                        .insert(cache_key, RasterCacheState::Errored(e.clone()));
 
-                // Inform all the other threads that may have been waiting.
-                if let Some(RasterCacheState::Incomplete(unparkers)) = old_state {
-                    for unparker in unparkers {
-                        unparker.unpark();
+                    // Inform all the other threads that may have been waiting.
+                    if let Some(RasterCacheState::Incomplete(unparkers)) = old_state {
+                        for unparker in unparkers {
+                            unparker.unpark();
+                        }
                     }
-                }
 
-                // Finally return the error
-                return Err(e);
-            }
+                    // Finally return the error
+                    return Err(e);
+                },
+            };
 
             // The glyph seems to have rastered sucessfully!
+            self.record_stats(|stats| {
+                stats.glyphs_rastered += 1;
+                stats.gpu_time_ms += gpu_time_ms.unwrap_or(0.0);
+            });
 
             // Wrap the bitmap into its final form.
             let bitmap = Arc::new(bitmap);
 
-            // Reobtain the lock
+            // Reobtain the lock, then complete the state & inform any waiting threads.
             cache_lk_op = Some(self.cache.lock());
-
-            // Update the state to completed and retrieve the old one.
-            let old_state = cache_lk_op.as_mut().unwrap().insert(
-                (height_key, index),
-                RasterCacheState::Completed(bitmap.clone()),
-            );
-
-            // Inform all the other threads that may have been waiting.
-            if let Some(RasterCacheState::Incomplete(unparkers)) = old_state {
-                for unparker in unparkers {
-                    unparker.unpark();
-                }
-            }
+            Self::cache_completed(cache_lk_op.as_mut().unwrap(), cache_key, bitmap.clone());
 
             rastered_glyphs_out.push(ImtRasteredGlyph {
                 shaped,
@@ -459,3 +2115,306 @@ impl ImtRaster {
         Ok(rastered_glyphs_out)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Barrier;
+
+    use super::*;
+    use crate::parse::direct_glyph;
+    use crate::test_font::TestFont;
+    use crate::{ImtCmapEncoding, ImtFontProps, ImtParsedGlyph, ImtPosition};
+
+    fn phases(subpixel_phases: u32) -> ImtRasterOpts {
+        ImtRasterOpts {
+            subpixel_phases,
+            ..ImtRasterOpts::default()
+        }
+    }
+
+    #[test]
+    fn subpixel_phase_disabled() {
+        for subpixel_phases in [0, 1] {
+            let opts = phases(subpixel_phases);
+            assert_eq!(opts.subpixel_phase(10.3), (10.3, 0));
+            assert_eq!(opts.subpixel_phase(-2.7), (-2.7, 0));
+        }
+    }
+
+    #[test]
+    fn subpixel_phase_buckets() {
+        let opts = phases(4);
+        assert_eq!(opts.subpixel_phase(10.0), (10.0, 0));
+        assert_eq!(opts.subpixel_phase(10.1), (10.0, 0));
+        assert_eq!(opts.subpixel_phase(10.2), (10.0, 1));
+        assert_eq!(opts.subpixel_phase(10.5), (10.0, 2));
+        assert_eq!(opts.subpixel_phase(10.7), (10.0, 3));
+
+        // Rounding up to a whole phase moves to the next pixel.
+        assert_eq!(opts.subpixel_phase(10.9), (11.0, 0));
+
+        // Negative positions are split toward negative infinity.
+        assert_eq!(opts.subpixel_phase(-0.25), (-1.0, 3));
+        assert_eq!(opts.subpixel_phase(-0.05), (0.0, 0));
+    }
+
+    #[test]
+    fn subpixel_phase_offsets() {
+        // The whole pixel plus the phase's offset stays within half a phase of the position.
+        for subpixel_phases in [2, 3, 4, 8] {
+            let opts = phases(subpixel_phases);
+
+            for i in -100..100 {
+                let x = i as f32 * 0.137;
+                let (whole, phase) = opts.subpixel_phase(x);
+                assert!(phase < subpixel_phases);
+                assert_eq!(whole, whole.floor());
+
+                let snapped = whole + (phase as f32 / subpixel_phases as f32);
+                assert!((snapped - x).abs() <= (0.5 / subpixel_phases as f32) + 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn pen_rounding() {
+        let mut opts = ImtRasterOpts {
+            align_whole_pixels: true,
+            subpixel_phases: 1,
+            pixel_scale: 1.0,
+            ..ImtRasterOpts::default()
+        };
+
+        assert_eq!(opts.pen_position(10.4, 3.6), (10.0, 4.0, 0));
+        assert_eq!(opts.pen_position(-2.6, -0.4), (-3.0, 0.0, 0));
+
+        // Rounded to device pixels.
+        opts.pixel_scale = 2.0;
+        assert_eq!(opts.pen_position(10.3, 3.6), (10.5, 3.5, 0));
+
+        // The phase is split off of the horizontal position before rounding.
+        opts.pixel_scale = 1.0;
+        opts.subpixel_phases = 4;
+        assert_eq!(opts.pen_position(10.3, 3.6), (10.0, 4.0, 1));
+        assert_eq!(opts.pen_position(10.9, 3.6), (11.0, 4.0, 0));
+
+        // Without alignment the pen is left as is.
+        opts.align_whole_pixels = false;
+        opts.subpixel_phases = 1;
+        assert_eq!(opts.pen_position(10.3, 3.6), (10.3, 3.6, 0));
+    }
+
+    /// Bitmap of a glyph with a 1000 by 1000 unit outline in a 1000 units per em font.
+    fn sized_bitmap(text_height: f32, opts: &ImtRasterOpts) -> ImtGlyphBitmap {
+        let parsed = ImtParsedGlyph {
+            inner: direct_glyph(0),
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: 1000.0,
+            max_y: 1000.0,
+            hori_adv: 1000.0,
+            is_mark: false,
+            contours: Vec::new(),
+        };
+
+        let font_props = ImtFontProps {
+            scaler: 1.0 / 1000.0,
+            ascender: 1000.0,
+            descender: 0.0,
+            line_gap: 0.0,
+            min_x: 0.0,
+            max_x: 1000.0,
+            min_y: 0.0,
+            max_y: 1000.0,
+            bbox: [0.0, 0.0, 1000.0, 1000.0],
+            cap_height: None,
+            x_height: None,
+            is_monospace: false,
+            underline_position: None,
+            underline_thickness: None,
+            strikeout_position: None,
+            strikeout_thickness: None,
+            cmap_encoding: ImtCmapEncoding::Unicode,
+        };
+
+        let metrics = ImtGlyphBitmap::extent(&parsed, &font_props, text_height, opts);
+        ImtGlyphBitmap::from_data(0, false, false, false, metrics, ImtBitmapData::Empty, opts)
+    }
+
+    #[test]
+    fn bitmap_extent_limit() {
+        let raster = ImtRaster::new_cpu(ImtRasterOpts {
+            max_bitmap_dimension: 4096,
+            padding: 1,
+            ..ImtRasterOpts::default()
+        })
+        .unwrap();
+
+        let opts = raster.opts().clone();
+        assert!(raster.check_extent(&sized_bitmap(16.0, &opts)).is_ok());
+
+        for text_height in [5000.0, 1.0e9, 1.0e30, f32::MAX] {
+            let err = raster
+                .check_extent(&sized_bitmap(text_height, &opts))
+                .unwrap_err();
+
+            match err.ty {
+                ImtErrorTy::BitmapTooLarge {
+                    max_dimension, ..
+                } => assert_eq!(max_dimension, 4096),
+                ty => panic!("unexpected error {:?} at {}", ty, text_height),
+            }
+        }
+    }
+
+    fn cache_entries() -> Vec<CacheEntry> {
+        let metrics = |width, height| {
+            ImtBitmapMetrics {
+                width,
+                height,
+                bearing_x: -1.5,
+                bearing_y: 12.25,
+            }
+        };
+
+        vec![
+            (
+                (OrderedFloat(14.0), 36, false, false, false, 0),
+                metrics(0, 0),
+                ImtBitmapData::Empty,
+            ),
+            (
+                (OrderedFloat(14.0), 37, true, false, false, 2),
+                metrics(2, 3),
+                ImtBitmapData::LRGBA(Arc::new((0..24).map(|i| i as f32 / 23.0).collect())),
+            ),
+            (
+                (OrderedFloat(20.5), 38, false, true, true, 1),
+                metrics(3, 2),
+                ImtBitmapData::R8(Arc::new((0..6).map(|i| i * 40).collect())),
+            ),
+        ]
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let entries = cache_entries();
+        let bytes = encode_cache(1, 2, &entries);
+        let decoded = decode_cache(&bytes, 1, 2).unwrap();
+        assert_eq!(decoded.len(), entries.len());
+
+        for ((key, metrics, data), (expected_key, expected_metrics, expected_data)) in
+            decoded.iter().zip(entries.iter())
+        {
+            assert_eq!(key, expected_key);
+            assert_eq!(metrics.width, expected_metrics.width);
+            assert_eq!(metrics.height, expected_metrics.height);
+            assert_eq!(metrics.bearing_x, expected_metrics.bearing_x);
+            assert_eq!(metrics.bearing_y, expected_metrics.bearing_y);
+
+            match (data, expected_data) {
+                (ImtBitmapData::Empty, ImtBitmapData::Empty) => (),
+                (ImtBitmapData::LRGBA(data), ImtBitmapData::LRGBA(expected)) => {
+                    assert_eq!(data, expected)
+                },
+                (ImtBitmapData::R8(data), ImtBitmapData::R8(expected)) => {
+                    assert_eq!(data, expected)
+                },
+                _ => panic!("data of {:?} changed variant", key),
+            }
+        }
+    }
+
+    #[test]
+    fn cache_rejects_mismatch() {
+        let bytes = encode_cache(1, 2, &cache_entries());
+        let err_ty = |bytes: &[u8]| decode_cache(bytes, 1, 2).err().unwrap().ty;
+
+        // A different font or options.
+        assert_eq!(
+            decode_cache(&bytes, 3, 2).err().unwrap().ty,
+            ImtErrorTy::CacheStale
+        );
+        assert_eq!(
+            decode_cache(&bytes, 1, 3).err().unwrap().ty,
+            ImtErrorTy::CacheStale
+        );
+
+        assert_eq!(err_ty(b"NOPE"), ImtErrorTy::FileUnsupportedFormat);
+        assert_eq!(err_ty(&bytes[..bytes.len() - 1]), ImtErrorTy::FileBadEof);
+
+        let mut bad_version = bytes.clone();
+        bad_version[4] ^= 0xff;
+        assert_eq!(err_ty(&bad_version), ImtErrorTy::FileBadVersion);
+    }
+
+    #[test]
+    fn concurrent_rasters_share_bitmaps() {
+        const THREADS: usize = 16;
+        const ROUNDS: usize = 50;
+
+        let test_font = TestFont::new();
+        let parser = ImtParser::new(test_font.build()).unwrap();
+        let parsed = parser.retrieve_glyph(test_font.glyph_index('O')).unwrap();
+        let barrier = Barrier::new(THREADS);
+
+        let raster = ImtRaster::new_cpu(ImtRasterOpts {
+            collect_stats: true,
+            ..ImtRasterOpts::default()
+        })
+        .unwrap();
+
+        // Every thread rasters the same glyph at once each round, so all but one wait on the
+        // thread rastering it. A missed wakeup leaves a thread parked forever.
+        let bitmaps: Vec<Vec<Arc<ImtGlyphBitmap>>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..ROUNDS)
+                            .map(|round| {
+                                let shaped = ImtShapedGlyph {
+                                    parsed: parsed.clone(),
+                                    position: ImtPosition {
+                                        x: 0.0,
+                                        y: 0.0,
+                                    },
+                                    x_overflow: 0.0,
+                                    y_overflow: 0.0,
+                                    x_underflow: 0.0,
+                                    y_underflow: 0.0,
+                                    unicodes: Vec::new(),
+                                };
+
+                                barrier.wait();
+
+                                raster
+                                    .raster_shaped_glyphs(&parser, 8.0 + round as f32, vec![shaped])
+                                    .unwrap()
+                                    .pop()
+                                    .unwrap()
+                                    .bitmap
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        for thread_bitmaps in bitmaps.iter() {
+            for (bitmap, expected) in thread_bitmaps.iter().zip(bitmaps[0].iter()) {
+                assert!(Arc::ptr_eq(bitmap, expected));
+            }
+        }
+
+        // Each glyph was rastered once, with the other threads given the cached bitmap.
+        let stats = raster.stats().unwrap();
+        assert_eq!(stats.glyphs_rastered, ROUNDS as u64);
+        assert_eq!(stats.cache_misses, ROUNDS as u64);
+        assert_eq!(stats.cache_hits, ((THREADS - 1) * ROUNDS) as u64);
+    }
+}
@@ -25,3 +25,33 @@ impl ImtLang {
         }
     }
 }
+
+/// An OpenType feature tag such as `liga`, `onum` or `ss01`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImtFeatureTag(pub u32);
+
+impl ImtFeatureTag {
+    /// Contextual alternates, replacing glyphs based on their neighbors.
+    pub const CALT: Self = Self::new(b"calt");
+    pub const CLIG: Self = Self::new(b"clig");
+    pub const KERN: Self = Self::new(b"kern");
+    pub const LIGA: Self = Self::new(b"liga");
+
+    pub const fn new(tag: &[u8; 4]) -> Self {
+        ImtFeatureTag(u32::from_be_bytes(*tag))
+    }
+
+    /// The stylistic set feature `ss01` through `ss20`. `None` if `set` isn't within `1..=20`.
+    pub fn stylistic_set(set: u8) -> Option<Self> {
+        if !(1..=20).contains(&set) {
+            return None;
+        }
+
+        Some(Self::new(&[
+            b's',
+            b's',
+            b'0' + (set / 10),
+            b'0' + (set % 10),
+        ]))
+    }
+}
@@ -8,28 +8,38 @@ pub mod glyph_cs {
 
 layout(local_size_x = 1, local_size_y = 1, local_size_z = 1) in;
 
+// Set 0 is shared by all glyphs, set 1 is per glyph.
 layout(set = 0, binding = 0) readonly uniform Common {
-	vec4 samples_and_rays[25];
 	uint sample_count;
 	uint ray_count;
+	uint premultiplied;
+	uint coverage_only;
+	uint nonzero;
+	uint aliased;
 } com;
 
-layout(set = 0, binding = 1) readonly uniform Glyph {
+// Sample offsets in xy & ray directions in zw.
+layout(set = 0, binding = 1) readonly buffer SamplesAndRays {
+	vec4 samples_and_rays[];
+} sar;
+
+layout(set = 1, binding = 0) readonly uniform Glyph {
 	float scaler;
 	uint width;
 	uint height;
 	uint line_count;
 	vec4 bounds;
 	vec2 offset;
+	float dilate;
 } glyph;
 
-layout(set = 0, binding = 2) writeonly uniform image2D bitmap;
+layout(set = 1, binding = 1) writeonly uniform image2D bitmap;
 
-layout(set = 0, binding = 3) readonly buffer Line {
+layout(set = 1, binding = 2) readonly buffer Line {
 	vec4 line[];
 } lines;
 
-bool ray_intersects(vec2 l1p1, vec2 l1p2, vec2 l2p1, vec2 l2p2, out vec2 point) {
+bool ray_intersects(vec2 l1p1, vec2 l1p2, vec2 l2p1, vec2 l2p2, out vec2 point, out int dir) {
 	vec2 r = l1p2 - l1p1;
 	vec2 s = l2p2 - l2p1;
 	float det = r.x * s.y - r.y * s.x;
@@ -38,6 +48,7 @@ bool ray_intersects(vec2 l1p1, vec2 l1p2, vec2 l2p1, vec2 l2p2, out vec2 point)
 	
 	if ((t >= 0. && t <= 1.) && (u >= 0. && u <= 1.)) {
 		point = l1p1 + r * t;
+		dir = det > 0.0 ? 1 : -1;
 		return true;
 	} else {
 		return false;
@@ -46,6 +57,7 @@ bool ray_intersects(vec2 l1p1, vec2 l1p2, vec2 l2p1, vec2 l2p2, out vec2 point)
 
 bool sample_filled(vec2 ray_src, float ray_len, out float fill_amt) {
 	vec2 intersect_point = vec2(0.0);
+	int intersect_dir = 0;
 	int rays_filled = 0;
 	float ray_fill_amt = 0.0;
 	float cell_height = (glyph.scaler / sqrt(com.sample_count));
@@ -53,8 +65,9 @@ bool sample_filled(vec2 ray_src, float ray_len, out float fill_amt) {
 	
 	for(uint ray_dir_i = 0; ray_dir_i < com.ray_count; ray_dir_i++) {
 		int hits = 0;
-		vec2 ray_dest = ray_src + (com.samples_and_rays[ray_dir_i].zw * ray_len);
-		float ray_angle = atan(com.samples_and_rays[ray_dir_i].w / com.samples_and_rays[ray_dir_i].z);
+		int winding = 0;
+		vec2 ray_dest = ray_src + (sar.samples_and_rays[ray_dir_i].zw * ray_len);
+		float ray_angle = atan(sar.samples_and_rays[ray_dir_i].w / sar.samples_and_rays[ray_dir_i].z);
 		float ray_max_dist = (cell_width / 2.0) / cos(ray_angle);
 
 		if(ray_max_dist > (cell_height / 2.0)) {
@@ -64,7 +77,7 @@ bool sample_filled(vec2 ray_src, float ray_len, out float fill_amt) {
 		float ray_min_dist = ray_max_dist;
 		
 		for(uint line_i = 0; line_i < glyph.line_count; line_i ++) {
-			if(ray_intersects(ray_src, ray_dest, lines.line[line_i].xy, lines.line[line_i].zw, intersect_point)) {
+			if(ray_intersects(ray_src, ray_dest, lines.line[line_i].xy, lines.line[line_i].zw, intersect_point, intersect_dir)) {
 				float dist = distance(ray_src, intersect_point);
 				
 				if(dist < ray_min_dist) {
@@ -72,10 +85,11 @@ bool sample_filled(vec2 ray_src, float ray_len, out float fill_amt) {
 				}
 				
 				hits++;
+				winding += intersect_dir;
 			}
 		}
 
-		if(hits % 2 != 0) {
+		if(com.nonzero != 0 ? winding != 0 : hits % 2 != 0) {
 			rays_filled++;
 			ray_fill_amt += ray_min_dist / ray_max_dist;
 		}
@@ -93,7 +107,7 @@ vec2 transform_coords(uint offset_i, vec2 offset) {
 	vec2 coords = vec2(float(gl_GlobalInvocationID.x), float(gl_GlobalInvocationID.y) * -1.0);
 	coords -= glyph.offset;
 	// Apply the pixel offset for sampling
-	coords += com.samples_and_rays[offset_i].xy;
+	coords += sar.samples_and_rays[offset_i].xy;
 	coords += offset;
 	// Convert to font units
 	coords /= glyph.scaler;
@@ -128,6 +142,16 @@ float get_value(vec2 offset, float ray_len) {
 	}
 }
 
+float get_dilated_value(vec2 offset, float ray_len) {
+	float value = get_value(offset, ray_len);
+
+	if(glyph.dilate > 0.0) {
+		value = max(value, get_value(offset - vec2(glyph.dilate, 0.0), ray_len));
+	}
+
+	return value;
+}
+
 void main() {
 	float ray_len = sqrt(
 		pow(float(glyph.width) / glyph.scaler, 2)
@@ -135,11 +159,26 @@ void main() {
 	);
 	
 	uint rindex = ((gl_GlobalInvocationID.y * glyph.width) + gl_GlobalInvocationID.x) * 4;
-	float left = get_value(vec2(-1.0 / 6.0, 0.0), ray_len);
-	float r = get_value(vec2(1.0 / 6.0, 0.0), ray_len);
-	float g = get_value(vec2(3.0 / 6.0, 0.0), ray_len);
-	float b = get_value(vec2(5.0 / 6.0, 0.0), ray_len);
-	float right = get_value(vec2(7.0 / 6.0, 0.0), ray_len);
+
+	if(com.aliased != 0) {
+		float coverage = get_dilated_value(vec2(0.5, 0.0), ray_len);
+		coverage = !isnan(coverage) && coverage >= 0.5 ? 1.0 : 0.0;
+		imageStore(bitmap, ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y), vec4(coverage));
+		return;
+	}
+
+	if(com.coverage_only != 0) {
+		float coverage = get_dilated_value(vec2(0.5, 0.0), ray_len);
+		coverage = isnan(coverage) ? 0.0 : coverage;
+		imageStore(bitmap, ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y), vec4(coverage));
+		return;
+	}
+
+	float left = get_dilated_value(vec2(-1.0 / 6.0, 0.0), ray_len);
+	float r = get_dilated_value(vec2(1.0 / 6.0, 0.0), ray_len);
+	float g = get_dilated_value(vec2(3.0 / 6.0, 0.0), ray_len);
+	float b = get_dilated_value(vec2(5.0 / 6.0, 0.0), ray_len);
+	float right = get_dilated_value(vec2(7.0 / 6.0, 0.0), ray_len);
 
 	vec3 color = vec3(
 		(left * (1.0 / 3.0)) + (r * (1.0 / 3.0)) + (g * (1.0 / 3.0)),
@@ -147,7 +186,18 @@ void main() {
 		(g * (1.0 / 3.0)) + (b * (1.0 / 3.0)) + (right * (1.0 / 3.0))
 	);
 
+	color = mix(color, vec3(0.0), isnan(color));
 	float alpha = max(color.r, max(color.g, color.b));
+
+	if(!(alpha > 0.0)) {
+		imageStore(bitmap, ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y), vec4(0.0));
+		return;
+	}
+
+	if(com.premultiplied == 0) {
+		color /= alpha;
+	}
+
 	imageStore(bitmap, ivec2(gl_GlobalInvocationID.x, gl_GlobalInvocationID.y), vec4(color, alpha));
 }
 	"}
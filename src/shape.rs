@@ -1,16 +1,25 @@
+use std::ops::Range;
 use std::sync::Arc;
 
 use allsorts::gpos::Placement;
+use allsorts::gsub::RawGlyph;
+use unicode_bidi::BidiInfo;
 
 use crate::{
-    ImtError, ImtErrorSrc, ImtErrorTy, ImtLang, ImtParsedGlyph, ImtParser, ImtPosition, ImtScript,
+    ImtError, ImtErrorSrc, ImtErrorTy, ImtFeatureTag, ImtFontProps, ImtGlyphBitmap, ImtLang,
+    ImtParsedGlyph, ImtParser, ImtPosition, ImtRasterOpts, ImtScript, ImtTextOpts,
 };
 
+/// Minimum distance between baselines as a fraction of the em.
+const MIN_LINE_HEIGHT_EM: f32 = 0.25;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ImtVertAlign {
     Top,
     Bottom,
     Center,
+    /// Place the first line's baseline at this many pixels from the top of the body.
+    Baseline(f32),
 }
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -25,19 +34,86 @@ pub enum ImtTextWrap {
     Shift,
     NewLine,
     None,
+    /// Same as `None`, but lines exceeding the body's width are truncated and end with an
+    /// ellipsis. Falls back to three periods if the font has no ellipsis.
     NoneDotted,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct ImtShapeOpts {
+    /// Width of the body in pixels. A width of `0.0` is unbounded: text is never wrapped,
+    /// clipped horizontally or aligned other than to the left.
     pub body_width: f32,
+    /// Height of the body in pixels. A height of `0.0` is unbounded: text is never clipped
+    /// vertically.
     pub body_height: f32,
+    /// Height of the em box in pixels. See `ImtShapeOpts::set_text_size_pt` for sizing in points.
     pub text_height: f32,
+    /// Additional space between lines in pixels, added to `line_height` if set. May be negative
+    /// to tighten lines.
     pub line_spacing: f32,
+    /// Distance between baselines in pixels, replacing the font's natural line height. Lines
+    /// are never closer than a quarter of `text_height`.
+    pub line_height: Option<f32>,
     pub text_wrap: ImtTextWrap,
     pub vert_align: ImtVertAlign,
     pub hori_align: ImtHoriAlign,
+    /// Place glyphs & lines on whole pixels. Each glyph's position is rounded on its own after
+    /// layout, so rounding doesn't accumulate along a line. Line advances are rounded up.
+    ///
+    /// This places the pen while `ImtRasterOpts::align_whole_pixels` places the bitmap relative
+    /// to it. With both set glyphs land exactly where they were laid out on whole pixels.
     pub align_whole_pixels: bool,
+    /// Device pixels per pixel, such as `2.0` on a HiDPI display. Sizes remain in pixels, but
+    /// `align_whole_pixels` aligns to the device's pixels, `round(x * pixel_scale) / pixel_scale`.
+    pub pixel_scale: f32,
+    /// Fake a bold face by widening and dilating glyphs.
+    pub synthetic_bold: bool,
+    /// Fake an italic face by shearing glyphs.
+    pub synthetic_italic: bool,
+    /// Render lowercase as small capitals, see `ImtTextOpts::small_caps`.
+    pub small_caps: bool,
+    /// OpenType features to enable or disable in addition to the defaults. Later entries take
+    /// precedence. Unsupported tags are ignored.
+    pub features: Vec<(ImtFeatureTag, bool)>,
+    /// Apply the `liga` & `clig` ligature features. Entries in `features` take precedence.
+    pub ligatures: bool,
+    /// Produce an empty final line when text ends with a new line, such as for placing a caret
+    /// after it. See `ImtShapedLines::line_y` for its position.
+    pub trailing_empty_line: bool,
+}
+
+impl ImtShapeOpts {
+    pub fn builder() -> ImtShapeOptsBuilder {
+        ImtShapeOptsBuilder::default()
+    }
+
+    /// Convert a size in points to pixels at the given DPI, `pt * dpi / 72`.
+    pub fn pt_to_px(pt: f32, dpi: f32) -> f32 {
+        pt * dpi / 72.0
+    }
+
+    /// Set `text_height` from a size in points at the given DPI.
+    pub fn set_text_size_pt(&mut self, pt: f32, dpi: f32) {
+        self.text_height = Self::pt_to_px(pt, dpi);
+    }
+
+    /// Enable the font's stylistic set `set`, `ss01` through `ss20`. Other values are ignored.
+    ///
+    /// What each set changes is up to the font, such as alternate forms of `a` & `g`.
+    pub fn enable_stylistic_set(&mut self, set: u8) {
+        self.features.extend(
+            ImtFeatureTag::stylistic_set(set)
+                .into_iter()
+                .map(|tag| (tag, true)),
+        );
+    }
+
+    /// Enable or disable contextual alternates (`calt`), which fonts use to adjust glyphs
+    /// based on their neighbors such as connecting script letters. Enabled by default.
+    pub fn enable_contextual_alternates(&mut self, enable: bool) {
+        self.features.push((ImtFeatureTag::CALT, enable));
+    }
 }
 
 impl Default for ImtShapeOpts {
@@ -47,14 +123,154 @@ impl Default for ImtShapeOpts {
             body_height: 0.0,
             text_height: 36.0,
             line_spacing: 0.0,
+            line_height: None,
             text_wrap: ImtTextWrap::None,
             vert_align: ImtVertAlign::Top,
             hori_align: ImtHoriAlign::Left,
             align_whole_pixels: true,
+            pixel_scale: 1.0,
+            synthetic_bold: false,
+            synthetic_italic: false,
+            small_caps: false,
+            features: Vec::new(),
+            ligatures: true,
+            trailing_empty_line: false,
         }
     }
 }
 
+/// Builder for `ImtShapeOpts`. Fields not set are their default.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct ImtShapeOptsBuilder {
+    opts: ImtShapeOpts,
+}
+
+impl ImtShapeOptsBuilder {
+    /// Set `ImtShapeOpts::body_width`.
+    pub fn body_width(mut self, body_width: f32) -> Self {
+        self.opts.body_width = body_width;
+        self
+    }
+
+    /// Set `ImtShapeOpts::body_height`.
+    pub fn body_height(mut self, body_height: f32) -> Self {
+        self.opts.body_height = body_height;
+        self
+    }
+
+    /// Set `ImtShapeOpts::text_height`.
+    pub fn text_height(mut self, text_height: f32) -> Self {
+        self.opts.text_height = text_height;
+        self
+    }
+
+    /// Set `ImtShapeOpts::text_height` from a size in points at the given DPI.
+    pub fn text_size_pt(mut self, pt: f32, dpi: f32) -> Self {
+        self.opts.set_text_size_pt(pt, dpi);
+        self
+    }
+
+    /// Set `ImtShapeOpts::line_spacing`.
+    pub fn line_spacing(mut self, line_spacing: f32) -> Self {
+        self.opts.line_spacing = line_spacing;
+        self
+    }
+
+    /// Set `ImtShapeOpts::line_height`.
+    pub fn line_height(mut self, line_height: Option<f32>) -> Self {
+        self.opts.line_height = line_height;
+        self
+    }
+
+    /// Set `ImtShapeOpts::text_wrap`.
+    pub fn text_wrap(mut self, text_wrap: ImtTextWrap) -> Self {
+        self.opts.text_wrap = text_wrap;
+        self
+    }
+
+    /// Set `ImtShapeOpts::vert_align`.
+    pub fn vert_align(mut self, vert_align: ImtVertAlign) -> Self {
+        self.opts.vert_align = vert_align;
+        self
+    }
+
+    /// Set `ImtShapeOpts::hori_align`.
+    pub fn hori_align(mut self, hori_align: ImtHoriAlign) -> Self {
+        self.opts.hori_align = hori_align;
+        self
+    }
+
+    /// Set `ImtShapeOpts::align_whole_pixels`.
+    pub fn align_whole_pixels(mut self, align_whole_pixels: bool) -> Self {
+        self.opts.align_whole_pixels = align_whole_pixels;
+        self
+    }
+
+    /// Set `ImtShapeOpts::pixel_scale`.
+    pub fn pixel_scale(mut self, pixel_scale: f32) -> Self {
+        self.opts.pixel_scale = pixel_scale;
+        self
+    }
+
+    /// Set `ImtShapeOpts::synthetic_bold`.
+    pub fn synthetic_bold(mut self, synthetic_bold: bool) -> Self {
+        self.opts.synthetic_bold = synthetic_bold;
+        self
+    }
+
+    /// Set `ImtShapeOpts::synthetic_italic`.
+    pub fn synthetic_italic(mut self, synthetic_italic: bool) -> Self {
+        self.opts.synthetic_italic = synthetic_italic;
+        self
+    }
+
+    /// Set `ImtShapeOpts::small_caps`.
+    pub fn small_caps(mut self, small_caps: bool) -> Self {
+        self.opts.small_caps = small_caps;
+        self
+    }
+
+    /// Set `ImtShapeOpts::features`.
+    pub fn features(mut self, features: Vec<(ImtFeatureTag, bool)>) -> Self {
+        self.opts.features = features;
+        self
+    }
+
+    /// Set `ImtShapeOpts::ligatures`.
+    pub fn ligatures(mut self, ligatures: bool) -> Self {
+        self.opts.ligatures = ligatures;
+        self
+    }
+
+    /// Set `ImtShapeOpts::trailing_empty_line`.
+    pub fn trailing_empty_line(mut self, trailing_empty_line: bool) -> Self {
+        self.opts.trailing_empty_line = trailing_empty_line;
+        self
+    }
+
+    /// Enable or disable a single feature, see `ImtShapeOpts::features`.
+    pub fn feature(mut self, tag: ImtFeatureTag, enable: bool) -> Self {
+        self.opts.features.push((tag, enable));
+        self
+    }
+
+    /// See `ImtShapeOpts::enable_stylistic_set`.
+    pub fn stylistic_set(mut self, set: u8) -> Self {
+        self.opts.enable_stylistic_set(set);
+        self
+    }
+
+    /// See `ImtShapeOpts::enable_contextual_alternates`.
+    pub fn contextual_alternates(mut self, enable: bool) -> Self {
+        self.opts.enable_contextual_alternates(enable);
+        self
+    }
+
+    pub fn build(self) -> ImtShapeOpts {
+        self.opts
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ImtGlyphInfo {
     pub font_family: String,
@@ -70,8 +286,39 @@ pub struct ImtGlyphInfo {
 pub struct ImtShapedGlyph {
     pub parsed: Arc<ImtParsedGlyph>,
     pub position: ImtPosition,
+    /// Amount the glyph extends past the right of the body in font units.
+    ///
+    /// Overflows are measured from the edges of the glyph's bitmap, including padding, when
+    /// shaped by an `ImtFont` with a raster. Otherwise they're measured from its outline.
     pub x_overflow: f32,
+    /// Amount the glyph extends past the bottom of the body in font units.
     pub y_overflow: f32,
+    /// Amount the glyph extends past the left of the body in font units.
+    pub x_underflow: f32,
+    /// Amount the glyph extends past the top of the body in font units.
+    pub y_underflow: f32,
+    /// Characters of the text this glyph was shaped from, several for a ligature. Empty for the
+    /// additional glyphs of a multiple substitution, so joining those of each glyph in logical
+    /// order gives the original text less any carriage returns & variation selectors.
+    pub unicodes: Vec<char>,
+}
+
+/// Position of a glyph relative to the pen, see `ImtShaper::glyph_positions`. Values are in
+/// font units.
+#[derive(Clone)]
+pub struct ImtGlyphPosition {
+    pub parsed: Arc<ImtParsedGlyph>,
+    /// Characters of the text this glyph was shaped from, several for a ligature. Empty for the
+    /// additional glyphs of a multiple substitution.
+    pub unicodes: Vec<char>,
+    /// Amount to move the pen horizontally after this glyph.
+    pub x_advance: f32,
+    /// Amount to move the pen vertically after this glyph.
+    pub y_advance: f32,
+    /// Offset of the glyph from the pen.
+    pub x_offset: f32,
+    /// Offset of the glyph from the pen.
+    pub y_offset: f32,
 }
 
 pub struct ImtShaper {}
@@ -89,263 +336,817 @@ impl ImtShaper {
         opts: ImtShapeOpts,
         glyphs: Vec<Arc<ImtParsedGlyph>>,
     ) -> Result<Vec<ImtShapedGlyph>, ImtError> {
-        let font_props = parser.font_props();
-        let mut imt_shaped_glyphs: Vec<ImtShapedGlyph> = Vec::new();
-        let mut raw_glyphs = Vec::new();
-
-        for parsed_glyph in glyphs {
-            raw_glyphs.push(parsed_glyph.inner.clone());
-
-            imt_shaped_glyphs.push(ImtShapedGlyph {
-                parsed: parsed_glyph,
-                position: ImtPosition {
-                    x: 0.0,
-                    y: 0.0,
-                },
-                y_overflow: 0.0,
-                x_overflow: 0.0,
-            });
+        let mut imt_shaped_glyphs = Vec::new();
+
+        for line in self.shape_parsed_glyphs_by_line(parser, script, lang, opts, glyphs)? {
+            imt_shaped_glyphs.append(&mut line?);
         }
 
-        let mut shape_from = 0;
-        let mut y = 0.0;
-        let line_spacing = ((opts.text_height / 18.0).floor() + opts.line_spacing)
-            / (font_props.scaler * opts.text_height);
-        let mut vert_adv = font_props.line_gap + font_props.ascender + line_spacing;
+        Ok(imt_shaped_glyphs)
+    }
+
+    /// Shape glyphs without performing any layout, returning advances & offsets relative to the
+    /// pen instead of positions. This allows callers to perform their own line layout.
+    ///
+    /// Unlike `shape_parsed_glyphs`, kerning is included in the advances and new lines are
+    /// returned as any other glyph.
+    pub fn glyph_positions(
+        &self,
+        parser: &ImtParser,
+        script: ImtScript,
+        lang: ImtLang,
+        features: Vec<(ImtFeatureTag, bool)>,
+        glyphs: Vec<Arc<ImtParsedGlyph>>,
+    ) -> Result<Vec<ImtGlyphPosition>, ImtError> {
+        let raw_glyphs = glyphs.iter().map(|glyph| glyph.inner.clone()).collect();
+        let infos = parser.retreive_info_with_features(raw_glyphs, script, lang, features)?;
+
+        Ok(glyphs
+            .into_iter()
+            .zip(infos.into_iter())
+            .map(|(parsed, info)| {
+                let (x_offset, y_offset) = match info.placement {
+                    Placement::Distance(dist_x, dist_y) => (dist_x as f32, dist_y as f32),
+                    _ => (0.0, 0.0), // TODO: Anchored placements
+                };
+
+                ImtGlyphPosition {
+                    unicodes: occurrence_unicodes(&info.glyph),
+                    x_advance: match parsed.is_mark {
+                        true => 0.0,
+                        false => parsed.hori_adv + info.kerning as f32,
+                    },
+                    y_advance: 0.0,
+                    x_offset,
+                    y_offset,
+                    parsed,
+                }
+            })
+            .collect())
+    }
+
+    /// Same as `shape_parsed_glyphs`, but shapes one line at a time as the returned iterator is
+    /// advanced. Each item is a line, which may be empty. Iteration stops after an error.
+    pub fn shape_parsed_glyphs_by_line<'a>(
+        &self,
+        parser: &'a ImtParser,
+        script: ImtScript,
+        lang: ImtLang,
+        opts: ImtShapeOpts,
+        glyphs: Vec<Arc<ImtParsedGlyph>>,
+    ) -> Result<ImtShapedLines<'a>, ImtError> {
+        let font_props = parser.font_props()?;
+        let raw_glyphs = glyphs.iter().map(|glyph| glyph.inner.clone()).collect();
+        let px_scaler = font_props.scaler * opts.text_height;
+
+        let mut vert_adv = match opts.line_height {
+            Some(line_height) => (line_height + opts.line_spacing) / px_scaler,
+            None => font_props.line_height() + (opts.line_spacing / px_scaler),
+        }
+        .max(MIN_LINE_HEIGHT_EM / font_props.scaler);
 
         if opts.align_whole_pixels {
-            vert_adv = vert_adv.ceil();
+            let grid_scaler = px_scaler * opts.pixel_scale;
+            vert_adv = (vert_adv * grid_scaler).ceil() / grid_scaler;
         }
 
-        let mut lines: Vec<(usize, usize, f32)> = Vec::new();
+        let baseline_shift = match &opts.vert_align {
+            &ImtVertAlign::Baseline(baseline) => {
+                (baseline / (font_props.scaler * opts.text_height)) - font_props.ascender
+            },
+            _ => 0.0,
+        };
 
-        'line: loop {
-            let infos = parser.retreive_info(raw_glyphs[shape_from..].to_vec(), script, lang)?;
+        Ok(ImtShapedLines {
+            parser,
+            script,
+            lang,
+            opts,
+            font_props,
+            glyphs,
+            raw_glyphs,
+            shape_from: 0,
+            y: 0.0,
+            line_y: 0.0,
+            vert_adv,
+            baseline_shift,
+            raster_opts: None,
+            soft_break: false,
+            finished: false,
+        })
+    }
+}
 
-            let mut x: f32 = 0.0;
-            let mut x_offset = 0.0;
-            let mut line_max_x = 0.0;
-            let infos_len = infos.len();
+/// Iterator over the lines of shaped text. See `ImtShaper::shape_parsed_glyphs_by_line`.
+pub struct ImtShapedLines<'a> {
+    parser: &'a ImtParser,
+    script: ImtScript,
+    lang: ImtLang,
+    opts: ImtShapeOpts,
+    font_props: ImtFontProps,
+    glyphs: Vec<Arc<ImtParsedGlyph>>,
+    raw_glyphs: Vec<RawGlyph<()>>,
+    shape_from: usize,
+    y: f32,
+    line_y: f32,
+    vert_adv: f32,
+    baseline_shift: f32,
+    /// Options of the raster the glyphs are for, so they're clipped against their bitmaps.
+    raster_opts: Option<ImtRasterOpts>,
+    /// Whether the current line was started by wrapping rather than a new line character.
+    soft_break: bool,
+    finished: bool,
+}
 
-            for (i, info) in infos.into_iter().enumerate() {
-                if *info.glyph.unicodes.first().unwrap() == '\n' {
-                    if shape_from + i >= raw_glyphs.len() {
-                        break 'line;
-                    } else {
-                        if i == 0 {
-                            lines.push((shape_from, shape_from, line_max_x));
-                        } else {
-                            lines.push((shape_from, shape_from + i, line_max_x));
-                        }
+/// Byte ranges of `text` in visual order as given by the Unicode Bidirectional Algorithm, along
+/// with whether each is right-to-left. `None` if `text` is entirely left-to-right.
+///
+/// Each paragraph is reordered as a single line. A paragraph's line break is placed after its
+/// other runs regardless of direction.
+pub(crate) fn bidi_runs(text: &str) -> Option<Vec<(Range<usize>, bool)>> {
+    let bidi_info = BidiInfo::new(text, None);
 
-                        y += vert_adv;
-                        shape_from = shape_from + i + 1;
-                        continue 'line;
-                    }
-                }
+    if !bidi_info.has_rtl() {
+        return None;
+    }
 
-                if x == 0.0 {
-                    x_offset = imt_shaped_glyphs[i].parsed.min_x;
-                }
+    let mut runs = Vec::new();
 
-                let (glyph_x, glyph_y) = match info.placement {
-                    Placement::Distance(dist_x, dist_y) => {
-                        let dist_x = dist_x as f32;
-                        let dist_y = dist_y as f32;
-                        (x + dist_x, y + dist_y)
-                    },
-                    Placement::MarkAnchor(_base_glyph_i, _base_glyph_anc, _mark_anc) => (x, y), /* TODO: */
-                    Placement::MarkOverprint(_base_glyph_i) => (x, y), // TODO:
-                    Placement::CursiveAnchor(
-                        _exit_glyph_i,
-                        _rl_flag,
-                        _exit_glyph_anc,
-                        _entry_glyph_anc,
-                    ) => (x, y), // TODO:
-                    Placement::None => (x, y),
-                };
+    for para in bidi_info.paragraphs.iter() {
+        let content_end = para.range.start
+            + text[para.range.clone()]
+                .trim_end_matches(['\r', '\n'])
+                .len();
 
-                let lmaxx = glyph_x + x_offset + imt_shaped_glyphs[i + shape_from].parsed.max_x;
+        if content_end > para.range.start {
+            let (levels, visual_runs) = bidi_info.visual_runs(para, para.range.start..content_end);
 
-                if let &ImtTextWrap::NewLine = &opts.text_wrap {
-                    if lmaxx * font_props.scaler * opts.text_height > opts.body_width {
-                        if x == 0.0 {
-                            return Err(ImtError::src_and_ty(
-                                ImtErrorSrc::Shaper,
-                                ImtErrorTy::Other(format!("Body width too small.")),
-                            ));
-                        }
+            for run in visual_runs {
+                let rtl = levels[run.start].is_rtl();
+                runs.push((run, rtl));
+            }
+        }
+
+        if content_end < para.range.end {
+            runs.push((content_end..para.range.end, false));
+        }
+    }
+
+    Some(runs)
+}
+
+/// Characters a glyph of a shaped text was mapped from. The additional glyphs of a multiple
+/// substitution carry the characters of the glyph they were substituted with, so theirs are
+/// left empty.
+fn occurrence_unicodes(glyph: &RawGlyph<()>) -> Vec<char> {
+    if glyph.multi_subst_dup {
+        return Vec::new();
+    }
+
+    glyph.unicodes.to_vec()
+}
+
+/// Truncate `line` so that it ends with `dots` within `body_width_fu`, updating its `width`.
+/// Whitespace before the dots is removed.
+fn truncate_with_dots(
+    line: &mut Vec<ImtShapedGlyph>,
+    dots: Vec<Arc<ImtParsedGlyph>>,
+    body_width_fu: f32,
+    width: &mut f32,
+) {
+    let dots_adv: f32 = dots.iter().map(|parsed| parsed.hori_adv).sum();
+    let limit = body_width_fu - dots_adv;
+    let y = line[0].position.y;
+
+    let keep = line
+        .iter()
+        .position(|glyph| glyph.position.x + glyph.parsed.hori_adv > limit)
+        .unwrap_or(line.len());
+
+    line.truncate(keep);
+
+    while line.last().map(|glyph| is_whitespace(&glyph.parsed)) == Some(true) {
+        line.pop();
+    }
+
+    let mut x = match line.last() {
+        Some(glyph) => glyph.position.x + glyph.parsed.hori_adv,
+        None => 0.0,
+    };
+
+    for parsed in dots {
+        *width = x + parsed.max_x;
+        let glyph_adv = parsed.hori_adv;
+
+        line.push(ImtShapedGlyph {
+            unicodes: occurrence_unicodes(&parsed.inner),
+            parsed,
+            position: ImtPosition {
+                x,
+                y,
+            },
+            x_overflow: 0.0,
+            y_overflow: 0.0,
+            x_underflow: 0.0,
+            y_underflow: 0.0,
+        });
+
+        x += glyph_adv;
+    }
+}
+
+/// Round `value` to the nearest multiple of `1 / grid_scaler`.
+pub(crate) fn snap_to_grid(value: f32, grid_scaler: f32) -> f32 {
+    (value * grid_scaler).round() / grid_scaler
+}
 
-                        lines.push((shape_from, i + shape_from, line_max_x));
-                        shape_from += i;
-                        y += vert_adv;
-                        continue 'line;
+fn is_whitespace(glyph: &ImtParsedGlyph) -> bool {
+    match glyph.inner.unicodes.first() {
+        Some(c) => *c != '\n' && c.is_whitespace(),
+        None => false,
+    }
+}
+
+impl<'a> ImtShapedLines<'a> {
+    /// Vertical position in font units of the last line returned, matching the `position.y` of
+    /// its glyphs. Unlike glyphs this is also available for empty lines.
+    pub fn line_y(&self) -> f32 {
+        self.line_y
+    }
+
+    /// Clip glyphs against the bitmaps a raster with `raster_opts` creates for them.
+    pub(crate) fn with_raster_opts(mut self, raster_opts: ImtRasterOpts) -> Self {
+        self.raster_opts = Some(raster_opts);
+        self
+    }
+
+    /// Horizontal & vertical extent of a glyph relative to the body in font units, as
+    /// `[min_x, max_x, min_y, max_y]`. This is its bitmap if rastering, otherwise its outline.
+    fn glyph_extent(&self, glyph: &ImtShapedGlyph) -> [f32; 4] {
+        let font_props = &self.font_props;
+        let parsed = &glyph.parsed;
+
+        let raster_opts = match self.raster_opts.as_ref() {
+            Some(raster_opts) => raster_opts,
+            None => {
+                let min_x = glyph.position.x + parsed.min_x;
+                let min_y = glyph.position.y + font_props.ascender - parsed.max_y;
+
+                return [
+                    min_x,
+                    min_x + (parsed.max_x - parsed.min_x),
+                    min_y,
+                    min_y + (parsed.max_y - parsed.min_y),
+                ];
+            },
+        };
+
+        // Placed the same as `ImtFont::glyphs_for_shaped` places the bitmap.
+        let px_scaler = font_props.scaler * self.opts.text_height;
+        let metrics =
+            ImtGlyphBitmap::extent(parsed, font_props, self.opts.text_height, raster_opts);
+        let (pen_x, pen_y, phase) =
+            raster_opts.pen_position(glyph.position.x * px_scaler, glyph.position.y * px_scaler);
+
+        // A sub-pixel phase widens the bitmap by a pixel.
+        let width = metrics.width + (phase > 0) as u32;
+        let min_x = pen_x + metrics.bearing_x;
+        let min_y = pen_y + metrics.bearing_y;
+
+        [
+            min_x / px_scaler,
+            (min_x + width as f32) / px_scaler,
+            min_y / px_scaler,
+            (min_y + metrics.height as f32) / px_scaler,
+        ]
+    }
+
+    fn shape_line(&mut self) -> Result<Vec<ImtShapedGlyph>, ImtError> {
+        self.line_y = self.y + self.baseline_shift;
+
+        if self.opts.align_whole_pixels {
+            let grid_scaler =
+                self.font_props.scaler * self.opts.text_height * self.opts.pixel_scale;
+            self.line_y = snap_to_grid(self.line_y, grid_scaler);
+        }
+
+        // Only reached after a trailing new line with `trailing_empty_line` set.
+        if self.shape_from >= self.glyphs.len() {
+            self.finished = true;
+            return Ok(Vec::new());
+        }
+
+        // Whitespace at a wrapped line's start would appear as an indent.
+        if self.soft_break {
+            while self.shape_from < self.glyphs.len()
+                && is_whitespace(&self.glyphs[self.shape_from])
+            {
+                self.shape_from += 1;
+            }
+        }
+
+        // Only the glyphs up to & including the next new line can be part of this line.
+        let shape_to = self.raw_glyphs[self.shape_from..]
+            .iter()
+            .position(|glyph| glyph.unicodes.first() == Some(&'\n'))
+            .map(|i| self.shape_from + i + 1)
+            .unwrap_or(self.raw_glyphs.len());
+
+        let infos = self.parser.retreive_info_with_features(
+            self.raw_glyphs[self.shape_from..shape_to].to_vec(),
+            self.script,
+            self.lang,
+            self.opts.features.clone(),
+        )?;
+
+        let mut line: Vec<ImtShapedGlyph> = Vec::new();
+        let mut x: f32 = 0.0;
+        let mut x_offset = 0.0;
+        let mut line_max_x = 0.0;
+        // Extent of the line excluding trailing whitespace.
+        let mut content_max_x = 0.0;
+        let mut next_from = None;
+        let mut soft_break = false;
+
+        for (i, info) in infos.into_iter().enumerate() {
+            if info.glyph.unicodes.first() == Some(&'\n') {
+                // The new line character itself isn't part of either line.
+                next_from = Some(self.shape_from + i + 1);
+                break;
+            }
+
+            if x == 0.0 {
+                x_offset = self.glyphs[self.shape_from + i].min_x;
+            }
+
+            let (glyph_x, glyph_y) = match info.placement {
+                Placement::Distance(dist_x, dist_y) => {
+                    let dist_x = dist_x as f32;
+                    let dist_y = dist_y as f32;
+                    (x + dist_x, self.y + dist_y)
+                },
+                Placement::MarkAnchor(_base_glyph_i, _base_glyph_anc, _mark_anc) => (x, self.y), /* TODO: */
+                Placement::MarkOverprint(_base_glyph_i) => (x, self.y), // TODO:
+                Placement::CursiveAnchor(
+                    _exit_glyph_i,
+                    _rl_flag,
+                    _exit_glyph_anc,
+                    _entry_glyph_anc,
+                ) => (x, self.y), // TODO:
+                Placement::None => (x, self.y),
+            };
+
+            let parsed = &self.glyphs[self.shape_from + i];
+            let lmaxx = glyph_x + x_offset + parsed.max_x;
+
+            if self.opts.text_wrap == ImtTextWrap::NewLine && self.opts.body_width > 0.0 {
+                if lmaxx * self.font_props.scaler * self.opts.text_height > self.opts.body_width {
+                    if x == 0.0 {
+                        return Err(ImtError::src_and_ty(
+                            ImtErrorSrc::Shaper,
+                            ImtErrorTy::Other(format!("Body width too small.")),
+                        ));
                     }
+
+                    next_from = Some(self.shape_from + i);
+                    soft_break = true;
+                    break;
                 }
+            }
 
-                line_max_x = lmaxx;
+            line_max_x = lmaxx;
 
-                imt_shaped_glyphs[shape_from + i].position = if opts.align_whole_pixels {
-                    ImtPosition {
-                        x: (glyph_x + x_offset).ceil(),
-                        y: glyph_y.ceil(),
-                    }
-                } else {
-                    ImtPosition {
-                        x: glyph_x + x_offset,
-                        y: glyph_y,
-                    }
-                };
+            if !is_whitespace(parsed) {
+                content_max_x = lmaxx;
+            }
 
-                x += if opts.align_whole_pixels {
-                    imt_shaped_glyphs[shape_from + i].parsed.hori_adv.ceil()
-                } else {
-                    imt_shaped_glyphs[shape_from + i].parsed.hori_adv
-                };
+            let position = ImtPosition {
+                x: glyph_x + x_offset,
+                y: glyph_y,
+            };
+
+            // Marks are placed over the preceding glyph.
+            if !parsed.is_mark {
+                x += parsed.hori_adv;
             }
 
-            lines.push((shape_from, shape_from + infos_len, line_max_x));
-            break 'line;
+            line.push(ImtShapedGlyph {
+                parsed: parsed.clone(),
+                position,
+                x_overflow: 0.0,
+                y_overflow: 0.0,
+                x_underflow: 0.0,
+                y_underflow: 0.0,
+                unicodes: occurrence_unicodes(&info.glyph),
+            });
+        }
+
+        match next_from {
+            Some(next_from) if next_from < self.glyphs.len() || self.opts.trailing_empty_line => {
+                self.shape_from = next_from;
+                self.y += self.vert_adv;
+            },
+            _ => self.finished = true,
+        }
+
+        self.soft_break = soft_break;
+
+        if self.opts.text_wrap == ImtTextWrap::NoneDotted && self.opts.body_width > 0.0 {
+            self.apply_ellipsis(&mut line, &mut line_max_x)?;
+        }
+
+        // Trailing whitespace before a wrap shouldn't affect alignment.
+        if soft_break {
+            line_max_x = content_max_x;
+        }
+
+        Ok(self.finish_line(line, line_max_x))
+    }
+
+    /// Truncate a line that exceeds the body's width and end it with an ellipsis.
+    fn apply_ellipsis(
+        &self,
+        line: &mut Vec<ImtShapedGlyph>,
+        width: &mut f32,
+    ) -> Result<(), ImtError> {
+        let body_width_fu = self.opts.body_width / (self.opts.text_height * self.font_props.scaler);
+
+        if *width <= body_width_fu || line.is_empty() {
+            return Ok(());
+        }
+
+        let dots = match self.parser.missing_chars("\u{2026}")?.is_empty() {
+            true => "\u{2026}",
+            false => "...",
+        };
+
+        let dots = self.parser.retrieve_text_with_opts(
+            dots,
+            self.script,
+            self.lang,
+            ImtTextOpts {
+                fake_bold: self.opts.synthetic_bold,
+                fake_italic: self.opts.synthetic_italic,
+                small_caps: false,
+                features: self.opts.features.clone(),
+            },
+        )?;
+
+        truncate_with_dots(line, dots, body_width_fu, width);
+        Ok(())
+    }
+
+    fn finish_line(&self, mut line: Vec<ImtShapedGlyph>, mut width: f32) -> Vec<ImtShapedGlyph> {
+        let opts = &self.opts;
+        let font_props = &self.font_props;
+
+        // -- Baseline Alignment -- //
+
+        for glyph in line.iter_mut() {
+            glyph.position.y += self.baseline_shift;
         }
 
         // -- Shift Wrapping -- //
 
-        if let &ImtTextWrap::Shift = &opts.text_wrap {
-            let mut remove_glyphs = Vec::new();
-            let mut i_adjust = 0;
-
-            for (start, end, width) in &mut lines {
-                if start != end {
-                    let shift = (opts.body_width / (font_props.scaler * opts.text_height)) - *width;
-                    let mut new_start = None;
-
-                    for i in *start..*end {
-                        if new_start.is_none() {
-                            if imt_shaped_glyphs[i].position.x > shift {
-                                new_start = Some(i);
-                                imt_shaped_glyphs[i].position.x -= shift;
-                            } else {
-                                remove_glyphs.push(i);
-                            }
-                        } else {
-                            imt_shaped_glyphs[i].position.x -= shift;
+        if opts.text_wrap == ImtTextWrap::Shift && opts.body_width > 0.0 {
+            if !line.is_empty() {
+                let shift = (opts.body_width / (font_props.scaler * opts.text_height)) - width;
+
+                match line.iter().position(|glyph| glyph.position.x > shift) {
+                    Some(new_start) => {
+                        line.drain(..new_start);
+
+                        for glyph in line.iter_mut() {
+                            glyph.position.x -= shift;
                         }
-                    }
 
-                    if let Some(new_start) = new_start {
-                        let start_diff = new_start - *start;
-                        *start = new_start - i_adjust;
-                        *end = *end - i_adjust;
-                        *width -= shift;
-                        i_adjust += start_diff;
-                    }
+                        width -= shift;
+                    },
+                    None => line.clear(),
                 }
             }
+        }
 
-            for (i, g) in imt_shaped_glyphs.split_off(0).into_iter().enumerate() {
-                if !remove_glyphs.contains(&i) {
-                    imt_shaped_glyphs.push(g);
-                }
+        // -- Pixel Alignment -- //
+
+        let grid_scaler = font_props.scaler * opts.text_height * opts.pixel_scale;
+        let snap = |value: f32| snap_to_grid(value, grid_scaler);
+
+        if opts.align_whole_pixels {
+            for glyph in line.iter_mut() {
+                glyph.position.x = snap(glyph.position.x);
+                glyph.position.y = snap(glyph.position.y);
+            }
+        }
+
+        // -- Horizontal Alignment -- //
+
+        let hori_align_scaler = match &opts.hori_align {
+            &ImtHoriAlign::Left => 0.0,
+            &ImtHoriAlign::Right => 1.0,
+            &ImtHoriAlign::Center => 0.5,
+        };
+
+        if hori_align_scaler != 0.0 && opts.body_width > 0.0 && !line.is_empty() {
+            let space_px = opts.body_width - (width * font_props.scaler * opts.text_height);
+            let space_font_units = space_px / (font_props.scaler * opts.text_height);
+            let mut shift = space_font_units * hori_align_scaler;
+
+            if opts.align_whole_pixels {
+                shift = snap(shift);
+            }
+
+            for glyph in line.iter_mut() {
+                glyph.position.x += shift;
             }
         }
 
         // -- Calculate Overflows -- //
         // TODO: Adjust line width?
 
-        {
-            let mut remove_glyphs = Vec::new();
-            let mut i_adjust = 0;
-            let body_width_fu = opts.body_width / (opts.text_height * font_props.scaler);
-            let body_height_fu = opts.body_height / (opts.text_height * font_props.scaler);
-
-            for (start, end, _width) in &mut lines {
-                if start != end {
-                    let mut removed = 0;
-                    let mut new_start = None;
-                    let mut len = 0;
-
-                    for i in *start..*end {
-                        let glyph = &mut imt_shaped_glyphs[i];
-                        let mut remove = false;
-                        let width = glyph.parsed.max_x - glyph.parsed.min_x;
-                        let min_x = glyph.position.x + glyph.parsed.min_x;
-                        let max_x = min_x + width;
-
-                        if max_x > body_width_fu {
-                            if min_x > body_width_fu {
-                                remove = true;
-                                removed += 1;
-                            } else {
-                                glyph.x_overflow = max_x - body_width_fu;
-                            }
-                        }
+        let body_width_fu = opts.body_width / (opts.text_height * font_props.scaler);
+        let body_height_fu = opts.body_height / (opts.text_height * font_props.scaler);
 
-                        let height = glyph.parsed.max_y - glyph.parsed.min_y;
-                        let bearing_y = font_props.ascender - glyph.parsed.max_y;
-                        let min_y = glyph.position.y + bearing_y;
-                        let max_y = min_y + height;
-
-                        if max_y > body_height_fu {
-                            if min_y > body_height_fu {
-                                remove = true;
-                                removed += 1;
-                            } else {
-                                glyph.y_overflow = max_y - body_height_fu;
-                            }
-                        }
+        line.retain_mut(|glyph| {
+            let [min_x, max_x, min_y, max_y] = self.glyph_extent(glyph);
+            let mut remove = false;
 
-                        if remove {
-                            remove_glyphs.push(i);
-                        } else {
-                            if new_start.is_none() {
-                                new_start = Some(i);
-                            }
+            if opts.body_width > 0.0 {
+                if max_x > body_width_fu {
+                    if min_x > body_width_fu {
+                        remove = true;
+                    } else {
+                        glyph.x_overflow = max_x - body_width_fu;
+                    }
+                }
 
-                            len += 1;
-                        }
+                if min_x < 0.0 {
+                    if max_x < 0.0 {
+                        remove = true;
+                    } else {
+                        glyph.x_underflow = -min_x;
                     }
+                }
+            }
 
-                    if let Some(new_start) = new_start {
-                        *start = new_start - i_adjust;
+            if opts.body_height > 0.0 {
+                if max_y > body_height_fu {
+                    if min_y > body_height_fu {
+                        remove = true;
+                    } else {
+                        glyph.y_overflow = max_y - body_height_fu;
                     }
+                }
 
-                    *end = *start + len;
-                    i_adjust += removed;
+                if min_y < 0.0 {
+                    if max_y < 0.0 {
+                        remove = true;
+                    } else {
+                        glyph.y_underflow = -min_y;
+                    }
                 }
             }
 
-            for (i, g) in imt_shaped_glyphs.split_off(0).into_iter().enumerate() {
-                if !remove_glyphs.contains(&i) {
-                    imt_shaped_glyphs.push(g);
+            !remove
+        });
+
+        line
+    }
+}
+
+impl<'a> Iterator for ImtShapedLines<'a> {
+    type Item = Result<Vec<ImtShapedGlyph>, ImtError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+
+        let result = self.shape_line();
+
+        if result.is_err() {
+            self.finished = true;
+        }
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use allsorts::gsub::GlyphOrigin;
+
+    use super::*;
+    use crate::test_font::TestFont;
+    use crate::{ImtFont, ImtWeight};
+
+    fn parsed(c: char, hori_adv: f32) -> Arc<ImtParsedGlyph> {
+        Arc::new(ImtParsedGlyph {
+            inner: RawGlyph {
+                unicodes: std::iter::once(c).collect(),
+                glyph_index: 0,
+                liga_component_pos: 0,
+                glyph_origin: GlyphOrigin::Char(c),
+                small_caps: false,
+                multi_subst_dup: false,
+                is_vert_alt: false,
+                fake_bold: false,
+                fake_italic: false,
+                extra_data: (),
+                variation: None,
+            },
+            min_x: 0.0,
+            min_y: 0.0,
+            max_x: hori_adv,
+            max_y: 700.0,
+            hori_adv,
+            is_mark: false,
+            contours: Vec::new(),
+        })
+    }
+
+    /// Glyphs of `text` placed one after another, each `hori_adv` wide.
+    fn shaped_line(text: &str, hori_adv: f32) -> Vec<ImtShapedGlyph> {
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| {
+                ImtShapedGlyph {
+                    parsed: parsed(c, hori_adv),
+                    position: ImtPosition {
+                        x: i as f32 * hori_adv,
+                        y: 100.0,
+                    },
+                    x_overflow: 0.0,
+                    y_overflow: 0.0,
+                    x_underflow: 0.0,
+                    y_underflow: 0.0,
+                    unicodes: vec![c],
                 }
-            }
+            })
+            .collect()
+    }
+
+    fn line_text(line: &[ImtShapedGlyph]) -> String {
+        line.iter()
+            .flat_map(|glyph| glyph.unicodes.iter())
+            .collect()
+    }
+
+    #[test]
+    fn dotted_tail() {
+        let mut line = shaped_line("abcdefghij", 500.0);
+        let mut width = 5000.0;
+        truncate_with_dots(
+            &mut line,
+            vec![parsed('\u{2026}', 800.0)],
+            3000.0,
+            &mut width,
+        );
+
+        assert_eq!(line_text(&line), "abcd\u{2026}");
+        let dots = line.last().unwrap();
+        assert_eq!(dots.position.x, 2000.0);
+        assert_eq!(dots.position.y, 100.0);
+        assert_eq!(width, 2800.0);
+    }
+
+    #[test]
+    fn dotted_tail_trims_whitespace() {
+        let mut line = shaped_line("abc defghij", 500.0);
+        let mut width = 5500.0;
+
+        let dots = (0..3).map(|_| parsed('.', 200.0)).collect();
+        truncate_with_dots(&mut line, dots, 3000.0, &mut width);
+
+        assert_eq!(line_text(&line), "abc...");
+        let x: Vec<f32> = line.iter().map(|glyph| glyph.position.x).collect();
+        assert_eq!(x, [0.0, 500.0, 1000.0, 1500.0, 1700.0, 1900.0]);
+        assert_eq!(width, 2100.0);
+    }
+
+    #[test]
+    fn dotted_tail_only_dots() {
+        // Nothing fits before the dots.
+        let mut line = shaped_line("abc", 500.0);
+        let mut width = 1500.0;
+        truncate_with_dots(
+            &mut line,
+            vec![parsed('\u{2026}', 800.0)],
+            1000.0,
+            &mut width,
+        );
+
+        assert_eq!(line_text(&line), "\u{2026}");
+        assert_eq!(line[0].position.x, 0.0);
+        assert_eq!(width, 800.0);
+    }
+
+    #[test]
+    fn snap_to_pixels() {
+        // 16px text of a 2048 units per em font, a pixel is 128 font units.
+        let grid_scaler = 16.0 / 2048.0;
+        assert_eq!(snap_to_grid(0.0, grid_scaler), 0.0);
+        assert_eq!(snap_to_grid(63.0, grid_scaler), 0.0);
+        assert_eq!(snap_to_grid(65.0, grid_scaler), 128.0);
+        assert_eq!(snap_to_grid(1000.0, grid_scaler), 1024.0);
+        assert_eq!(snap_to_grid(-200.0, grid_scaler), -256.0);
+
+        // With a pixel scale of two, positions snap to device pixels of 64 font units.
+        assert_eq!(snap_to_grid(1000.0, grid_scaler * 2.0), 1024.0);
+        assert_eq!(snap_to_grid(950.0, grid_scaler * 2.0), 960.0);
+    }
+
+    #[test]
+    fn snapped_positions_dont_drift() {
+        // An advance of 1.3 pixels at 16px text.
+        let grid_scaler = 16.0 / 2048.0;
+        let hori_adv = 1.3 / grid_scaler;
+
+        // Each position is snapped on its own, so it stays within half a pixel of the unsnapped
+        // position however long the line is.
+        for i in 0..1000 {
+            let x = i as f32 * hori_adv;
+            let snapped = snap_to_grid(x, grid_scaler);
+            assert_eq!((snapped * grid_scaler).fract(), 0.0);
+            assert!(((snapped - x) * grid_scaler).abs() <= 0.5 + 1e-3);
         }
+    }
 
-        // -- Horizontal Alignment -- //
+    #[test]
+    fn line_advance() {
+        let mut font = TestFont::new();
+        font.hhea = (800, -200, 100);
+        let font = ImtFont::from_bytes_outline("Test", ImtWeight::Normal, font.build()).unwrap();
+        let font_props = font.parser().font_props().unwrap();
 
-        let hori_align_scaler = match &opts.hori_align {
-            &ImtHoriAlign::Left => 0.0,
-            &ImtHoriAlign::Right => 1.0,
-            &ImtHoriAlign::Center => 0.5,
+        // 800 - -200 + 100 font units, which is 22px for 20px text of 1000 units per em.
+        assert_eq!(font_props.line_height(), 1100.0);
+
+        let opts = ImtShapeOpts {
+            text_height: 20.0,
+            align_whole_pixels: false,
+            ..ImtShapeOpts::default()
         };
 
-        if hori_align_scaler != 0.0 {
-            for (start, end, width) in &lines {
-                if start != end {
-                    let space_px =
-                        opts.body_width - (*width * font_props.scaler * opts.text_height);
-                    let space_font_units = space_px / (font_props.scaler * opts.text_height);
-                    let shift = space_font_units * hori_align_scaler;
+        let baselines: Vec<f32> = font
+            .shape_text(opts, "a\na")
+            .unwrap()
+            .into_iter()
+            .filter(|glyph| glyph.unicodes == ['a'])
+            .map(|glyph| glyph.position.y)
+            .collect();
 
-                    for i in *start..*end {
-                        imt_shaped_glyphs[i].position.x += shift;
-                    }
+        assert_eq!(baselines.len(), 2);
+        let advance = font_props.font_units_to_px(baselines[1] - baselines[0], 20.0);
+        assert!((advance - 22.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn unbounded_body() {
+        let font = ImtFont::from_bytes_outline("Test", ImtWeight::Normal, TestFont::new().build())
+            .unwrap();
+
+        for text_wrap in [
+            ImtTextWrap::Shift,
+            ImtTextWrap::NewLine,
+            ImtTextWrap::None,
+            ImtTextWrap::NoneDotted,
+        ] {
+            let opts = ImtShapeOpts {
+                body_width: 0.0,
+                body_height: 0.0,
+                text_height: 40.0,
+                text_wrap,
+                hori_align: ImtHoriAlign::Right,
+                ..ImtShapeOpts::default()
+            };
+
+            let lines = font
+                .shape_text_by_line(opts, "abc abc abc abc\nabc abc")
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            // Only the new line breaks the text, and nothing is truncated, shifted, aligned or
+            // clipped.
+            assert_eq!(lines.len(), 2, "{:?}", text_wrap);
+
+            for (line, text) in lines.iter().zip(["abc abc abc abc", "abc abc"]) {
+                assert_eq!(
+                    line_text(line).trim_end_matches('\n'),
+                    text,
+                    "{:?}",
+                    text_wrap
+                );
+                assert_eq!(line[0].position.x, 0.0, "{:?}", text_wrap);
+
+                for glyph in line.iter() {
+                    assert_eq!(glyph.x_overflow, 0.0);
+                    assert_eq!(glyph.y_overflow, 0.0);
+                    assert_eq!(glyph.x_underflow, 0.0);
+                    assert_eq!(glyph.y_underflow, 0.0);
                 }
             }
         }
-
-        // Remove New Line Characters
-        imt_shaped_glyphs.retain(|g| g.parsed.inner.unicodes[0] != '\n');
-        Ok(imt_shaped_glyphs)
     }
 }
@@ -0,0 +1,378 @@
+//! Minimal TrueType fonts built in memory for tests.
+
+/// A glyph of a `TestFont`. Contours are closed polygons of on-curve points, clockwise for
+/// filled areas & counter-clockwise for holes.
+pub(crate) struct TestGlyph {
+    pub chars: Vec<char>,
+    pub advance: u16,
+    pub contours: Vec<Vec<(i16, i16)>>,
+}
+
+impl TestGlyph {
+    /// A filled rectangle from `(min_x, min_y)` to `(max_x, max_y)`.
+    pub fn rect(chars: &[char], advance: u16, min: (i16, i16), max: (i16, i16)) -> Self {
+        TestGlyph {
+            chars: chars.to_vec(),
+            advance,
+            contours: vec![rect_contour(min, max, true)],
+        }
+    }
+}
+
+/// Corners of a rectangle, clockwise if `filled` otherwise counter-clockwise.
+pub(crate) fn rect_contour(min: (i16, i16), max: (i16, i16), filled: bool) -> Vec<(i16, i16)> {
+    let mut contour = vec![min, (min.0, max.1), max, (max.0, min.1)];
+
+    if !filled {
+        contour.reverse();
+    }
+
+    contour
+}
+
+pub(crate) struct TestFont {
+    pub units_per_em: u16,
+    /// `hhea` ascender, descender & line gap.
+    pub hhea: (i16, i16, i16),
+    /// `OS/2` typographic ascender, descender & line gap, and whether `USE_TYPO_METRICS` is set.
+    /// The font has no `OS/2` table when `None`.
+    pub typo: Option<(i16, i16, i16, bool)>,
+    /// Glyphs following `.notdef`, which is always the first glyph.
+    pub glyphs: Vec<TestGlyph>,
+}
+
+impl TestFont {
+    /// A font of 1000 units per em with a space, `?`, `a` to `c`, an `O` with a hole & the Hebrew
+    /// letters alef to gimel. Letters other than the space are 500 units wide, while the `O` is
+    /// 700 units wide.
+    pub fn new() -> Self {
+        TestFont {
+            units_per_em: 1000,
+            hhea: (800, -200, 100),
+            typo: None,
+            glyphs: vec![
+                TestGlyph {
+                    chars: vec![' '],
+                    advance: 250,
+                    contours: Vec::new(),
+                },
+                TestGlyph::rect(&['?', 'a', 'b', 'c'], 500, (50, 0), (450, 500)),
+                TestGlyph {
+                    chars: vec!['O'],
+                    advance: 700,
+                    contours: vec![
+                        rect_contour((50, 0), (650, 700), true),
+                        rect_contour((200, 150), (500, 550), false),
+                    ],
+                },
+                TestGlyph::rect(&['\u{5D0}'], 500, (50, 0), (450, 600)),
+                TestGlyph::rect(&['\u{5D1}'], 500, (50, 0), (450, 600)),
+                TestGlyph::rect(&['\u{5D2}'], 500, (50, 0), (450, 600)),
+            ],
+        }
+    }
+
+    /// Index of the glyph mapped to `c`.
+    pub fn glyph_index(&self, c: char) -> u16 {
+        self.glyphs
+            .iter()
+            .position(|glyph| glyph.chars.contains(&c))
+            .map(|i| i as u16 + 1)
+            .unwrap()
+    }
+
+    /// Bytes of the font as a TrueType file.
+    pub fn build(&self) -> Vec<u8> {
+        let num_glyphs = self.glyphs.len() as u16 + 1;
+        let mut glyf = Vec::new();
+        let mut loca = vec![0_u32];
+        let mut hmtx = Vec::new();
+        let mut bounds = [i16::MAX, i16::MAX, i16::MIN, i16::MIN];
+        let mut max_points = 0;
+        let mut max_contours = 0;
+
+        // .notdef is empty.
+        push_u16(&mut hmtx, 500);
+        push_i16(&mut hmtx, 0);
+        loca.push(0);
+
+        for glyph in self.glyphs.iter() {
+            let glyph_bounds = glyph
+                .contours
+                .iter()
+                .flatten()
+                .fold([i16::MAX, i16::MAX, i16::MIN, i16::MIN], |b, &(x, y)| {
+                    [b[0].min(x), b[1].min(y), b[2].max(x), b[3].max(y)]
+                });
+
+            push_u16(&mut hmtx, glyph.advance);
+
+            if glyph.contours.is_empty() {
+                push_i16(&mut hmtx, 0);
+                loca.push(glyf.len() as u32);
+                continue;
+            }
+
+            push_i16(&mut hmtx, glyph_bounds[0]);
+            bounds = [
+                bounds[0].min(glyph_bounds[0]),
+                bounds[1].min(glyph_bounds[1]),
+                bounds[2].max(glyph_bounds[2]),
+                bounds[3].max(glyph_bounds[3]),
+            ];
+
+            let points: Vec<(i16, i16)> = glyph.contours.iter().flatten().copied().collect();
+            max_points = max_points.max(points.len() as u16);
+            max_contours = max_contours.max(glyph.contours.len() as u16);
+
+            push_i16(&mut glyf, glyph.contours.len() as i16);
+
+            for value in glyph_bounds {
+                push_i16(&mut glyf, value);
+            }
+
+            let mut end = 0;
+
+            for contour in glyph.contours.iter() {
+                end += contour.len() as u16;
+                push_u16(&mut glyf, end - 1);
+            }
+
+            // No instructions, then every point is on-curve with a 16-bit delta.
+            push_u16(&mut glyf, 0);
+            glyf.extend(points.iter().map(|_| 0x01_u8));
+            let mut prev = (0, 0);
+
+            for &(x, _) in points.iter() {
+                push_i16(&mut glyf, x - prev.0);
+                prev.0 = x;
+            }
+
+            for &(_, y) in points.iter() {
+                push_i16(&mut glyf, y - prev.1);
+                prev.1 = y;
+            }
+
+            if glyf.len() % 2 != 0 {
+                glyf.push(0);
+            }
+
+            loca.push(glyf.len() as u32);
+        }
+
+        let mut tables: Vec<([u8; 4], Vec<u8>)> = vec![
+            (*b"cmap", self.cmap()),
+            (*b"glyf", glyf),
+            (*b"head", self.head(bounds)),
+            (*b"hhea", self.hhea(num_glyphs, bounds)),
+            (*b"hmtx", hmtx),
+            (
+                *b"loca",
+                loca.iter()
+                    .flat_map(|offset| offset.to_be_bytes())
+                    .collect(),
+            ),
+            (*b"maxp", maxp(num_glyphs, max_points, max_contours)),
+            (*b"post", post()),
+        ];
+
+        if let Some(typo) = self.typo {
+            tables.push((*b"OS/2", self.os2(typo)));
+        }
+
+        tables.sort_by_key(|(tag, _)| *tag);
+        sfnt(tables)
+    }
+
+    fn cmap(&self) -> Vec<u8> {
+        let mut chars: Vec<(u16, u16)> = self
+            .glyphs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, glyph)| glyph.chars.iter().map(move |c| (*c as u16, i as u16 + 1)))
+            .collect();
+
+        chars.sort();
+
+        // One segment per character, followed by the required final segment.
+        let seg_count = chars.len() as u16 + 1;
+        let entry_selector = 15 - seg_count.leading_zeros() as u16;
+        let search_range = 2 << entry_selector;
+        let mut sub = Vec::new();
+        push_u16(&mut sub, 4);
+        push_u16(&mut sub, 16 + (seg_count * 8));
+        push_u16(&mut sub, 0);
+        push_u16(&mut sub, seg_count * 2);
+        push_u16(&mut sub, search_range);
+        push_u16(&mut sub, entry_selector);
+        push_u16(&mut sub, (seg_count * 2) - search_range);
+        chars.iter().for_each(|(code, _)| push_u16(&mut sub, *code));
+        push_u16(&mut sub, 0xFFFF);
+        push_u16(&mut sub, 0);
+        chars.iter().for_each(|(code, _)| push_u16(&mut sub, *code));
+        push_u16(&mut sub, 0xFFFF);
+
+        for (code, index) in chars.iter() {
+            push_u16(&mut sub, index.wrapping_sub(*code));
+        }
+
+        push_u16(&mut sub, 1);
+        (0..seg_count).for_each(|_| push_u16(&mut sub, 0));
+
+        let mut cmap = Vec::new();
+        push_u16(&mut cmap, 0);
+        push_u16(&mut cmap, 1);
+        push_u16(&mut cmap, 3);
+        push_u16(&mut cmap, 1);
+        push_u32(&mut cmap, 12);
+        cmap.extend(sub);
+        cmap
+    }
+
+    fn head(&self, bounds: [i16; 4]) -> Vec<u8> {
+        let mut head = Vec::new();
+        push_u32(&mut head, 0x00010000);
+        push_u32(&mut head, 0x00010000);
+        push_u32(&mut head, 0);
+        push_u32(&mut head, 0x5F0F3CF5);
+        push_u16(&mut head, 0x000B);
+        push_u16(&mut head, self.units_per_em);
+        head.extend([0; 16]);
+        bounds.iter().for_each(|value| push_i16(&mut head, *value));
+        push_u16(&mut head, 0);
+        push_u16(&mut head, 8);
+        push_i16(&mut head, 2);
+        // Long loca offsets.
+        push_i16(&mut head, 1);
+        push_i16(&mut head, 0);
+        head
+    }
+
+    fn hhea(&self, num_glyphs: u16, bounds: [i16; 4]) -> Vec<u8> {
+        let advance_max = self
+            .glyphs
+            .iter()
+            .map(|glyph| glyph.advance)
+            .max()
+            .unwrap_or(0);
+        let mut hhea = Vec::new();
+        push_u32(&mut hhea, 0x00010000);
+        push_i16(&mut hhea, self.hhea.0);
+        push_i16(&mut hhea, self.hhea.1);
+        push_i16(&mut hhea, self.hhea.2);
+        push_u16(&mut hhea, advance_max.max(500));
+        push_i16(&mut hhea, bounds[0]);
+        push_i16(&mut hhea, 0);
+        push_i16(&mut hhea, bounds[2]);
+        push_i16(&mut hhea, 1);
+        push_i16(&mut hhea, 0);
+        push_i16(&mut hhea, 0);
+        hhea.extend([0; 10]);
+        push_u16(&mut hhea, num_glyphs);
+        hhea
+    }
+
+    fn os2(&self, (ascender, descender, line_gap, use_typo): (i16, i16, i16, bool)) -> Vec<u8> {
+        let mut os2 = Vec::new();
+        push_u16(&mut os2, 4);
+        push_i16(&mut os2, 500);
+        push_u16(&mut os2, 400);
+        push_u16(&mut os2, 5);
+        push_u16(&mut os2, 0);
+        // Sub & superscript sizes & offsets.
+        os2.extend([0; 16]);
+        push_i16(&mut os2, 50);
+        push_i16(&mut os2, 250);
+        push_i16(&mut os2, 0);
+        // Panose, unicode ranges & vendor.
+        os2.extend([0; 30]);
+        // REGULAR, along with USE_TYPO_METRICS if used.
+        push_u16(&mut os2, 0x40 | if use_typo { 0x80 } else { 0 });
+        push_u16(&mut os2, 0x20);
+        push_u16(&mut os2, 0x5D2);
+        push_i16(&mut os2, ascender);
+        push_i16(&mut os2, descender);
+        push_i16(&mut os2, line_gap);
+        push_u16(&mut os2, self.hhea.0 as u16);
+        push_u16(&mut os2, self.hhea.1.unsigned_abs());
+        os2.extend([0; 8]);
+        // x-height, cap height, default, break & max context.
+        push_i16(&mut os2, 500);
+        push_i16(&mut os2, 700);
+        push_u16(&mut os2, 0);
+        push_u16(&mut os2, 0x20);
+        push_u16(&mut os2, 1);
+        os2
+    }
+}
+
+fn maxp(num_glyphs: u16, max_points: u16, max_contours: u16) -> Vec<u8> {
+    let mut maxp = Vec::new();
+    push_u32(&mut maxp, 0x00010000);
+    push_u16(&mut maxp, num_glyphs);
+    push_u16(&mut maxp, max_points);
+    push_u16(&mut maxp, max_contours);
+    push_u16(&mut maxp, 0);
+    push_u16(&mut maxp, 0);
+    push_u16(&mut maxp, 2);
+    maxp.extend([0; 16]);
+    maxp
+}
+
+fn post() -> Vec<u8> {
+    let mut post = Vec::new();
+    push_u32(&mut post, 0x00030000);
+    push_u32(&mut post, 0);
+    push_i16(&mut post, -100);
+    push_i16(&mut post, 50);
+    post.extend([0; 20]);
+    post
+}
+
+/// Table directory followed by the 4 byte aligned tables, which must be sorted by tag.
+fn sfnt(tables: Vec<([u8; 4], Vec<u8>)>) -> Vec<u8> {
+    let num_tables = tables.len() as u16;
+    let entry_selector = 15 - num_tables.leading_zeros() as u16;
+    let search_range = 16 << entry_selector;
+    let mut bytes = Vec::new();
+    push_u32(&mut bytes, 0x00010000);
+    push_u16(&mut bytes, num_tables);
+    push_u16(&mut bytes, search_range);
+    push_u16(&mut bytes, entry_selector);
+    push_u16(&mut bytes, (num_tables * 16) - search_range);
+
+    let mut offset = 12 + (tables.len() * 16);
+    let mut data = Vec::new();
+
+    for (tag, mut table) in tables {
+        let length = table.len();
+        table.resize((length + 3) & !3, 0);
+
+        let checksum = table
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .fold(0_u32, u32::wrapping_add);
+
+        bytes.extend(tag);
+        push_u32(&mut bytes, checksum);
+        push_u32(&mut bytes, offset as u32);
+        push_u32(&mut bytes, length as u32);
+        offset += table.len();
+        data.extend(table);
+    }
+
+    bytes.extend(data);
+    bytes
+}
+
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.extend(value.to_be_bytes());
+}
+
+fn push_i16(bytes: &mut Vec<u8>, value: i16) {
+    bytes.extend(value.to_be_bytes());
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend(value.to_be_bytes());
+}